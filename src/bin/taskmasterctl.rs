@@ -1,5 +1,9 @@
-use taskmaster::client;
+use std::env;
+use taskmaster::client::{self, Options};
 
 fn main() -> Result<(), String> {
-    client::run()
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let options = Options::from_args(&args)?;
+    let ctl = client::ctl_config(options.config.as_deref())?;
+    client::run(&ctl, options.output)
 }
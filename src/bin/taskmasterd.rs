@@ -1,5 +1,72 @@
-use taskmaster::server;
+use std::{env, process};
+use taskmaster::{
+    config::{Config, ConfigError},
+    server::{self, Options},
+};
 
 fn main() -> Result<(), String> {
-    server::run()
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    match args.first().map(String::as_str) {
+        Some("--diff") => diff(&args[1..]),
+        Some("--check-config") => check_config(&args[1..]),
+        _ => server::run(&Options::from_args(&args)?),
+    }
+}
+
+/// Checks a config file without starting the daemon, exiting with status 1 if it is invalid.
+///
+/// Usage: `taskmasterd --check-config [-c path]`
+fn check_config(args: &[String]) -> Result<(), String> {
+    match server::check_config(&Options::from_args(args)?) {
+        Ok(()) => {
+            println!("config OK");
+            Ok(())
+        }
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            process::exit(1);
+        }
+    }
+}
+
+/// Prints the differences between two config files.
+///
+/// Usage: `taskmasterd --diff old.yaml new.yaml [--format json]`
+fn diff(args: &[String]) -> Result<(), String> {
+    let usage = || "Usage: taskmasterd --diff <old> <new> [--format text|json]".to_string();
+    let (old, new, json) = match args {
+        [old, new] => (old, new, false),
+        [old, new, flag, format] if flag == "--format" => match format.as_str() {
+            "text" => (old, new, false),
+            "json" => (old, new, true),
+            _ => return Err(usage()),
+        },
+        _ => return Err(usage()),
+    };
+
+    let old = load(old)?;
+    let new = load(new)?;
+    let diff = old.diff(&new);
+
+    if json {
+        let json = serde_json::to_string_pretty(&diff).map_err(|e| e.to_string())?;
+        println!("{}", json);
+    } else {
+        print!("{}", diff);
+    }
+    Ok(())
+}
+
+/// Loads the config file at `path`, naming the file in the error if its content is at fault.
+fn load(path: &str) -> Result<Config, String> {
+    Config::from_path(path).map_err(|e| match e {
+        ConfigError::NotFound(_) | ConfigError::Io(_) | ConfigError::NoConfigFile => e.to_string(),
+        ConfigError::Yaml(_) | ConfigError::Json(_) | ConfigError::Validation(_) => {
+            format!("{}: {}", path, e)
+        }
+        #[cfg(feature = "toml")]
+        ConfigError::Toml(_) => format!("{}: {}", path, e),
+    })
 }
@@ -0,0 +1,140 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Marks the start of output to capture.
+const BEGIN: &[u8] = b"<!--XSUPERVISOR:BEGIN-->";
+/// Marks the end of output to capture.
+const END: &[u8] = b"<!--XSUPERVISOR:END-->";
+
+/// Scans a child's output for `BEGIN`/`END` markers and keeps what's in between, up to
+/// `maxbytes`. Bytes past the limit are discarded.
+#[derive(Debug)]
+pub struct CaptureBuffer {
+    maxbytes: usize,
+    /// Whether a `BEGIN` marker was seen without its `END` yet.
+    capturing: bool,
+    /// Payload of the capture in progress.
+    current: Vec<u8>,
+    /// Payload of the last completed capture.
+    captured: Option<Vec<u8>>,
+    /// End of the previous chunk, held back in case it is the beginning of a marker.
+    partial: Vec<u8>,
+}
+
+impl CaptureBuffer {
+    pub fn new(maxbytes: usize) -> Self {
+        Self {
+            maxbytes,
+            capturing: false,
+            current: Vec::new(),
+            captured: None,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Scans the next chunk of output.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        let mut data = std::mem::take(&mut self.partial);
+        data.extend_from_slice(chunk);
+        let mut rest = &data[..];
+
+        loop {
+            let marker = if self.capturing { END } else { BEGIN };
+            match find(rest, marker) {
+                Some(pos) => {
+                    if self.capturing {
+                        self.push(&rest[..pos]);
+                        self.captured = Some(std::mem::take(&mut self.current));
+                    }
+                    self.capturing = !self.capturing;
+                    rest = &rest[pos + marker.len()..];
+                }
+                None => {
+                    // Hold back whatever could be the start of a marker split across chunks.
+                    let keep = rest.len().min(marker.len() - 1);
+                    let (scanned, held) = rest.split_at(rest.len() - keep);
+                    if self.capturing {
+                        self.push(scanned);
+                    }
+                    self.partial = held.to_vec();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Payload of the last completed capture, if any.
+    pub fn captured(&self) -> Option<&[u8]> {
+        self.captured.as_deref()
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let room = self.maxbytes.saturating_sub(self.current.len());
+        self.current
+            .extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// `CaptureBuffer` fed by a log forwarder and read from elsewhere.
+#[derive(Debug, Clone)]
+pub struct SharedCapture(Arc<Mutex<CaptureBuffer>>);
+
+impl SharedCapture {
+    pub fn new(maxbytes: usize) -> Self {
+        Self(Arc::new(Mutex::new(CaptureBuffer::new(maxbytes))))
+    }
+
+    /// Payload of the last completed capture, if any.
+    pub fn captured(&self) -> Option<Vec<u8>> {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|buffer| buffer.captured().map(<[u8]>::to_vec))
+    }
+}
+
+impl Write for SharedCapture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .map_err(|e| io::Error::other(e.to_string()))?
+            .feed(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_between_markers() {
+        let mut buffer = CaptureBuffer::new(1024);
+        buffer.feed(b"noise <!--XSUPERVISOR:BEGIN-->pay");
+        assert_eq!(buffer.captured(), None);
+        // Markers may be split across chunks.
+        buffer.feed(b"load<!--XSUPERVISOR:E");
+        buffer.feed(b"ND--> more noise");
+        assert_eq!(buffer.captured(), Some(&b"payload"[..]));
+
+        buffer.feed(b"<!--XSUPERVISOR:BEGIN-->second<!--XSUPERVISOR:END-->");
+        assert_eq!(buffer.captured(), Some(&b"second"[..]));
+    }
+
+    #[test]
+    fn discards_overflow() {
+        let mut buffer = CaptureBuffer::new(4);
+        buffer.feed(b"<!--XSUPERVISOR:BEGIN-->0123456789<!--XSUPERVISOR:END-->");
+        assert_eq!(buffer.captured(), Some(&b"0123"[..]));
+    }
+}
@@ -1,10 +1,85 @@
-use crate::{command::Command, DEFAULT_ADDR};
-use liner::{Completer, Context};
-use std::io::{Read, Write};
-use std::{convert::TryFrom, net::TcpStream};
+use crate::{
+    command::{Command, Credentials, ParsingError},
+    config::{self, Config, ConfigError, ServerUrl, Taskmasterctl},
+    response::Response,
+};
+use liner::{Completer, Context, History, Prompt};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::Duration;
 
-/// Prompt displayed when using taskmaster in interactive mode.
-const TASKMASTER_PROMPT: &str = "taskmaster> ";
+/// Number of times to try reaching the daemon before giving up.
+const CONNECT_ATTEMPTS: u32 = 4;
+/// Wait after the first failed attempt to reach the daemon, doubled after each next one.
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(250);
+
+/// How often `fg` checks whether the daemon hung up while waiting for something typed, in
+/// milliseconds.
+const FG_POLL_INTERVAL_MS: libc::c_int = 250;
+
+/// What `taskmasterctl` takes, shown when its arguments are wrong.
+const USAGE: &str = "Usage: taskmasterctl [--json] [-c|--config <config>]
+
+Applying config changes, at the prompt:
+  reread   show what changed in the config file, without applying anything
+  update   apply the changes, restarting only the programs whose config changed
+  reload   stop every program, re-read the config file, then start everything again";
+
+/// Connection to the daemon, over TCP or its UNIX socket.
+enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_read_timeout(timeout),
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+            Self::Unix(stream) => stream.try_clone().map(Self::Unix),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
 
 /// Placeholder struct for Completer.
 struct EmptyCompleter;
@@ -15,51 +90,151 @@ impl Completer for EmptyCompleter {
     }
 }
 
-/// Runs the client.
+/// How answers of the daemon are printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    /// Formatted for people to read.
+    Human,
+    /// As the serialized `Response`, one JSON object per command, for scripts.
+    Json,
+}
+
+/// Command line options of `taskmasterctl`.
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    /// How answers are printed.
+    pub output: Output,
+    /// Config file to use instead of looking one up.
+    pub config: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            output: Output::Human,
+            config: None,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the arguments of `taskmasterctl`, without the program name.
+    ///
+    /// # Errors
+    ///
+    /// Errors with the usage if an argument is unknown or `-c` has no path.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let usage = || USAGE.to_string();
+        let mut options = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => options.output = Output::Json,
+                "-c" | "--config" => {
+                    options.config = Some(args.next().ok_or_else(usage)?.into());
+                }
+                _ => return Err(usage()),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// What the client does with a line typed at the prompt.
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// Leave the client.
+    Exit,
+    /// Send the command and print its answer.
+    Send(Command),
+    /// Send the command and print its answer as it keeps coming, until the daemon hangs up.
+    Follow(Command),
+}
+
+/// Parses a `line` typed at the prompt into what to do with it.
+fn dispatch(line: &str) -> Result<Step, ParsingError> {
+    let args = line.split_ascii_whitespace().collect::<Vec<&str>>();
+    Ok(match Command::try_from(&args[..])? {
+        Command::Exit => Step::Exit,
+        command if is_follow(&command) => Step::Follow(command),
+        command => Step::Send(command),
+    })
+}
+
+/// Runs the client with the settings of `ctl`, printing answers as `output` says.
 ///
 /// # Errors
 ///
 /// Returns an error if the stream fails to open, or if there's an error while reading stdin.
-pub fn run() -> Result<(), String> {
+pub fn run(ctl: &Taskmasterctl, output: Output) -> Result<(), String> {
     let mut con = Context::new();
+    let url = ctl.server_url();
+    let timeout = ctl.timeout();
+    let credentials = ctl.credentials();
 
-    // Try connecting to the daemon to make sure it's running.
+    // Make sure the daemon runs, and takes our credentials, before prompting for commands.
+    if let Response::Error(e) = send_command(url, credentials.as_ref(), &Command::Version, timeout)?
     {
-        let _stream = TcpStream::connect(DEFAULT_ADDR).map_err(|_| {
-            "Could not connect to the daemon. You can start the daemon by typing `taskmasterd`"
-                .to_string()
-        })?;
+        return Err(e);
     }
 
+    if let Some(path) = &ctl.history_file {
+        load_history(&mut con.history, path)
+            .unwrap_or_else(|e| eprintln!("Failed to load history: {}", e));
+    }
+    let result = repl(
+        &mut con,
+        url,
+        credentials.as_ref(),
+        timeout,
+        &ctl.prompt(),
+        output,
+    );
+    if let Some(path) = &ctl.history_file {
+        save_history(&mut con.history, path)
+            .unwrap_or_else(|e| eprintln!("Failed to save history: {}", e));
+    }
+    result
+}
+
+/// Reads commands after `prompt` and sends them to the daemon at `url`, authenticated with
+/// `credentials` if set, until `exit`.
+fn repl(
+    con: &mut Context,
+    url: &ServerUrl,
+    credentials: Option<&Credentials>,
+    timeout: Duration,
+    prompt: &str,
+    output: Output,
+) -> Result<(), String> {
     loop {
         let line = con
-            .read_line(TASKMASTER_PROMPT, None, &mut EmptyCompleter)
+            .read_line(Prompt::from(prompt), None, &mut EmptyCompleter)
             .map_err(|e| e.to_string())?;
 
-        let args = line.split_ascii_whitespace().collect::<Vec<&str>>();
-        let cmd = Command::try_from(&args[..]);
-        match cmd {
-            Ok(Command::Exit) => break,
-            Ok(command) => match serde_json::to_string(&command) {
-                Ok(message) => {
-                    // Open up the stream to communicate with the daemon.
-                    let mut stream = TcpStream::connect(DEFAULT_ADDR).map_err(|_| {
-        			    "Could not connect to the daemon. You can start the daemon by typing `taskmasterd`"
-        			        .to_string()
-					})?;
-
-                    if let Err(e) = stream.write(message.as_bytes()) {
-                        eprintln!("Could not sent message: {:?}", e);
-                    } else {
-                        // Message got sent correctly.
-                        let mut res = String::new();
-
-                        // Read back answer from server.
-                        stream.read_to_string(&mut res).map_err(|e| e.to_string())?;
-                        println!("response: {}", res);
+        match dispatch(&line) {
+            Ok(Step::Exit) => break,
+            Ok(Step::Follow(command)) => {
+                // The answer keeps coming until the daemon hangs up.
+                match open(url, credentials, &command, timeout) {
+                    Ok(mut stream) => {
+                        // The daemon only writes when there is something new.
+                        stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+                        if let Command::Fg(_) = command {
+                            foreground(stream)?;
+                        } else {
+                            io::copy(&mut stream, &mut io::stdout()).map_err(|e| e.to_string())?;
+                        }
                     }
+                    Err(e) => eprintln!("{}", e),
                 }
-                Err(e) => eprintln!("Could not serialize command: {:?}", e),
+            }
+            Ok(Step::Send(command)) => match send_command(url, credentials, &command, timeout) {
+                Ok(response) => println!("{}", format_response(&command, &response, output)),
+                Err(e) if output == Output::Json => {
+                    println!("{}", format_response(&command, &Response::Error(e), output));
+                }
+                Err(e) => eprintln!("{}", e),
             },
             Err(e) => e.display(),
         }
@@ -70,3 +245,480 @@ pub fn run() -> Result<(), String> {
     }
     Ok(())
 }
+
+/// Renders the `response` to `command` for printing.
+fn format_response(command: &Command, response: &Response, output: Output) -> String {
+    match output {
+        Output::Json => serde_json::to_string(response)
+            .unwrap_or_else(|e| format!("Could not serialize response: {:?}", e)),
+        Output::Human if *command == Command::Version => {
+            format!("taskmasterctl {}\n{}", env!("CARGO_PKG_VERSION"), response)
+        }
+        Output::Human => response.to_string(),
+    }
+}
+
+/// Whether the daemon keeps streaming the answer to `command` until it hangs up.
+fn is_follow(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Tail { follow: true, .. } | Command::MainTail { follow: true } | Command::Fg(_)
+    )
+}
+
+/// Prints what the daemon streams over `stream` while sending it what is typed, until it
+/// hangs up.
+fn foreground(mut stream: Connection) -> Result<(), String> {
+    let mut input = stream.try_clone().map_err(|e| e.to_string())?;
+    let done = Arc::new(AtomicBool::new(false));
+    let forwarder = {
+        let done = Arc::clone(&done);
+        thread::spawn(move || forward_stdin(&mut input, &done))
+    };
+    let copied = io::copy(&mut stream, &mut io::stdout());
+    done.store(true, Ordering::SeqCst);
+    if forwarder.join().is_err() {
+        eprintln!("The stdin forwarder panicked");
+    }
+    copied.map(drop).map_err(|e| e.to_string())
+}
+
+/// Sends what is read from stdin to `output`, until stdin is closed or `done` is set.
+///
+/// Polls, so that it stops without consuming what is typed next at the prompt.
+fn forward_stdin<W: Write>(output: &mut W, done: &AtomicBool) {
+    let mut stdin = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let mut buf = [0; 1024];
+    while !done.load(Ordering::SeqCst) {
+        // SAFETY: `stdin` is one valid pollfd.
+        match unsafe { libc::poll(&mut stdin, 1, FG_POLL_INTERVAL_MS) } {
+            0 => continue,
+            ready if ready < 0 => {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return;
+            }
+            _ => {}
+        }
+        let bytes = match io::stdin().read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(bytes) => bytes,
+        };
+        if output.write_all(&buf[..bytes]).is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends `command` to the daemon at `url`, authenticated with `credentials` if set, and
+/// reads back its answer.
+///
+/// Reconnects a few times if the daemon can't be reached, for instance while it restarts.
+/// Each step gives up after `timeout`.
+///
+/// # Errors
+///
+/// Errors if the daemon stayed unavailable, or its answer could not be read in time.
+pub fn send_command(
+    url: &ServerUrl,
+    credentials: Option<&Credentials>,
+    command: &Command,
+    timeout: Duration,
+) -> Result<Response, String> {
+    let stream = open(url, credentials, command, timeout)?;
+    read_response(stream).map_err(|e| match e.kind() {
+        // Read timeouts are reported as `WouldBlock` on some platforms.
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => format!(
+            "Timed out after {}s waiting for the daemon to answer",
+            timeout.as_secs_f32()
+        ),
+        io::ErrorKind::UnexpectedEof => {
+            "Connection closed by the daemon before it finished answering".to_string()
+        }
+        io::ErrorKind::InvalidData => format!("Could not deserialize response: {}", e),
+        _ => format!("Could not read answer: {}", e),
+    })
+}
+
+/// Reads the one response the daemon answers a command with, stopping at the end of it.
+fn read_response<R: Read>(reader: R) -> io::Result<Response> {
+    let mut deserializer = serde_json::Deserializer::from_reader(io::BufReader::new(reader));
+    Ok(Response::deserialize(&mut deserializer)?)
+}
+
+/// Connects to the daemon at `url` and sends it `command`, preceded by `credentials` if set,
+/// retrying if either fails.
+fn open(
+    url: &ServerUrl,
+    credentials: Option<&Credentials>,
+    command: &Command,
+    timeout: Duration,
+) -> Result<Connection, String> {
+    let serialize = |command: &Command| {
+        serde_json::to_string(command).map_err(|e| format!("Could not serialize command: {:?}", e))
+    };
+    let mut message = match credentials {
+        Some(credentials) => serialize(&Command::Authenticate(credentials.clone()))?,
+        None => String::new(),
+    };
+    message.push_str(&serialize(command)?);
+    with_retries(CONNECT_ATTEMPTS, RECONNECT_BACKOFF, thread::sleep, || {
+        let mut stream = connect_url(url, timeout)?;
+        stream.write_all(message.as_bytes())?;
+        Ok(stream)
+    })
+    .map_err(|e| unavailable(url, &e))
+}
+
+/// Connects to the daemon at `url`. Reads and writes on the stream time out after `timeout`.
+fn connect_url(url: &ServerUrl, timeout: Duration) -> io::Result<Connection> {
+    match url {
+        ServerUrl::Http(addr) => connect_timeout(addr, timeout).map(Connection::Tcp),
+        ServerUrl::Unix(path) => {
+            let stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            Ok(Connection::Unix(stream))
+        }
+        // Only left unresolved without a config file, which leaves the default address.
+        ServerUrl::Auto => connect_timeout(crate::DEFAULT_ADDR, timeout).map(Connection::Tcp),
+    }
+}
+
+/// Connects to the first address `addr` resolves to that answers within `timeout`. Reads
+/// and writes on the stream time out after `timeout` too.
+fn connect_timeout(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+    }))
+}
+
+fn unavailable(url: &ServerUrl, e: &io::Error) -> String {
+    format!(
+        "Daemon unavailable at {} ({}). You can start the daemon by typing `taskmasterd`",
+        url, e
+    )
+}
+
+/// Calls `attempt` until it succeeds, at most `attempts` times. Waits `backoff` with `sleep`
+/// after the first failure, and twice as long after each of the next ones.
+fn with_retries<T>(
+    attempts: u32,
+    backoff: Duration,
+    mut sleep: impl FnMut(Duration),
+    mut attempt: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut delay = backoff;
+    let mut tries = 1;
+    loop {
+        match attempt() {
+            Ok(value) => return Ok(value),
+            Err(e) if tries >= attempts => return Err(e),
+            Err(_) => {
+                sleep(delay);
+                delay *= 2;
+                tries += 1;
+            }
+        }
+    }
+}
+
+/// The `taskmasterctl` section of the config file, or the defaults when there is no config
+/// file, or of `path` if given. An `AUTO` `serverurl` is resolved against the rest of the
+/// file.
+///
+/// # Errors
+///
+/// Errors if the config file could not be found or loaded. Only a config file that was
+/// looked up may be missing.
+pub fn ctl_config(path: Option<&Path>) -> Result<Taskmasterctl, String> {
+    let path = match path {
+        Some(path) => Ok(path.to_path_buf()),
+        None => config::find_file(),
+    };
+    let config = match path {
+        Ok(path) => Config::from_path(&path).map_err(|e| format!("{}: {}", path.display(), e))?,
+        Err(ConfigError::NoConfigFile) => Config::default(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let url = config.server_url();
+    let mut ctl = config.taskmasterctl;
+    ctl.serverurl = url;
+    Ok(ctl)
+}
+
+/// Replaces `history` with the commands saved in `path`. A missing file leaves it empty.
+fn load_history(history: &mut History, path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        history.clear_history();
+        return Ok(());
+    }
+    history.load_history_file(path, false).map(|_| ())
+}
+
+/// Saves the commands of `history` to `path`, replacing its content.
+fn save_history(history: &mut History, path: &Path) -> io::Result<()> {
+    history.commit_to_file_path(path).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_options() {
+        let args = |args: &[&str]| {
+            Options::from_args(&args.iter().map(ToString::to_string).collect::<Vec<_>>())
+        };
+        assert_eq!(args(&[]), Ok(Options::default()));
+        assert_eq!(
+            args(&["-c", "foo.yaml"]),
+            Ok(Options {
+                output: Output::Human,
+                config: Some("foo.yaml".into()),
+            })
+        );
+        assert_eq!(
+            args(&["--json", "--config", "foo.yaml"]),
+            Ok(Options {
+                output: Output::Json,
+                config: Some("foo.yaml".into()),
+            })
+        );
+        assert!(args(&["-c"]).is_err());
+        assert!(args(&["--yaml"]).is_err());
+        let usage = args(&["--help"]).unwrap_err();
+        assert!(
+            usage.contains("update") && usage.contains("reload"),
+            "{}",
+            usage
+        );
+
+        let ctl = ctl_config(Some(Path::new("config_files/one_program.yaml"))).unwrap();
+        assert_eq!(ctl.prompt(), "taskmaster> ");
+        let e = ctl_config(Some(Path::new("config_files/nope.yaml"))).unwrap_err();
+        assert!(e.contains("nope.yaml"), "{}", e);
+    }
+
+    #[test]
+    fn dispatches_lines() {
+        assert_eq!(dispatch("exit"), Ok(Step::Exit));
+        assert_eq!(
+            dispatch("  status   web "),
+            Ok(Step::Send(Command::Status(vec!["web".into()])))
+        );
+        assert!(matches!(
+            dispatch("tail -f web"),
+            Ok(Step::Follow(Command::Tail { follow: true, .. }))
+        ));
+        assert!(matches!(
+            dispatch("tail web"),
+            Ok(Step::Send(Command::Tail { follow: false, .. }))
+        ));
+        assert_eq!(dispatch(""), Err(ParsingError::EmptyCommand));
+        assert_eq!(
+            dispatch("frobnicate"),
+            Err(ParsingError::UnknownCommand("frobnicate".into()))
+        );
+    }
+
+    #[test]
+    fn formats_responses() {
+        let status = Command::Status(vec![]);
+        let error = Response::Error("nope: ERROR (no such process)".into());
+        assert_eq!(
+            format_response(&status, &error, Output::Human),
+            "ERROR: nope: ERROR (no such process)"
+        );
+        assert_eq!(
+            format_response(&status, &error, Output::Json),
+            r#"{"Error":"nope: ERROR (no such process)"}"#
+        );
+
+        let pid = Response::Pid(std::iter::once(("web".to_string(), Ok(42))).collect());
+        assert_eq!(
+            format_response(&Command::PID(vec![]), &pid, Output::Json),
+            r#"{"Pid":{"web":{"Ok":42}}}"#
+        );
+        let version = Response::Ok("taskmasterd 1.0".into());
+        assert!(format_response(&Command::Version, &version, Output::Human)
+            .starts_with("taskmasterctl "));
+        assert_eq!(
+            format_response(&Command::Version, &version, Output::Json),
+            r#"{"Ok":"taskmasterd 1.0"}"#
+        );
+    }
+
+    #[test]
+    fn retries_with_backoff() {
+        let mut delays = Vec::new();
+        let mut failures = 2;
+        let result = with_retries(
+            4,
+            Duration::from_millis(10),
+            |delay| delays.push(delay),
+            || {
+                if failures > 0 {
+                    failures -= 1;
+                    Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+                } else {
+                    Ok("connected")
+                }
+            },
+        );
+        assert_eq!(result.unwrap(), "connected");
+        assert_eq!(
+            delays,
+            [Duration::from_millis(10), Duration::from_millis(20)]
+        );
+
+        let mut tries = 0;
+        let result: io::Result<()> = with_retries(
+            3,
+            Duration::from_millis(10),
+            |_| {},
+            || {
+                tries += 1;
+                Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+            },
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(tries, 3);
+    }
+
+    #[test]
+    fn times_out() {
+        // Connections to a listener that never accepts them still complete, but nothing
+        // ever answers on them.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = ServerUrl::Http(listener.local_addr().unwrap().to_string());
+
+        let start = std::time::Instant::now();
+        let result = send_command(&url, None, &Command::Version, Duration::from_millis(200));
+        assert!(result.unwrap_err().starts_with("Timed out"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn connects_to_unix_socket() {
+        let path = crate::temp_path("ctl").with_extension("sock");
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let daemon = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let bytes = stream.read(&mut buf).unwrap();
+            let command: Command = serde_json::from_slice(&buf[..bytes]).unwrap();
+            assert_eq!(command, Command::Version);
+            let answer = serde_json::to_string(&Response::Ok("taskmasterd 1.0".into())).unwrap();
+            stream.write_all(answer.as_bytes()).unwrap();
+        });
+
+        let url = ServerUrl::Unix(path.clone());
+        let response = send_command(&url, None, &Command::Version, Duration::from_secs(5));
+        daemon.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(response, Ok(Response::Ok("taskmasterd 1.0".into())));
+    }
+
+    #[test]
+    fn sends_credentials_first() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = ServerUrl::Http(listener.local_addr().unwrap().to_string());
+        let credentials = Credentials {
+            username: "admin".into(),
+            password: "secret".into(),
+        };
+        let daemon = {
+            let credentials = credentials.clone();
+            thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut commands = serde_json::Deserializer::from_reader(&mut stream);
+                assert_eq!(
+                    Command::deserialize(&mut commands).unwrap(),
+                    Command::Authenticate(credentials)
+                );
+                assert_eq!(
+                    Command::deserialize(&mut commands).unwrap(),
+                    Command::Version
+                );
+                let answer = serde_json::to_string(&Response::Ok("taskmasterd 1.0".into()));
+                stream.write_all(answer.unwrap().as_bytes()).unwrap();
+            })
+        };
+
+        let response = send_command(
+            &url,
+            Some(&credentials),
+            &Command::Version,
+            Duration::from_secs(5),
+        );
+        daemon.join().unwrap();
+        assert_eq!(response, Ok(Response::Ok("taskmasterd 1.0".into())));
+    }
+
+    #[test]
+    fn daemon_closing_mid_answer() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = ServerUrl::Http(listener.local_addr().unwrap().to_string());
+        let daemon = thread::spawn(move || {
+            for answer in &[&br#"{"Ok":"taskma"#[..], b""] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut command = serde_json::Deserializer::from_reader(&mut stream);
+                assert_eq!(
+                    Command::deserialize(&mut command).unwrap(),
+                    Command::Version
+                );
+                stream.write_all(answer).unwrap();
+            }
+        });
+
+        for _ in 0..2 {
+            let response = send_command(&url, None, &Command::Version, Duration::from_secs(5));
+            assert_eq!(
+                response,
+                Err("Connection closed by the daemon before it finished answering".into())
+            );
+        }
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn history_round_trip() {
+        let path = crate::temp_path("history");
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = History::new();
+        load_history(&mut history, &path).unwrap();
+        assert!(history.is_empty());
+
+        history.push("status".into()).unwrap();
+        history.push("start web".into()).unwrap();
+        save_history(&mut history, &path).unwrap();
+
+        let mut history = History::new();
+        load_history(&mut history, &path).unwrap();
+        let commands = history
+            .into_iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(commands, ["status", "start web"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,19 +1,44 @@
+use crate::config::Signal;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 /// Command that will be executed.
 pub enum Command {
     /// Activates any updates in config for process/group.
     Add(Vec<String>),
+    /// Credentials of the client, sent ahead of the command they authenticate, to a server
+    /// that asks for a `username` or `password`. Never typed at the prompt.
+    Authenticate(Credentials),
     /// Clear one or multiple process’ log files.
     Clear(Vec<String>),
     /// Exit taskmasterctl.
     Exit,
+    /// Stream the output of a program as it gets logged, and send it what is typed, until
+    /// the client hangs up.
+    Fg(String),
+    /// Show the end of the daemon's activity log, and with `follow`, keep streaming it.
+    MainTail { follow: bool },
     /// Get the PID of one or multiple child processes.
     PID(Vec<String>),
     /// Removes process/group from active config.
     Remove(Vec<String>),
     /// Reload the daemon’s configuration files, without add/remove (no restarts).
     ReRead,
+    /// Replace the daemon with a fresh run of its own binary, which keeps listening on the
+    /// same socket and keeps managing the running programs.
+    ReExec,
+    /// Stop every process, re-read the config file, then start everything it lists again.
+    /// Unlike `Update`, even the programs whose config did not change are restarted.
+    Reload,
+    /// Clear the restart and failure counters of one or multiple processes or groups,
+    /// without restarting them.
+    ResetCounters(Vec<String>),
+    /// Send a signal to one or multiple processes or groups, without stopping them.
+    Signal { signal: Signal, names: Vec<String> },
+    /// Stop every process, then the daemon itself.
+    Shutdown,
+    /// Show what stopping one or multiple processes will do, signal-wise.
+    Signals(Vec<String>),
     /// Restart multiple processes or groups.
     /// Note: restart does not reread config files. For that, see `Reread` and `Update`.
     Restart(Vec<String>),
@@ -23,8 +48,40 @@ pub enum Command {
     Status(Vec<String>),
     /// Stop one or multiple processes or groups.
     Stop(Vec<String>),
+    /// Show the end of a program's log, and with `follow`, keep streaming what gets logged.
+    Tail {
+        name: String,
+        stream: Stream,
+        follow: bool,
+    },
     /// Reload config and add/remove as necessary, and will restart affected programs.
     Update(Vec<String>),
+    /// Get the version of the daemon.
+    Version,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+/// Username and password a client authenticates with, from the `taskmasterctl` section.
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Leaves the password out, so that logging a command never reveals it.
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Output stream of a process.
+pub enum Stream {
+    Stdout,
+    Stderr,
 }
 
 #[derive(Debug, PartialEq)]
@@ -34,6 +91,8 @@ pub enum ParsingError {
     UnknownCommand(String),
     UnexpectedArguments,
     MissingArguments,
+    /// The argument is not the name of a supported signal. Holds the reason why.
+    UnknownSignal(String),
 }
 
 impl ParsingError {
@@ -42,6 +101,7 @@ impl ParsingError {
             Self::UnknownCommand(s) => eprintln!("Unknown command: {}", s),
             Self::UnexpectedArguments => eprintln!("Unexpected arguments"),
             Self::MissingArguments => eprintln!("Missing arguments"),
+            Self::UnknownSignal(e) => eprintln!("{}", e),
             _ => {}
         }
     }
@@ -49,7 +109,7 @@ impl ParsingError {
 
 /// Creates a new `Command` based on the arguments provided.
 /// Example:
-/// ```rust
+/// ```ignore
 /// create_command!(args, Exit, zero_args);
 /// ```
 /// Will create a `Command::Exit`, and will error if the number of
@@ -85,30 +145,79 @@ macro_rules! create_command {
     };
 }
 
-impl<'a> std::convert::TryFrom<&[&str]> for Command {
+impl std::convert::TryFrom<&[&str]> for Command {
     type Error = ParsingError;
 
     fn try_from(args: &[&str]) -> Result<Self, Self::Error> {
-        match args.get(0) {
+        match args.first() {
             None => Err(Self::Error::EmptyCommand),
-            Some(&command) => match command {
+            Some(&command) => match command.to_ascii_lowercase().as_str() {
                 "add" => create_command!(args, Add, multiple_args),
                 "clear" => create_command!(args, Clear, multiple_args),
-                "exit" => create_command!(args, Exit, zero_args),
+                "exit" | "quit" | "q" => create_command!(args, Exit, zero_args),
+                "fg" => match &args[1..] {
+                    [] => Err(Self::Error::MissingArguments),
+                    [name] => Ok(Self::Fg((*name).to_string())),
+                    _ => Err(Self::Error::UnexpectedArguments),
+                },
+                "maintail" => match &args[1..] {
+                    [] => Ok(Self::MainTail { follow: false }),
+                    ["-f"] => Ok(Self::MainTail { follow: true }),
+                    _ => Err(Self::Error::UnexpectedArguments),
+                },
                 "pid" => create_command!(args, PID, unspecified),
                 "remove" => create_command!(args, Remove, multiple_args),
+                "reload" => create_command!(args, Reload, zero_args),
+                "reexec" => create_command!(args, ReExec, zero_args),
                 "reread" => create_command!(args, ReRead, zero_args),
+                "reset_counters" => create_command!(args, ResetCounters, multiple_args),
                 "restart" => create_command!(args, Restart, multiple_args),
+                "shutdown" => create_command!(args, Shutdown, zero_args),
+                "signal" => parse_signal(&args[1..]),
+                "signals" => create_command!(args, Signals, unspecified),
                 "start" => create_command!(args, Start, multiple_args),
                 "status" => create_command!(args, Status, unspecified),
                 "stop" => create_command!(args, Stop, multiple_args),
+                "tail" => parse_tail(&args[1..]),
                 "update" => create_command!(args, Update, multiple_args),
-                other => Err(Self::Error::UnknownCommand(other.into())),
+                "version" => create_command!(args, Version, zero_args),
+                _ => Err(Self::Error::UnknownCommand(command.into())),
             },
         }
     }
 }
 
+/// Parses the arguments of `signal <SIG> <name>...`.
+fn parse_signal(args: &[&str]) -> Result<Command, ParsingError> {
+    match args {
+        [] | [_] => Err(ParsingError::MissingArguments),
+        [signal, names @ ..] => Ok(Command::Signal {
+            signal: signal.parse().map_err(ParsingError::UnknownSignal)?,
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }),
+    }
+}
+
+/// Parses the arguments of `tail [-f] <name> [stdout|stderr]`.
+fn parse_tail(args: &[&str]) -> Result<Command, ParsingError> {
+    let (follow, args) = match args.split_first() {
+        Some((&"-f", rest)) => (true, rest),
+        _ => (false, args),
+    };
+    let (name, stream) = match args {
+        [] => return Err(ParsingError::MissingArguments),
+        [name] => (name, Stream::Stdout),
+        [name, "stdout"] => (name, Stream::Stdout),
+        [name, "stderr"] => (name, Stream::Stderr),
+        _ => return Err(ParsingError::UnexpectedArguments),
+    };
+    Ok(Command::Tail {
+        name: name.to_string(),
+        stream,
+        follow,
+    })
+}
+
 #[cfg(test)]
 #[allow(clippy::shadow_unrelated)] // We don't mind shadowing here, so stop Clippy from complaining.
 mod tests {
@@ -147,6 +256,65 @@ mod tests {
         assert_eq!(res, Err(ParsingError::UnexpectedArguments));
     }
 
+    #[test]
+    fn version() {
+        let args: &[&str] = &["version"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Ok(Command::Version));
+
+        let args: &[&str] = &["version", "foo"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Err(ParsingError::UnexpectedArguments));
+    }
+
+    #[test]
+    fn shutdown() {
+        let args: &[&str] = &["shutdown"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Ok(Command::Shutdown));
+
+        let args: &[&str] = &["shutdown", "now"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Err(ParsingError::UnexpectedArguments));
+    }
+
+    #[test]
+    fn reload() {
+        let args: &[&str] = &["reload"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Ok(Command::Reload));
+
+        let args: &[&str] = &["reload", "web"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Err(ParsingError::UnexpectedArguments));
+    }
+
+    #[test]
+    fn fg() {
+        let args: &[&str] = &["fg", "web"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Ok(Command::Fg("web".into())));
+
+        let args: &[&str] = &["fg"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Err(ParsingError::MissingArguments));
+
+        let args: &[&str] = &["fg", "web", "api"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Err(ParsingError::UnexpectedArguments));
+    }
+
+    #[test]
+    fn reexec() {
+        let args: &[&str] = &["reexec"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Ok(Command::ReExec));
+
+        let args: &[&str] = &["reexec", "now"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Err(ParsingError::UnexpectedArguments));
+    }
+
     #[test]
     fn multiple_args_command() {
         let args: &[&str] = &["clear"];
@@ -183,20 +351,129 @@ mod tests {
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn aliases() {
+        for &alias in &["quit", "q", "EXIT"] {
+            let args: &[&str] = &[alias];
+            let res = Command::try_from(args);
+            assert_eq!(res, Ok(Command::Exit));
+        }
+
+        let args: &[&str] = &["Status", "cat"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Ok(Command::Status(vec!["cat".into()])));
+
+        let args: &[&str] = &["QUITE"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Err(ParsingError::UnknownCommand("QUITE".into())));
+    }
+
+    #[test]
+    fn signal() {
+        let args: &[&str] = &["signal", "HUP", "web"];
+        let res = Command::try_from(args);
+        assert_eq!(
+            res,
+            Ok(Command::Signal {
+                signal: Signal::HUP,
+                names: vec!["web".into()]
+            })
+        );
+
+        let args: &[&str] = &["signal", "sigusr1", "web", "worker"];
+        let res = Command::try_from(args);
+        assert_eq!(
+            res,
+            Ok(Command::Signal {
+                signal: Signal::USR1,
+                names: vec!["web".into(), "worker".into()]
+            })
+        );
+
+        let args: &[&str] = &["signal", "BOGUS", "web"];
+        let res = Command::try_from(args);
+        assert!(matches!(res, Err(ParsingError::UnknownSignal(_))));
+
+        let args: &[&str] = &["signal", "HUP"];
+        let res = Command::try_from(args);
+        assert_eq!(res, Err(ParsingError::MissingArguments));
+    }
+
+    #[test]
+    fn tail() {
+        let tail = |args: &[&str]| Command::try_from(args);
+        assert_eq!(
+            tail(&["tail", "web"]),
+            Ok(Command::Tail {
+                name: "web".into(),
+                stream: Stream::Stdout,
+                follow: false
+            })
+        );
+        assert_eq!(
+            tail(&["tail", "-f", "web"]),
+            Ok(Command::Tail {
+                name: "web".into(),
+                stream: Stream::Stdout,
+                follow: true
+            })
+        );
+        assert_eq!(
+            tail(&["tail", "web", "stderr"]),
+            Ok(Command::Tail {
+                name: "web".into(),
+                stream: Stream::Stderr,
+                follow: false
+            })
+        );
+        assert_eq!(tail(&["tail", "-f"]), Err(ParsingError::MissingArguments));
+        assert_eq!(
+            tail(&["tail", "web", "stdin"]),
+            Err(ParsingError::UnexpectedArguments)
+        );
+    }
+
+    #[test]
+    fn maintail() {
+        let maintail = |args: &[&str]| Command::try_from(args);
+        assert_eq!(
+            maintail(&["maintail"]),
+            Ok(Command::MainTail { follow: false })
+        );
+        assert_eq!(
+            maintail(&["maintail", "-f"]),
+            Ok(Command::MainTail { follow: true })
+        );
+        assert_eq!(
+            maintail(&["maintail", "web"]),
+            Err(ParsingError::UnexpectedArguments)
+        );
+    }
+
     #[test]
     fn supported_commands() {
         let lines: &[&[&str]] = &[
             &["add", "cat"],
             &["clear", "python"],
             &["exit"],
+            &["fg", "cat"],
+            &["maintail", "-f"],
             &["pid", "cat"],
+            &["reload"],
+            &["reexec"],
             &["remove", "cat"],
             &["reread"],
+            &["reset_counters", "cat"],
             &["restart", "cat"],
+            &["shutdown"],
+            &["signal", "TERM", "cat"],
+            &["signals"],
             &["start", "cat"],
             &["status", "cat", "nginx", "top"],
             &["stop", "cat", "nginx"],
+            &["tail", "cat"],
             &["update", "cat", "ft_server"],
+            &["version"],
         ];
         for &line in lines {
             let res = Command::try_from(line);
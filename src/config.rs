@@ -1,33 +1,3114 @@
+use crate::command::{Credentials, Stream};
+use crate::template;
+use crate::webhook::{self, Webhook};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::str::FromStr;
+use std::time::Duration;
 
+/// Reasons a config could not be loaded.
 #[derive(Debug)]
-/// Configuration structure used to run a task.
+pub enum ConfigError {
+    /// The config file does not exist.
+    NotFound(PathBuf),
+    /// The config file exists but could not be read.
+    Io(std::io::Error),
+    /// The config is not valid YAML, or doesn't have the expected shape.
+    Yaml(serde_yaml::Error),
+    /// The config is not valid JSON.
+    Json(serde_json::Error),
+    /// The config is not valid TOML.
+    #[cfg(feature = "toml")]
+    Toml(toml::de::Error),
+    /// The config is well-formed but its settings don't make sense.
+    Validation(String),
+    /// No config file was specified and none could be found.
+    NoConfigFile,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "Config file {} not found", path.display()),
+            Self::Io(e) => write!(f, "Could not read config: {}", e),
+            Self::Yaml(e) => write!(f, "Invalid config: {}", e),
+            Self::Json(e) => write!(f, "Invalid config: {}", e),
+            #[cfg(feature = "toml")]
+            Self::Toml(e) => write!(f, "Invalid config: {}", e),
+            Self::Validation(message) => f.write_str(message),
+            Self::NoConfigFile => f.write_str("No config file found"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Yaml(e) => Some(e),
+            Self::Json(e) => Some(e),
+            #[cfg(feature = "toml")]
+            Self::Toml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Environment variable pointing at the config file to use, bypassing the search.
+pub const CONFIG_ENV: &str = "TASKMASTER_CONFIG";
+
+/// Where to look for a config file when none is specified, in this order: the first one
+/// that exists wins. Relative paths are relative to the working directory.
+const LOOKAT: [&str; 4] = [
+    "config.yaml",
+    "taskmasterd.yaml",
+    "etc/taskmasterd.yaml",
+    "/etc/taskmasterd.yaml",
+];
+
+/// Finds the config file to use: the one `TASKMASTER_CONFIG` points at if it is set,
+/// otherwise the first of `LOOKAT` that exists. The path is returned canonicalized, so it
+/// stays valid if the working directory changes.
+///
+/// # Errors
+///
+/// Errors with `ConfigError::NotFound` if `TASKMASTER_CONFIG` points at a missing file, and
+/// with `ConfigError::NoConfigFile` if the search came up empty.
+pub fn find_file() -> Result<PathBuf, ConfigError> {
+    if let Some(path) = std::env::var_os(CONFIG_ENV) {
+        let path = PathBuf::from(path);
+        if !path.is_file() {
+            return Err(ConfigError::NotFound(path));
+        }
+        return path.canonicalize().map_err(ConfigError::Io);
+    }
+    let cwd = std::env::current_dir().map_err(ConfigError::Io)?;
+    find_in(&cwd)
+}
+
+/// Searches `LOOKAT`, with relative entries taken relative to `dir`.
+fn find_in(dir: &Path) -> Result<PathBuf, ConfigError> {
+    let path = LOOKAT
+        .iter()
+        .map(|candidate| dir.join(candidate))
+        .find(|path| path.is_file())
+        .ok_or(ConfigError::NoConfigFile)?;
+    path.canonicalize().map_err(ConfigError::Io)
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Configuration of the daemon, as read from its configuration file.
 pub struct Config {
+    /// Settings of the daemon itself.
+    #[serde(default)]
+    pub(crate) taskmasterd: Taskmasterd,
+    /// Programs managed by the daemon, by name.
+    #[serde(default)]
+    pub(crate) programs: HashMap<String, Program>,
+    /// FastCGI programs managed by the daemon, by name.
+    #[serde(default)]
+    pub(crate) fcgi_programs: HashMap<String, FcgiProgram>,
+    /// Event listener pools managed by the daemon, by name.
+    #[serde(default)]
+    pub(crate) eventlisteners: HashMap<String, EventListener>,
+    /// Groups of programs that can be controlled together, by name.
+    #[serde(default)]
+    pub(crate) group: HashMap<String, Group>,
+    /// TCP server clients connect to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) inet_http_server: Option<InetHttpServer>,
+    /// UNIX socket server clients connect to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) unix_http_server: Option<UnixHttpServer>,
+    /// Settings of the client.
+    #[serde(default)]
+    pub(crate) taskmasterctl: Taskmasterctl,
+    /// Directory of the config file, substituted for `%(here)s`. Only set by `from_path`.
+    #[serde(skip)]
+    pub(crate) here: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// TCP server clients connect to.
+pub struct InetHttpServer {
+    /// Address to listen on, like `127.0.0.1:9001` or `[::1]:9001`. `*:9001` and `:9001`
+    /// listen on every interface.
+    pub(crate) port: String,
+    /// Username clients must authenticate with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) username: Option<String>,
+    /// Password clients must authenticate with, either in cleartext or as `{SHA}` followed
+    /// by its hex-encoded SHA-1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) password: Option<String>,
+    /// File holding the password, read when the config is loaded. Conflicts with `password`.
+    #[serde(default, skip_serializing)]
+    pub(crate) password_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// UNIX socket server clients connect to.
+pub struct UnixHttpServer {
+    /// Path of the socket.
+    pub(crate) file: PathBuf,
+    /// Permissions of the socket file, in octal.
+    #[serde(default = "default_socket_mode")]
+    pub(crate) chmod: String,
+    /// Owner the socket file is given, as `user` or `user:group`. Left to the daemon's user
+    /// if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) chown: Option<String>,
+    /// Username clients must authenticate with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) username: Option<String>,
+    /// Password clients must authenticate with, same format as `InetHttpServer.password`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) password: Option<String>,
+    /// File holding the password, read when the config is loaded. Conflicts with `password`.
+    #[serde(default, skip_serializing)]
+    pub(crate) password_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Settings of the client.
+pub struct Taskmasterctl {
+    /// URL of the daemon to connect to, like `http://127.0.0.1:9001`.
+    #[serde(default)]
+    pub(crate) serverurl: ServerUrl,
+    /// Username to authenticate with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) username: Option<String>,
+    /// Password to authenticate with, in cleartext.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) password: Option<String>,
+    /// File holding the password, read when the config is loaded. Conflicts with `password`.
+    #[serde(default, skip_serializing)]
+    pub(crate) password_file: Option<PathBuf>,
+    /// File the command history is kept in across sessions, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) history_file: Option<PathBuf>,
+    /// Prompt of the interactive shell, shown followed by `> `.
+    #[serde(default = "default_prompt")]
+    pub(crate) prompt: String,
+    /// Seconds to wait for the daemon when connecting, sending a command or reading its
+    /// answer. Must not be 0.
+    #[serde(default = "default_timeout")]
+    pub(crate) timeout: u64,
+}
+
+fn default_prompt() -> String {
+    "taskmaster".into()
+}
+
+const fn default_timeout() -> u64 {
+    5
+}
+
+impl Default for Taskmasterctl {
+    fn default() -> Self {
+        Self {
+            serverurl: ServerUrl::Auto,
+            username: None,
+            password: None,
+            password_file: None,
+            history_file: None,
+            prompt: default_prompt(),
+            timeout: default_timeout(),
+        }
+    }
+}
+
+/// Password clients authenticate with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Password {
+    Plain(String),
+    /// Hex-encoded SHA-1 of the password.
+    Sha1(String),
+}
+
+impl Password {
+    /// Parses a configured password, hashed if it starts with `{SHA}`.
+    pub fn parse(password: &str) -> Self {
+        match password.strip_prefix("{SHA}") {
+            Some(hash) => Self::Sha1(hash.to_ascii_lowercase()),
+            None => Self::Plain(password.to_string()),
+        }
+    }
+
+    /// Whether `candidate` is this password.
+    pub fn matches(&self, candidate: &str) -> bool {
+        match self {
+            Self::Plain(password) => password == candidate,
+            Self::Sha1(hash) => *hash == sha1_smol::Sha1::from(candidate).digest().to_string(),
+        }
+    }
+}
+
+/// Whether a client sending `credentials` may use a server asking for `username` and
+/// `password`. Servers asking for neither let every client in.
+fn authenticates(
+    username: Option<&str>,
+    password: Option<Password>,
+    credentials: Option<&Credentials>,
+) -> bool {
+    if username.is_none() && password.is_none() {
+        return true;
+    }
+    credentials.is_some_and(|credentials| {
+        username.is_none_or(|username| username == credentials.username)
+            && password.is_none_or(|password| password.matches(&credentials.password))
+    })
+}
+
+impl InetHttpServer {
+    /// Address to listen on.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `port` is not an IP address (or `*`, or nothing) followed by `:` and a port.
+    pub fn bind_addr(&self) -> Result<SocketAddr, String> {
+        let invalid =
+            |reason: &str| format!("inet_http_server: invalid port `{}`: {}", self.port, reason);
+        let (host, port) = self
+            .port
+            .rsplit_once(':')
+            .ok_or_else(|| invalid("expected host:port"))?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| invalid("port must be a number between 0 and 65535"))?;
+        let ip = match host {
+            "" | "*" => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            host => host
+                .strip_prefix('[')
+                .and_then(|host| host.strip_suffix(']'))
+                .unwrap_or(host)
+                .parse()
+                .map_err(|_| invalid("host must be an IP address or `*`"))?,
+        };
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    /// Password clients must authenticate with, if any.
+    pub fn password(&self) -> Option<Password> {
+        self.password.as_deref().map(Password::parse)
+    }
+
+    /// Whether a client sending `credentials` may use this server.
+    pub fn authenticates(&self, credentials: Option<&Credentials>) -> bool {
+        authenticates(self.username.as_deref(), self.password(), credentials)
+    }
+}
+
+impl UnixHttpServer {
+    /// Permissions the socket file gets, from `chmod`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `chmod` is not an octal mode.
+    pub fn mode(&self) -> Result<u32, String> {
+        parse_mode(&self.chmod).ok_or_else(|| {
+            format!(
+                "unix_http_server: invalid chmod `{}`: expected an octal mode like 0700",
+                self.chmod
+            )
+        })
+    }
+
+    /// Password clients must authenticate with, if any.
+    pub fn password(&self) -> Option<Password> {
+        self.password.as_deref().map(Password::parse)
+    }
+
+    /// Whether a client sending `credentials` may use this server.
+    pub fn authenticates(&self, credentials: Option<&Credentials>) -> bool {
+        authenticates(self.username.as_deref(), self.password(), credentials)
+    }
+}
+
+impl FcgiProgram {
+    /// Permissions the socket file gets, from `socket_mode`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `socket_mode` is not an octal mode.
+    pub fn mode(&self) -> Result<u32, String> {
+        parse_mode(&self.socket_mode).ok_or_else(|| {
+            format!(
+                "invalid socket_mode `{}`: expected an octal mode like 0700",
+                self.socket_mode
+            )
+        })
+    }
+}
+
+/// Parses a permission mode of at most 4 octal digits, like `0700`.
+fn parse_mode(mode: &str) -> Option<u32> {
+    if (1..=4).contains(&mode.len()) && mode.bytes().all(|digit| (b'0'..=b'7').contains(&digit)) {
+        u32::from_str_radix(mode, 8).ok()
+    } else {
+        None
+    }
+}
+
+/// Splits an owner setting like `chown` into its user and its group, if it has one.
+///
+/// # Errors
+///
+/// Errors if the user or the group is empty, or if there is more than one `:`.
+pub fn parse_chown(owner: &str) -> Result<(String, Option<String>), String> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+    if user.is_empty() || group.is_some_and(|group| group.is_empty() || group.contains(':')) {
+        return Err(format!(
+            "invalid owner `{}`: expected a user or user:group",
+            owner
+        ));
+    }
+    Ok((user.to_string(), group.map(String::from)))
+}
+
+/// Looks up the uid of the user of an owner setting like `chown`, and the gid of its group
+/// if it has one.
+///
+/// # Errors
+///
+/// Errors if the user or the group doesn't exist.
+pub fn resolve_owner(owner: &str) -> Result<(u32, Option<u32>), String> {
+    let (user, group) = parse_chown(owner)?;
+    let uid = users::get_user_by_name(&user)
+        .ok_or_else(|| format!("Unknown user `{}`", user))?
+        .uid();
+    let gid = group
+        .map(|group| {
+            users::get_group_by_name(&group)
+                .map(|group| group.gid())
+                .ok_or_else(|| format!("Unknown group `{}`", group))
+        })
+        .transpose()?;
+    Ok((uid, gid))
+}
+
+impl Taskmasterctl {
+    /// Where to reach the daemon, as set by `serverurl`.
+    pub fn server_url(&self) -> &ServerUrl {
+        &self.serverurl
+    }
+
+    /// What to authenticate with, if `username` or `password` is set.
+    pub fn credentials(&self) -> Option<Credentials> {
+        if self.username.is_none() && self.password.is_none() {
+            return None;
+        }
+        Some(Credentials {
+            username: self.username.clone().unwrap_or_default(),
+            password: self.password.clone().unwrap_or_default(),
+        })
+    }
+
+    /// How long to wait for the daemon before giving up.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    /// Prompt of the interactive shell, like `taskmaster> `.
+    pub fn prompt(&self) -> String {
+        format!("{}> ", self.prompt)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+/// Where the client reaches the daemon, parsed from `serverurl`.
+pub enum ServerUrl {
+    /// `AUTO`: the `unix_http_server` socket if there is one, the TCP address the daemon
+    /// listens on otherwise.
+    #[default]
+    Auto,
+    /// `http://host:port`, holding `host:port`.
+    Http(String),
+    /// `unix:///path/to/socket`, holding the path.
+    Unix(PathBuf),
+}
+
+impl TryFrom<String> for ServerUrl {
+    type Error = String;
+
+    fn try_from(url: String) -> Result<Self, Self::Error> {
+        if url == "AUTO" {
+            Ok(Self::Auto)
+        } else if let Some(addr) = url.strip_prefix("http://") {
+            Ok(Self::Http(addr.trim_end_matches('/').to_string()))
+        } else if let Some(path) = url
+            .strip_prefix("unix://")
+            .filter(|path| path.starts_with('/'))
+        {
+            Ok(Self::Unix(path.into()))
+        } else {
+            Err(format!(
+                "unsupported serverurl `{}`, expected http://host:port, unix:///path or AUTO",
+                url
+            ))
+        }
+    }
+}
+
+impl From<ServerUrl> for String {
+    fn from(url: ServerUrl) -> Self {
+        url.to_string()
+    }
+}
+
+impl fmt::Display for ServerUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "AUTO"),
+            Self::Http(addr) => write!(f, "http://{}", addr),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// Replaces `password` with the content of `password_file`, if there is one.
+///
+/// Warns if the file is world-readable, since it is supposed to keep the secret out of
+/// the main config.
+fn read_password_file(
+    section: &str,
+    password: &mut Option<String>,
+    password_file: &Option<PathBuf>,
+) -> Result<(), String> {
+    let path = match password_file {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    if password.is_some() {
+        return Err(format!(
+            "{}: `password` and `password_file` are mutually exclusive",
+            section
+        ));
+    }
+
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("{}: could not read {}: {}", section, path.display(), e))?;
+    let world_readable = std::fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o004 != 0)
+        .unwrap_or(false);
+    if world_readable {
+        warn!(
+            "{}: password file {} is world-readable",
+            section,
+            path.display()
+        );
+    }
+    *password = Some(content.trim_end_matches(&['\r', '\n'][..]).to_string());
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Settings of the daemon itself.
+pub struct Taskmasterd {
+    /// Seconds a client connection may stay idle before sending its command. Past that,
+    /// the connection is dropped so it doesn't hold a worker forever. 0 disables the timeout.
+    #[serde(default = "default_command_timeout")]
+    pub(crate) command_timeout: u64,
+    /// `http://` URL notified of significant events, see `webhook::Event`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) webhook_url: Option<String>,
+    /// Events reported to `webhook_url`.
+    #[serde(default = "webhook::Event::all")]
+    pub(crate) webhook_events: Vec<webhook::Event>,
+    /// How many times to retry delivering an event to `webhook_url`.
+    #[serde(default = "default_webhook_retries")]
+    pub(crate) webhook_retries: u32,
+    /// Environment variables set for every child, on top of the daemon's own environment.
+    /// Either a map, or a string like `KEY="val",KEY2="val2"`.
+    #[serde(default, deserialize_with = "environment::deserialize")]
+    pub(crate) environment: HashMap<String, String>,
+    /// Whether to remove ANSI escape sequences, like color codes, from the children's log files.
+    #[serde(default)]
+    pub(crate) strip_ansi: bool,
+    /// Directory the `AUTO` log files of the children are created in.
+    #[serde(default = "std::env::temp_dir")]
+    pub(crate) childlogdir: PathBuf,
+    /// Name of this daemon, part of its `AUTO` log file names.
+    #[serde(default = "default_identifier")]
+    pub(crate) identifier: String,
+    /// Whether to keep the `AUTO` log files of previous daemons on start.
+    #[serde(default)]
+    pub(crate) nocleanup: bool,
+    /// Minimum number of file descriptors the daemon must be able to open. The soft limit is
+    /// raised to it on start.
+    #[serde(default = "default_minfds")]
+    pub(crate) minfds: u64,
+    /// Minimum number of processes the daemon's user must be able to run, raised like `minfds`.
+    #[serde(default = "default_minprocs")]
+    pub(crate) minprocs: u64,
+    /// Least severe level of the messages written to `logfile`.
+    #[serde(default)]
+    pub(crate) loglevel: LogLevel,
+    /// Activity log of the daemon. Relative to the home directory once daemonized.
+    #[serde(default = "default_logfile")]
+    pub(crate) logfile: PathBuf,
+    /// Size in bytes past which `logfile` is rotated. 0 disables rotation.
+    #[serde(default = "default_logfile_maxbytes")]
+    pub(crate) logfile_maxbytes: u64,
+    /// Number of rotated `logfile` backups to keep.
+    #[serde(default = "default_logfile_backups")]
+    pub(crate) logfile_backups: u32,
+    /// Whether to run in the foreground, also logging to stderr, instead of daemonizing.
+    #[serde(default)]
+    pub(crate) nodaemon: bool,
+    /// Whether to keep quiet when running in the foreground.
+    #[serde(default)]
+    pub(crate) silent: bool,
+    /// Whether to reload the config file whenever it changes, as on SIGHUP. Needs the daemon
+    /// to be built with the `notify` feature.
+    #[serde(default)]
+    pub(crate) watch: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Severity of an activity log message, from the most to the least severe.
+pub enum LogLevel {
+    Critical,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+    Blather,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_ascii_uppercase())
+    }
+}
+
+fn default_logfile() -> PathBuf {
+    "taskmasterd.log".into()
+}
+
+const fn default_minfds() -> u64 {
+    1024
+}
+
+const fn default_minprocs() -> u64 {
+    200
+}
+
+fn default_identifier() -> String {
+    "taskmaster".into()
+}
+
+const fn default_webhook_retries() -> u32 {
+    3
+}
+
+const fn default_command_timeout() -> u64 {
+    10
+}
+
+impl Default for Taskmasterd {
+    fn default() -> Self {
+        Self {
+            command_timeout: default_command_timeout(),
+            webhook_url: None,
+            webhook_events: webhook::Event::all(),
+            webhook_retries: default_webhook_retries(),
+            environment: HashMap::new(),
+            strip_ansi: false,
+            childlogdir: std::env::temp_dir(),
+            identifier: default_identifier(),
+            nocleanup: false,
+            minfds: default_minfds(),
+            minprocs: default_minprocs(),
+            loglevel: LogLevel::default(),
+            logfile: default_logfile(),
+            logfile_maxbytes: default_logfile_maxbytes(),
+            logfile_backups: default_logfile_backups(),
+            nodaemon: false,
+            silent: false,
+            watch: false,
+        }
+    }
+}
+
+impl Taskmasterd {
+    /// How long to wait for a client's command, if at all.
+    pub fn command_timeout(&self) -> Option<std::time::Duration> {
+        match self.command_timeout {
+            0 => None,
+            secs => Some(std::time::Duration::from_secs(secs)),
+        }
+    }
+
+    /// Webhook notified of significant events, if one is configured.
+    pub fn webhook(&self) -> Option<Webhook> {
+        let url = self.webhook_url.as_ref()?;
+        match Webhook::new(url, self.webhook_events.clone(), self.webhook_retries) {
+            Ok(webhook) => Some(webhook),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        }
+    }
+}
+
+/// (De)serializes a list of names written like `web, worker,db`.
+mod comma_separated {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(names: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&names.join(","))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        let names = String::deserialize(deserializer)?;
+        Ok(names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect())
+    }
+}
+
+/// Deserializes environment variables given either as a map, or as a string like
+/// `KEY="val:123",KEY2="val,456",KEY3=plain`.
+mod environment {
+    use serde::{de::Error, Deserialize, Deserializer};
+    use std::collections::HashMap;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Map(HashMap<String, String>),
+        String(String),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<String, String>, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Map(map) => Ok(map),
+            Repr::String(s) => parse(&s).map_err(D::Error::custom),
+        }
+    }
+
+    /// Parses comma-separated `KEY=value` pairs. Values may be quoted with `"` or `'`, in
+    /// which case they can hold commas.
+    pub fn parse(s: &str) -> Result<HashMap<String, String>, String> {
+        let mut environment = HashMap::new();
+        let mut rest = s.trim_start();
+        while !rest.is_empty() {
+            let eq = rest
+                .find('=')
+                .ok_or_else(|| format!("environment: expected KEY=value in `{}`", rest))?;
+            let key = rest[..eq].trim();
+            if key.is_empty() || key.contains(',') {
+                return Err(format!("environment: expected KEY=value in `{}`", rest));
+            }
+            rest = rest[eq + 1..].trim_start();
+
+            let value = match rest.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let end = rest[1..]
+                        .find(quote)
+                        .ok_or_else(|| format!("environment: unterminated quote for `{}`", key))?;
+                    let value = &rest[1..=end];
+                    rest = rest[end + 2..].trim_start();
+                    value
+                }
+                _ => {
+                    let end = rest.find(',').unwrap_or(rest.len());
+                    let value = rest[..end].trim_end();
+                    rest = &rest[end..];
+                    value
+                }
+            };
+            environment.insert(key.to_string(), value.to_string());
+
+            rest = match rest.strip_prefix(',') {
+                Some(next) => next.trim_start(),
+                None if rest.is_empty() => rest,
+                None => {
+                    return Err(format!(
+                        "environment: expected `,` after the value of `{}`",
+                        key
+                    ))
+                }
+            };
+        }
+        Ok(environment)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Set of programs that can be controlled together.
+pub struct Group {
+    /// Names of the programs in the group, written comma-separated.
+    #[serde(with = "comma_separated")]
+    pub(crate) programs: Vec<String>,
+    /// Order of the group relative to other groups: lower priorities start first
+    /// and stop last.
+    #[serde(default = "default_priority")]
+    pub(crate) priority: i32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+/// Configuration structure used to run a task.
+pub struct Program {
+    /// Name of another program whose settings this one inherits, and selectively overrides.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) extends: Option<String>,
+    #[serde(flatten)]
+    pub(crate) process: ProcessConfig,
+    #[serde(flatten, skip_serializing)]
+    pub(crate) unknown_fields: DenyUnknownFields,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// FastCGI program: a pool of processes sharing one listening socket.
+pub struct FcgiProgram {
+    /// Socket the processes accept connections on, like `tcp://localhost:9002` or
+    /// `unix:///tmp/fcgi.sock`.
+    pub(crate) socket: String,
+    /// Owner of a UNIX socket, as `user` or `user:group`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) socket_owner: Option<String>,
+    /// Permissions of a UNIX socket, in octal.
+    #[serde(default = "default_socket_mode")]
+    pub(crate) socket_mode: String,
+    /// Length of the socket's queue of pending connections.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) socket_backlog: Option<u32>,
+    #[serde(flatten)]
+    pub(crate) process: ProcessConfig,
+    #[serde(flatten, skip_serializing)]
+    pub(crate) unknown_fields: DenyUnknownFields,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Pool of processes notified of the events they subscribe to.
+pub struct EventListener {
+    /// Names of the events the listeners subscribe to.
+    pub(crate) events: Vec<String>,
+    /// Number of events queued for the pool, past which the oldest are dropped.
+    #[serde(default = "default_buffer_size")]
+    pub(crate) buffer_size: usize,
+    #[serde(flatten)]
+    pub(crate) process: ProcessConfig,
+    #[serde(flatten, skip_serializing)]
+    pub(crate) unknown_fields: DenyUnknownFields,
+}
+
+/// Rejects the fields no other field of the section consumed.
+///
+/// Stands in for `deny_unknown_fields`, which serde doesn't support along with `flatten`. It
+/// must come after the flattened fields, since it only sees what they left over.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DenyUnknownFields;
+
+impl<'de> Deserialize<'de> for DenyUnknownFields {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rest = BTreeMap::<String, serde::de::IgnoredAny>::deserialize(deserializer)?;
+        match rest.keys().next() {
+            Some(field) => Err(serde::de::Error::custom(format!(
+                "unknown field `{}`",
+                field
+            ))),
+            None => Ok(Self),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Settings shared by every kind of process section.
+pub struct ProcessConfig {
     /// Command to run.
-    cmd: String,
+    pub(crate) command: String,
     /// Number of processors this task should run with.
     // u16 is fine because we are not expecting a machine to run more
     // than 2^16 proc at any single time.
-    numprocs: u16,
+    #[serde(default = "default_numprocs")]
+    pub(crate) numprocs: u16,
+    /// Template used to name each of the `numprocs` instances, see `template::render`.
+    #[serde(default = "default_process_name")]
+    pub(crate) process_name: String,
     /// Working directory of the task.
-    workingdir: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) directory: Option<PathBuf>,
+    /// Named pipe created at start whose writes are forwarded to the task's stdin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) stdin_fifo: Option<PathBuf>,
+    /// Signal sent to the task to ask it to stop.
+    #[serde(default = "default_stopsignal")]
+    pub(crate) stopsignal: Signal,
+    /// Seconds to wait after sending `stopsignal` before killing the task with SIGKILL.
+    #[serde(default = "default_stopwaitsecs")]
+    pub(crate) stopwaitsecs: u64,
+    /// Whether `stopsignal` is sent to the task's whole process group, rather than to the task
+    /// alone. Implies `killasgroup`.
+    #[serde(default)]
+    pub(crate) stopasgroup: bool,
+    /// Whether the SIGKILL sent past `stopwaitsecs` goes to the task's whole process group.
+    #[serde(default)]
+    pub(crate) killasgroup: bool,
+    /// Order of the program relative to others: lower priorities start first and stop last.
+    #[serde(default = "default_priority")]
+    pub(crate) priority: i32,
+    /// Whether the task is started along with the daemon.
+    #[serde(default = "default_autostart")]
+    pub(crate) autostart: bool,
+    /// When the task is started again after exiting on its own.
+    #[serde(default)]
+    pub(crate) autorestart: Restart,
+    /// Exit codes the task is expected to exit with, see `Restart::Unexpected`.
+    #[serde(default = "default_exitcodes")]
+    pub(crate) exitcodes: Vec<i32>,
+    /// Seconds the task must stay up for its start to count as successful. Exiting sooner
+    /// is a failed start, retried after a delay whatever `autorestart` says. 0 disables the
+    /// check.
+    #[serde(default = "default_startsecs")]
+    pub(crate) startsecs: u64,
+    /// How many failed starts in a row are retried before giving up on the task.
+    #[serde(default = "default_startretries")]
+    pub(crate) startretries: u32,
+    /// User to run the task as. Requires the daemon to run as root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) user: Option<String>,
+    /// Environment variables set for the task, written like `taskmasterd.environment`. They
+    /// take precedence over the daemon-wide ones.
+    #[serde(default, deserialize_with = "environment::deserialize")]
+    pub(crate) environment: HashMap<String, String>,
+    /// Where the task's stdout is written to. Left unset, and without `stdout_syslog`, stdout is
+    /// inherited from the daemon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) stdout_logfile: Option<LogTarget>,
+    /// Size in bytes past which `stdout_logfile` is rotated. 0 disables rotation.
+    #[serde(default = "default_logfile_maxbytes")]
+    pub(crate) stdout_logfile_maxbytes: u64,
+    /// Number of rotated `stdout_logfile` backups to keep.
+    #[serde(default = "default_logfile_backups")]
+    pub(crate) stdout_logfile_backups: u32,
+    /// Whether to also send the task's stdout to syslog, tagged with the process name.
+    #[serde(default)]
+    pub(crate) stdout_syslog: bool,
+    /// Maximum size of the stdout output kept between capture markers, see
+    /// `capture::CaptureBuffer`. 0 disables capture mode.
+    #[serde(default)]
+    pub(crate) stdout_capture_maxbytes: u64,
+    /// Where the task's stderr is written to. Left unset, and without `stderr_syslog`, stderr is
+    /// inherited from the daemon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) stderr_logfile: Option<LogTarget>,
+    /// Size in bytes past which `stderr_logfile` is rotated. 0 disables rotation.
+    #[serde(default = "default_logfile_maxbytes")]
+    pub(crate) stderr_logfile_maxbytes: u64,
+    /// Number of rotated `stderr_logfile` backups to keep.
+    #[serde(default = "default_logfile_backups")]
+    pub(crate) stderr_logfile_backups: u32,
+    /// Whether to also send the task's stderr to syslog, tagged with the process name.
+    #[serde(default)]
+    pub(crate) stderr_syslog: bool,
+    /// Maximum size of the stderr output kept between capture markers. 0 disables capture mode.
+    #[serde(default)]
+    pub(crate) stderr_capture_maxbytes: u64,
+    /// Whether to start each line of the task's log files with the time it was logged at.
+    #[serde(default)]
+    pub(crate) log_timestamps: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PathBuf", into = "PathBuf")]
+/// Log file of a task's stdout or stderr.
+pub enum LogTarget {
+    /// `AUTO`: a file generated under `taskmasterd.childlogdir`.
+    Auto,
+    /// `NONE`: no log file, the output is dropped.
+    None,
+    Path(PathBuf),
+}
+
+impl From<PathBuf> for LogTarget {
+    fn from(path: PathBuf) -> Self {
+        match path.to_str() {
+            Some("AUTO") => Self::Auto,
+            Some("NONE") => Self::None,
+            _ => Self::Path(path),
+        }
+    }
+}
+
+impl From<LogTarget> for PathBuf {
+    fn from(target: LogTarget) -> Self {
+        match target {
+            LogTarget::Auto => "AUTO".into(),
+            LogTarget::None => "NONE".into(),
+            LogTarget::Path(path) => path,
+        }
+    }
+}
+
+fn default_socket_mode() -> String {
+    "0700".into()
+}
+
+const fn default_buffer_size() -> usize {
+    10
+}
+
+const fn default_logfile_maxbytes() -> u64 {
+    50 * 1024 * 1024
+}
+
+const fn default_logfile_backups() -> u32 {
+    10
+}
+
+const fn default_numprocs() -> u16 {
+    1
+}
+
+fn default_process_name() -> String {
+    "%(program_name)s".into()
+}
+
+const fn default_stopsignal() -> Signal {
+    Signal::TERM
+}
+
+const fn default_stopwaitsecs() -> u64 {
+    10
+}
+
+const fn default_priority() -> i32 {
+    999
+}
+
+const fn default_autostart() -> bool {
+    true
+}
+
+fn default_exitcodes() -> Vec<i32> {
+    vec![0]
+}
+
+const fn default_startsecs() -> u64 {
+    1
+}
+
+const fn default_startretries() -> u32 {
+    3
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// When a task that exited on its own is started again.
+pub enum Restart {
+    Never,
+    Always,
+    /// Only if it exited with a code outside of its `exitcodes`, or was killed by a signal.
+    #[default]
+    Unexpected,
+}
+
+/// Also accepts `true` for `always` and `false` for `never`, like supervisord does.
+impl<'de> Deserialize<'de> for Restart {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = Restart;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "one of never, always, unexpected, true or false")
+            }
+
+            fn visit_bool<E: serde::de::Error>(self, value: bool) -> Result<Restart, E> {
+                Ok(if value {
+                    Restart::Always
+                } else {
+                    Restart::Never
+                })
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Restart, E> {
+                match value.to_ascii_lowercase().as_str() {
+                    "never" | "false" => Ok(Restart::Never),
+                    "always" | "true" => Ok(Restart::Always),
+                    "unexpected" => Ok(Restart::Unexpected),
+                    _ => Err(E::invalid_value(serde::de::Unexpected::Str(value), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl Restart {
+    /// Whether a task expected to exit with one of `exitcodes`, that exited with `status`,
+    /// should be started again.
+    pub fn should_restart(self, status: ExitStatus, exitcodes: &[i32]) -> bool {
+        match self {
+            Self::Never => false,
+            Self::Always => true,
+            Self::Unexpected => !status.code().is_some_and(|code| exitcodes.contains(&code)),
+        }
+    }
 }
 
-impl Default for Config {
+impl Default for ProcessConfig {
     fn default() -> Self {
         Self {
-            cmd: "ls".into(),
-            numprocs: 1,
-            workingdir: PathBuf::from("/tmp"),
+            command: "ls".into(),
+            numprocs: default_numprocs(),
+            process_name: default_process_name(),
+            directory: None,
+            stdin_fifo: None,
+            stopsignal: default_stopsignal(),
+            stopwaitsecs: default_stopwaitsecs(),
+            stopasgroup: false,
+            killasgroup: false,
+            priority: default_priority(),
+            autostart: default_autostart(),
+            autorestart: Restart::default(),
+            exitcodes: default_exitcodes(),
+            startsecs: default_startsecs(),
+            startretries: default_startretries(),
+            user: None,
+            environment: HashMap::new(),
+            stdout_logfile: None,
+            stdout_logfile_maxbytes: default_logfile_maxbytes(),
+            stdout_logfile_backups: default_logfile_backups(),
+            stdout_syslog: false,
+            stdout_capture_maxbytes: 0,
+            stderr_logfile: None,
+            stderr_logfile_maxbytes: default_logfile_maxbytes(),
+            stderr_logfile_backups: default_logfile_backups(),
+            stderr_syslog: false,
+            stderr_capture_maxbytes: 0,
+            log_timestamps: false,
         }
     }
 }
 
-impl std::convert::TryFrom<&Path> for Config {
-    type Error = std::io::Error;
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// Signals that can be sent to a task.
+pub enum Signal {
+    TERM,
+    HUP,
+    INT,
+    QUIT,
+    KILL,
+    USR1,
+    USR2,
+}
+
+impl Signal {
+    /// Every supported signal.
+    pub const ALL: [Self; 7] = [
+        Self::TERM,
+        Self::HUP,
+        Self::INT,
+        Self::QUIT,
+        Self::KILL,
+        Self::USR1,
+        Self::USR2,
+    ];
+
+    /// Raw signal number, as expected by `kill(2)`.
+    pub fn as_raw(self) -> libc::c_int {
+        match self {
+            Self::TERM => libc::SIGTERM,
+            Self::HUP => libc::SIGHUP,
+            Self::INT => libc::SIGINT,
+            Self::QUIT => libc::SIGQUIT,
+            Self::KILL => libc::SIGKILL,
+            Self::USR1 => libc::SIGUSR1,
+            Self::USR2 => libc::SIGUSR2,
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SIG{:?}", self)
+    }
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    /// Parses a signal name, with or without its `SIG` prefix, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|signal| format!("{:?}", signal) == name)
+            .ok_or_else(|| {
+                let names = Self::ALL
+                    .iter()
+                    .map(|signal| format!("{:?}", signal))
+                    .collect::<Vec<String>>();
+                format!(
+                    "Unknown signal `{}`, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl Program {
+    /// Name of the program this one inherits its settings from, if any.
+    pub fn extends(&self) -> Option<&str> {
+        self.extends.as_deref()
+    }
+
+    /// Settings of the program's processes.
+    pub fn process(&self) -> &ProcessConfig {
+        &self.process
+    }
+}
+
+/// Builds a `Program` from its command, with defaults for the settings that aren't given.
+///
+/// ```
+/// use taskmaster::config::ProgramBuilder;
+///
+/// let program = ProgramBuilder::new("sleep 10").numprocs(2).priority(1).build();
+/// assert_eq!(program.process().numprocs(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProgramBuilder {
+    program: Program,
+}
+
+impl ProgramBuilder {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            program: Program {
+                process: ProcessConfig {
+                    command: command.into(),
+                    ..ProcessConfig::default()
+                },
+                ..Program::default()
+            },
+        }
+    }
+
+    pub fn numprocs(mut self, numprocs: u16) -> Self {
+        self.program.process.numprocs = numprocs;
+        self
+    }
+
+    pub fn autostart(mut self, autostart: bool) -> Self {
+        self.program.process.autostart = autostart;
+        self
+    }
+
+    pub fn autorestart(mut self, autorestart: Restart) -> Self {
+        self.program.process.autorestart = autorestart;
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.program.process.priority = priority;
+        self
+    }
+
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.program.process.user = Some(user.into());
+        self
+    }
+
+    pub fn directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.program.process.directory = Some(directory.into());
+        self
+    }
+
+    pub fn build(self) -> Program {
+        self.program
+    }
+}
+
+impl Group {
+    /// Names of the programs in the group, as listed.
+    pub fn programs(&self) -> &[String] {
+        &self.programs
+    }
+}
+
+impl ProcessConfig {
+    /// Command to run.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Number of instances to run.
+    pub fn numprocs(&self) -> u16 {
+        self.numprocs
+    }
+
+    /// Template the instances are named after.
+    pub fn process_name(&self) -> &str {
+        &self.process_name
+    }
+
+    /// Working directory, if set.
+    pub fn directory(&self) -> Option<&Path> {
+        self.directory.as_deref()
+    }
+
+    /// Start order relative to the other programs, lowest first.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Whether the task is started along with the daemon.
+    pub fn autostart(&self) -> bool {
+        self.autostart
+    }
+
+    /// When the task is started again after exiting on its own.
+    pub fn autorestart(&self) -> Restart {
+        self.autorestart
+    }
+
+    /// User to run as, if set.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Environment variables set on top of the daemon-wide ones.
+    pub fn environment(&self) -> &HashMap<String, String> {
+        &self.environment
+    }
+
+    /// Where `stream` is written to, if anywhere.
+    pub fn logfile(&self, stream: Stream) -> Option<&LogTarget> {
+        match stream {
+            Stream::Stdout => self.stdout_logfile.as_ref(),
+            Stream::Stderr => self.stderr_logfile.as_ref(),
+        }
+    }
+
+    /// Describes what stopping this program does, signal-wise.
+    pub fn stop_behavior(&self) -> String {
+        let target = |group: bool| {
+            if group {
+                "the process group"
+            } else {
+                "the process"
+            }
+        };
+        let stop = format!(
+            "stop sends {} to {}",
+            self.stopsignal,
+            target(self.stopasgroup)
+        );
+        if self.stopsignal == Signal::KILL {
+            return stop;
+        }
+        // Stopping as a group implies killing as a group, see `Process::spawn`.
+        format!(
+            "{}, then {} to {} after {}s",
+            stop,
+            Signal::KILL,
+            target(self.killasgroup || self.stopasgroup),
+            self.stopwaitsecs
+        )
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    /// Parses a YAML config.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = serde_yaml::from_str(s).map_err(ConfigError::Yaml)?;
+        Self::from_value(value, None)
+    }
+}
+
+/// Parses a JSON config into YAML, to go through the same resolution steps as YAML configs.
+fn json_value(s: &str) -> Result<serde_yaml::Value, ConfigError> {
+    let json: serde_json::Value = serde_json::from_str(s).map_err(ConfigError::Json)?;
+    serde_yaml::to_value(json).map_err(ConfigError::Yaml)
+}
+
+/// Parses a TOML config into YAML, like `json_value`.
+#[cfg(feature = "toml")]
+fn toml_value(s: &str) -> Result<serde_yaml::Value, ConfigError> {
+    let toml: toml::Value = toml::from_str(s).map_err(ConfigError::Toml)?;
+    serde_yaml::to_value(toml).map_err(ConfigError::Yaml)
+}
+
+impl Config {
+    /// Parses a JSON config.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `s` is not valid JSON, or does not describe a valid config.
+    pub fn from_json(s: &str) -> Result<Self, ConfigError> {
+        Self::from_value(json_value(s)?, None)
+    }
+
+    /// Parses a TOML config.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `s` is not valid TOML, or does not describe a valid config.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+        Self::from_value(toml_value(s)?, None)
+    }
+
+    /// Parses the config file at `path`, as JSON or TOML if it has a `.json` or `.toml`
+    /// extension and as YAML otherwise.
+    ///
+    /// `%(here)s` in path settings is replaced with the absolute path of the file's directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the file can't be read, or does not describe a valid config.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ConfigError::NotFound(path.to_path_buf()),
+            _ => ConfigError::Io(e),
+        })?;
+        let value = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => json_value(&content)?,
+            #[cfg(feature = "toml")]
+            Some("toml") => toml_value(&content)?,
+            #[cfg(not(feature = "toml"))]
+            Some("toml") => {
+                return Err(ConfigError::Validation(format!(
+                    "{}: TOML configs need taskmaster to be built with the `toml` feature",
+                    path.display()
+                )))
+            }
+            _ => serde_yaml::from_str(&content).map_err(ConfigError::Yaml)?,
+        };
+        let here = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        let here = here.canonicalize().map_err(ConfigError::Io)?;
+        Self::from_value(value, Some(here))
+    }
+
+    /// Parses the config file at `path`, or the one `find_file` finds if none is given.
+    ///
+    /// # Errors
+    ///
+    /// Same as `from_path` and `find_file`.
+    pub fn parse(path: Option<&Path>) -> Result<Self, ConfigError> {
+        match path {
+            Some(path) => Self::from_path(path),
+            None => Self::from_path(find_file()?),
+        }
+    }
+
+    /// Writes the config back as YAML that parses into an equal config, leaving out the
+    /// settings that are unset.
+    ///
+    /// `%(here)s` is not restored in the paths it was expanded in, and passwords read from a
+    /// `password_file` are written as `password`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if serializing fails.
+    pub fn to_yaml(&self) -> Result<String, ConfigError> {
+        serde_yaml::to_string(self).map_err(ConfigError::Yaml)
+    }
+
+    fn from_value(
+        mut value: serde_yaml::Value,
+        here: Option<PathBuf>,
+    ) -> Result<Self, ConfigError> {
+        resolve_extends(&mut value).map_err(ConfigError::Validation)?;
+        reject_negative(&value).map_err(ConfigError::Validation)?;
+        let mut config: Self = serde_yaml::from_value(value).map_err(ConfigError::Yaml)?;
+        config.expand_paths(here.as_deref());
+        config.here = here;
+        config
+            .read_password_files()
+            .map_err(ConfigError::Validation)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the settings that deserializing alone doesn't catch.
+    ///
+    /// # Errors
+    ///
+    /// Errors with `ConfigError::Validation`, naming the first offending program.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        // Commands address programs and groups by name alone, so names must be unique
+        // across sections.
+        let sections = [
+            ("programs", self.programs.keys().collect::<BTreeSet<_>>()),
+            ("fcgi_programs", self.fcgi_programs.keys().collect()),
+            ("eventlisteners", self.eventlisteners.keys().collect()),
+            ("group", self.group.keys().collect()),
+        ];
+        let mut sections_by_name = BTreeMap::new();
+        for (section, names) in &sections {
+            for name in names {
+                if let Some(first) = sections_by_name.insert(*name, *section) {
+                    return Err(ConfigError::Validation(format!(
+                        "`{}` is defined in both {} and {}",
+                        name, first, section
+                    )));
+                }
+            }
+        }
+
+        let mut processes = self
+            .programs
+            .iter()
+            .map(|(name, program)| (name, &program.process))
+            .chain(
+                self.fcgi_programs
+                    .iter()
+                    .map(|(name, program)| (name, &program.process)),
+            )
+            .chain(
+                self.eventlisteners
+                    .iter()
+                    .map(|(name, listener)| (name, &listener.process)),
+            )
+            .collect::<Vec<(&String, &ProcessConfig)>>();
+        processes.sort_by_key(|&(name, _)| name);
+        for (name, process) in processes {
+            if process.command.trim().is_empty() {
+                return Err(ConfigError::Validation(format!(
+                    "{}: `command` must not be empty",
+                    name
+                )));
+            }
+            if let Some(code) = process
+                .exitcodes
+                .iter()
+                .find(|code| !(0..=255).contains(*code))
+            {
+                return Err(ConfigError::Validation(format!(
+                    "{}: exit code {} in `exitcodes` is not within 0-255",
+                    name, code
+                )));
+            }
+        }
+        let mut fcgi_programs = self.fcgi_programs.iter().collect::<Vec<_>>();
+        fcgi_programs.sort_by_key(|&(name, _)| name);
+        for (name, program) in fcgi_programs {
+            program
+                .mode()
+                .map_err(|e| ConfigError::Validation(format!("{}: {}", name, e)))?;
+            if let Some(owner) = &program.socket_owner {
+                parse_chown(owner).map_err(|e| {
+                    ConfigError::Validation(format!("{}: socket_owner: {}", name, e))
+                })?;
+            }
+        }
+        let mut groups = self.group.iter().collect::<Vec<(&String, &Group)>>();
+        groups.sort_by_key(|&(name, _)| name);
+        for (name, group) in groups {
+            if let Some(missing) = group
+                .programs
+                .iter()
+                .find(|member| !self.programs.contains_key(*member))
+            {
+                return Err(ConfigError::Validation(format!(
+                    "group {}: no program called `{}`",
+                    name, missing
+                )));
+            }
+        }
+        if let Some(server) = &self.inet_http_server {
+            server.bind_addr().map_err(ConfigError::Validation)?;
+        }
+        if let Some(server) = &self.unix_http_server {
+            server.mode().map_err(ConfigError::Validation)?;
+            if let Some(owner) = &server.chown {
+                parse_chown(owner).map_err(|e| {
+                    ConfigError::Validation(format!("unix_http_server: chown: {}", e))
+                })?;
+            }
+        }
+        // Sockets refuse a zero timeout, the client could never connect.
+        if self.taskmasterctl.timeout == 0 {
+            return Err(ConfigError::Validation(
+                "taskmasterctl: `timeout` must be at least 1 second".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Looks up the owners the sockets should be given, `socket_owner` and `chown`, returning
+    /// those that don't exist on this host. Unlike `validate`, only the daemon needs them to:
+    /// clients may read the same config on hosts without them.
+    pub fn check_owners(&self) -> Vec<String> {
+        let mut fcgi_programs = self.fcgi_programs.iter().collect::<Vec<_>>();
+        fcgi_programs.sort_by_key(|&(name, _)| name);
+        let owners = fcgi_programs
+            .into_iter()
+            .filter_map(|(name, program)| {
+                Some((
+                    format!("{}: socket_owner", name),
+                    program.socket_owner.as_ref()?,
+                ))
+            })
+            .chain(
+                self.unix_http_server
+                    .as_ref()
+                    .and_then(|server| server.chown.as_ref())
+                    .map(|owner| ("unix_http_server: chown".to_string(), owner)),
+            );
+        owners
+            .filter_map(|(setting, owner)| {
+                resolve_owner(owner)
+                    .err()
+                    .map(|e| format!("{}: {}", setting, e))
+            })
+            .collect()
+    }
+
+    /// Where the client reaches the daemon: the `serverurl` of `taskmasterctl`, with `AUTO`
+    /// resolved to the `unix_http_server` socket, then to the `inet_http_server` port, then
+    /// to `DEFAULT_ADDR`.
+    pub fn server_url(&self) -> ServerUrl {
+        match &self.taskmasterctl.serverurl {
+            ServerUrl::Auto => {}
+            url => return url.clone(),
+        }
+        if let Some(server) = &self.unix_http_server {
+            return ServerUrl::Unix(server.file.clone());
+        }
+        match self
+            .inet_http_server
+            .as_ref()
+            .map(InetHttpServer::bind_addr)
+        {
+            Some(Ok(mut addr)) => {
+                // Listening on every address includes the loopback one.
+                if addr.ip().is_unspecified() {
+                    addr.set_ip(match addr {
+                        SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                        SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+                    });
+                }
+                ServerUrl::Http(addr.to_string())
+            }
+            _ => ServerUrl::Http(crate::DEFAULT_ADDR.to_string()),
+        }
+    }
+
+    /// Things that are valid but most likely a mistake, for the daemon to warn about.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.process_names().next().is_none() && self.group.is_empty() {
+            warnings.push(
+                "No programs, fcgi_programs, eventlisteners or groups are configured: \
+                 there is nothing to supervise"
+                    .to_string(),
+            );
+        }
+        warnings
+    }
+
+    /// Replaces `%(here)s` with `here`, if known, in every path setting. `%%` escapes are
+    /// left alone in the log files of the programs, which `registry::render` expands later,
+    /// and collapsed into `%` in the other settings, which nothing expands later.
+    fn expand_paths(&mut self, here: Option<&Path>) {
+        let here = here.map(Path::to_string_lossy);
+        let here = here.as_deref();
+        let expand = |path: &mut PathBuf, rendered: bool| {
+            if let Some(expanded) = path.to_str().map(|s| match (rendered, here) {
+                (true, Some(here)) => template::substitute(s, "here", here),
+                (true, None) => s.to_string(),
+                (false, here) => template::substitute_final(s, "here", here),
+            }) {
+                *path = PathBuf::from(expanded);
+            }
+        };
+        let expand_final = |path: &mut PathBuf| expand(path, false);
+
+        let daemon = &mut self.taskmasterd;
+        expand_final(&mut daemon.childlogdir);
+        expand_final(&mut daemon.logfile);
+        let processes = self
+            .programs
+            .values_mut()
+            .map(|program| &mut program.process)
+            .chain(
+                self.fcgi_programs
+                    .values_mut()
+                    .map(|program| &mut program.process),
+            )
+            .chain(
+                self.eventlisteners
+                    .values_mut()
+                    .map(|listener| &mut listener.process),
+            );
+        for process in processes {
+            let paths = process.directory.iter_mut().chain(&mut process.stdin_fifo);
+            paths.for_each(expand_final);
+            let logfiles = process
+                .stdout_logfile
+                .iter_mut()
+                .chain(&mut process.stderr_logfile);
+            for logfile in logfiles {
+                if let LogTarget::Path(path) = logfile {
+                    expand(path, true);
+                }
+            }
+        }
+        if let Some(server) = &mut self.inet_http_server {
+            server.password_file.iter_mut().for_each(expand_final);
+        }
+        if let Some(server) = &mut self.unix_http_server {
+            expand_final(&mut server.file);
+            server.password_file.iter_mut().for_each(expand_final);
+        }
+        let ctl = &mut self.taskmasterctl;
+        ctl.password_file.iter_mut().for_each(expand_final);
+        ctl.history_file.iter_mut().for_each(expand_final);
+    }
+
+    fn read_password_files(&mut self) -> Result<(), String> {
+        if let Some(server) = &mut self.inet_http_server {
+            read_password_file(
+                "inet_http_server",
+                &mut server.password,
+                &server.password_file,
+            )?;
+        }
+        if let Some(server) = &mut self.unix_http_server {
+            read_password_file(
+                "unix_http_server",
+                &mut server.password,
+                &server.password_file,
+            )?;
+        }
+        let ctl = &mut self.taskmasterctl;
+        read_password_file("taskmasterctl", &mut ctl.password, &ctl.password_file)
+    }
+}
+
+/// Settings of the daemon that can't be negative.
+const NON_NEGATIVE_DAEMON: [&str; 4] =
+    ["logfile_maxbytes", "logfile_backups", "minfds", "minprocs"];
+/// Settings of a process that can't be negative.
+const NON_NEGATIVE_PROCESS: [&str; 10] = [
+    "numprocs",
+    "startsecs",
+    "startretries",
+    "stopwaitsecs",
+    "stdout_logfile_maxbytes",
+    "stdout_logfile_backups",
+    "stdout_capture_maxbytes",
+    "stderr_logfile_maxbytes",
+    "stderr_logfile_backups",
+    "stderr_capture_maxbytes",
+];
+
+/// Rejects negative values of the `NON_NEGATIVE_*` settings, naming the program and the
+/// setting. Deserializing would reject them too, only without saying where they are.
+fn reject_negative(config: &serde_yaml::Value) -> Result<(), String> {
+    let check = |name: &str, settings: &serde_yaml::Value, fields: &[&str]| {
+        for field in fields {
+            if let Some(value) = settings.get(*field).and_then(serde_yaml::Value::as_i64) {
+                if value < 0 {
+                    return Err(format!(
+                        "{}: `{}` must not be negative, got {}",
+                        name, field, value
+                    ));
+                }
+            }
+        }
+        Ok(())
+    };
+
+    if let Some(daemon) = config.get("taskmasterd") {
+        check("taskmasterd", daemon, &NON_NEGATIVE_DAEMON)?;
+    }
+    for section in &["programs", "fcgi_programs", "eventlisteners"] {
+        if let Some(serde_yaml::Value::Mapping(programs)) = config.get(*section) {
+            let mut programs = programs
+                .iter()
+                .filter_map(|(name, program)| Some((name.as_str()?, program)))
+                .collect::<Vec<_>>();
+            programs.sort_by_key(|&(name, _)| name);
+            for (name, program) in programs {
+                check(name, program, &NON_NEGATIVE_PROCESS)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Merges the settings of every program's `extends` base beneath its own.
+///
+/// This happens on the raw YAML so that only the fields actually written in the derived
+/// program override the base, rather than every field once defaults are filled in.
+fn resolve_extends(config: &mut serde_yaml::Value) -> Result<(), String> {
+    let programs = match config.get_mut("programs") {
+        Some(serde_yaml::Value::Mapping(programs)) => programs,
+        _ => return Ok(()),
+    };
+
+    let raw = programs
+        .iter()
+        .filter_map(|(name, program)| match (name, program) {
+            (serde_yaml::Value::String(name), serde_yaml::Value::Mapping(program)) => {
+                Some((name.clone(), program.clone()))
+            }
+            _ => None,
+        })
+        .collect::<HashMap<String, serde_yaml::Mapping>>();
+
+    let mut resolved = HashMap::new();
+    for name in raw.keys() {
+        resolve_program(name, &raw, &mut resolved, &mut Vec::new())?;
+    }
+    for (name, program) in resolved {
+        programs.insert(name.into(), serde_yaml::Value::Mapping(program));
+    }
+    Ok(())
+}
+
+/// Resolves the program called `name`, after its base if it has one.
+///
+/// `chain` holds the programs currently being resolved, to detect `extends` cycles.
+fn resolve_program(
+    name: &str,
+    raw: &HashMap<String, serde_yaml::Mapping>,
+    resolved: &mut HashMap<String, serde_yaml::Mapping>,
+    chain: &mut Vec<String>,
+) -> Result<(), String> {
+    if resolved.contains_key(name) {
+        return Ok(());
+    }
+    if chain.iter().any(|n| n == name) {
+        chain.push(name.into());
+        return Err(format!("Cycle in `extends`: {}", chain.join(" -> ")));
+    }
+
+    let program = &raw[name];
+    let merged = match program.get(&"extends".into()) {
+        None => program.clone(),
+        Some(serde_yaml::Value::String(base)) => {
+            if !raw.contains_key(base) {
+                return Err(format!("{} extends unknown program {}", name, base));
+            }
+            chain.push(name.into());
+            resolve_program(base, raw, resolved, chain)?;
+            chain.pop();
+
+            let mut merged = resolved[base].clone();
+            for (key, value) in program {
+                merged.insert(key.clone(), value.clone());
+            }
+            merged
+        }
+        Some(_) => return Err(format!("{}: `extends` must be a program name", name)),
+    };
+    resolved.insert(name.into(), merged);
+    Ok(())
+}
+
+impl std::convert::TryFrom<&Path> for Config {
+    type Error = ConfigError;
+
+    fn try_from(file_name: &Path) -> Result<Self, Self::Error> {
+        Self::from_path(file_name)
+    }
+}
+
+impl Config {
+    /// Settings of the daemon itself.
+    pub fn taskmasterd(&self) -> &Taskmasterd {
+        &self.taskmasterd
+    }
+
+    /// Programs managed by the daemon, by name.
+    pub fn programs(&self) -> &HashMap<String, Program> {
+        &self.programs
+    }
+
+    /// FastCGI programs managed by the daemon, by name.
+    pub fn fcgi_programs(&self) -> &HashMap<String, FcgiProgram> {
+        &self.fcgi_programs
+    }
+
+    /// Event listener pools managed by the daemon, by name.
+    pub fn eventlisteners(&self) -> &HashMap<String, EventListener> {
+        &self.eventlisteners
+    }
+
+    /// Groups of programs, by name.
+    pub fn groups(&self) -> &HashMap<String, Group> {
+        &self.group
+    }
+
+    /// TCP server clients connect to, if any.
+    pub fn inet_http_server(&self) -> Option<&InetHttpServer> {
+        self.inet_http_server.as_ref()
+    }
+
+    /// UNIX socket server clients connect to, if any.
+    pub fn unix_http_server(&self) -> Option<&UnixHttpServer> {
+        self.unix_http_server.as_ref()
+    }
+
+    /// Settings of the client.
+    pub fn taskmasterctl(&self) -> &Taskmasterctl {
+        &self.taskmasterctl
+    }
+
+    /// Settings of the processes of the program, FastCGI program or event listener pool
+    /// called `name`.
+    pub fn process_config(&self, name: &str) -> Option<&ProcessConfig> {
+        self.programs
+            .get(name)
+            .map(|program| &program.process)
+            .or_else(|| self.fcgi_programs.get(name).map(|program| &program.process))
+            .or_else(|| self.eventlisteners.get(name).map(|pool| &pool.process))
+    }
+
+    /// Names of every program, FastCGI program and event listener pool, in no particular
+    /// order.
+    pub fn process_names(&self) -> impl Iterator<Item = &str> {
+        self.programs
+            .keys()
+            .chain(self.fcgi_programs.keys())
+            .chain(self.eventlisteners.keys())
+            .map(String::as_str)
+    }
+
+    /// Sorts program `names` in the order they should be started: by ascending priority, then
+    /// by name so the order is deterministic. Programs should be stopped in the reverse order.
+    pub fn start_order<'a, I: IntoIterator<Item = &'a str>>(&self, names: I) -> Vec<&'a str> {
+        let mut names = names.into_iter().collect::<Vec<&str>>();
+        names.sort_by_key(|&name| {
+            let priority = self
+                .process_config(name)
+                .map_or_else(default_priority, |process| process.priority);
+            (priority, name)
+        });
+        names
+    }
+
+    /// Members of the group called `name`, in start order.
+    pub fn group_members(&self, name: &str) -> Option<Vec<&str>> {
+        self.group
+            .get(name)
+            .map(|group| self.start_order(group.programs.iter().map(String::as_str)))
+    }
+
+    /// Name of the group the program called `name` belongs to. A program outside of
+    /// any group is its own group.
+    pub fn group_name<'a>(&'a self, name: &'a str) -> &'a str {
+        let mut groups = self.group.keys().collect::<Vec<&String>>();
+        groups.sort();
+        groups
+            .into_iter()
+            .find(|group| {
+                self.group[*group]
+                    .programs
+                    .iter()
+                    .any(|member| member == name)
+            })
+            .map_or(name, String::as_str)
+    }
+
+    /// Environment of the program, FastCGI program or event listener pool called `name`: the
+    /// daemon-wide environment, overridden by its own.
+    pub fn environment(&self, name: &str) -> HashMap<&str, &str> {
+        let mut environment = self
+            .taskmasterd
+            .environment
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<HashMap<&str, &str>>();
+        if let Some(process) = self.process_config(name) {
+            environment.extend(
+                process
+                    .environment
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            );
+        }
+        environment
+    }
+
+    /// Computes what changed between `self` and `new`: entry by entry for the programs,
+    /// FastCGI programs, event listener pools and groups, and setting by setting for the
+    /// other sections.
+    pub fn diff(&self, new: &Self) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+        diff_entries(&mut diff, &self.programs, &new.programs);
+        diff_entries(&mut diff, &self.fcgi_programs, &new.fcgi_programs);
+        diff_entries(&mut diff, &self.eventlisteners, &new.eventlisteners);
+        diff_entries(&mut diff, &self.group, &new.group);
+
+        let sections = [
+            (
+                "taskmasterd",
+                field_changes(&self.taskmasterd, &new.taskmasterd),
+            ),
+            (
+                "unix_http_server",
+                field_changes(&self.unix_http_server, &new.unix_http_server),
+            ),
+            (
+                "inet_http_server",
+                field_changes(&self.inet_http_server, &new.inet_http_server),
+            ),
+            (
+                "taskmasterctl",
+                field_changes(&self.taskmasterctl, &new.taskmasterctl),
+            ),
+        ];
+        for (section, mut changes) in IntoIterator::into_iter(sections) {
+            // Don't print passwords along with the rest.
+            for change in changes
+                .iter_mut()
+                .filter(|change| change.field == "password")
+            {
+                for value in [&mut change.old, &mut change.new] {
+                    if !value.is_null() {
+                        *value = "<redacted>".into();
+                    }
+                }
+            }
+            if !changes.is_empty() {
+                diff.sections.insert(section.to_string(), changes);
+            }
+        }
+        diff
+    }
+}
+
+/// Records in `diff` the entries of a section that were added, removed or changed from `old`
+/// to `new`, by name.
+fn diff_entries<T: PartialEq + Serialize>(
+    diff: &mut ConfigDiff,
+    old: &HashMap<String, T>,
+    new: &HashMap<String, T>,
+) {
+    for (name, old_entry) in old {
+        match new.get(name) {
+            None => {
+                diff.removed.insert(name.clone());
+            }
+            Some(new_entry) if new_entry != old_entry => {
+                diff.changed
+                    .insert(name.clone(), field_changes(old_entry, new_entry));
+            }
+            Some(_) => {}
+        }
+    }
+    diff.added
+        .extend(new.keys().filter(|name| !old.contains_key(*name)).cloned());
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+/// Differences between two configurations.
+pub struct ConfigDiff {
+    /// Programs, FastCGI programs, event listener pools and groups only present in the new
+    /// configuration.
+    pub added: BTreeSet<String>,
+    /// Programs, FastCGI programs, event listener pools and groups only present in the old
+    /// configuration.
+    pub removed: BTreeSet<String>,
+    /// Programs, FastCGI programs, event listener pools and groups present in both
+    /// configurations, but with different settings.
+    pub changed: BTreeMap<String, Vec<FieldChange>>,
+    /// Settings that differ in the other sections, like `taskmasterd`, by section.
+    pub sections: BTreeMap<String, Vec<FieldChange>>,
+}
+
+impl ConfigDiff {
+    /// Whether both configurations are identical.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.sections.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes.");
+        }
+        for name in &self.added {
+            writeln!(f, "+ {}", name)?;
+        }
+        for name in &self.removed {
+            writeln!(f, "- {}", name)?;
+        }
+        for (name, changes) in &self.changed {
+            writeln!(f, "~ {}", name)?;
+            for change in changes {
+                writeln!(f, "    {}: {} -> {}", change.field, change.old, change.new)?;
+            }
+        }
+        for (section, changes) in &self.sections {
+            writeln!(f, "~ [{}]", section)?;
+            for change in changes {
+                writeln!(f, "    {}: {} -> {}", change.field, change.old, change.new)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+/// A single setting that differs between two versions of a program or section.
+pub struct FieldChange {
+    pub field: String,
+    /// Old value, `null` if it was unset.
+    pub old: serde_json::Value,
+    /// New value, `null` if it is now unset.
+    pub new: serde_json::Value,
+}
+
+/// Lists the settings that differ between `old` and `new`, in field name order.
+fn field_changes<T: Serialize>(old: &T, new: &T) -> Vec<FieldChange> {
+    let to_map = |entry: &T| match serde_json::to_value(entry) {
+        Ok(serde_json::Value::Object(map)) => map,
+        _ => serde_json::Map::new(),
+    };
+    let (old, new) = (to_map(old), to_map(new));
+
+    old.keys()
+        .chain(new.keys())
+        .collect::<BTreeSet<&String>>()
+        .into_iter()
+        .filter_map(|field| {
+            let old_value = old.get(field).cloned().unwrap_or_default();
+            let new_value = new.get(field).cloned().unwrap_or_default();
+            if old_value == new_value {
+                None
+            } else {
+                Some(FieldChange {
+                    field: field.clone(),
+                    old: old_value,
+                    new: new_value,
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_path() {
+        let config = Config::from_path("./config_files/one_program.yaml").unwrap();
+        assert_eq!(config.programs["ls"].process.command, "ls -l");
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        let config = Config::from_path("./config_files/one_program.yaml").unwrap();
+        let yaml = config.to_yaml().unwrap();
+        assert!(!yaml.contains("null"), "{}", yaml);
+        let reparsed: Config = yaml.parse().unwrap();
+        assert_eq!(
+            reparsed,
+            Config {
+                here: None,
+                ..config
+            }
+        );
+
+        let config: Config = "
+taskmasterd:
+  environment:
+    A: '1'
+programs:
+  web:
+    command: ./web
+    numprocs: 2
+    autorestart: unexpected
+    stopsignal: INT
+    stdout_logfile: NONE
+    stderr_logfile: /tmp/web.err
+group:
+  app:
+    programs: web
+inet_http_server:
+  port: 127.0.0.1:9001
+  username: admin
+  password: '{SHA}0123'
+"
+        .parse()
+        .unwrap();
+        let reparsed: Config = config.to_yaml().unwrap().parse().unwrap();
+        assert_eq!(reparsed, config);
+    }
+
+    #[test]
+    fn unique_names() {
+        let err = "
+programs:
+  php:
+    command: php-fpm
+fcgi_programs:
+  php:
+    command: php-cgi
+    socket: tcp://localhost:9002
+"
+        .parse::<Config>()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`php` is defined in both programs and fcgi_programs"
+        );
+
+        let err = "
+programs:
+  web:
+    command: ls
+group:
+  web:
+    programs: web
+"
+        .parse::<Config>()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`web` is defined in both programs and group"
+        );
+    }
+
+    #[test]
+    fn expands_here() {
+        let dir = crate::temp_dir("here");
+        let path = dir.join("taskmaster.yaml");
+        std::fs::write(
+            &path,
+            "
+taskmasterd:
+  logfile: '%(here)s/tm.log'
+programs:
+  web:
+    command: ls
+    directory: '%(here)s'
+    stdout_logfile: '%(here)s/web.log'
+",
+        )
+        .unwrap();
+
+        let config = Config::from_path(&path).unwrap();
+        let here = dir.canonicalize().unwrap();
+        assert_eq!(config.taskmasterd.logfile, here.join("tm.log"));
+        assert!(config.taskmasterd.logfile.is_absolute());
+        let web = &config.programs["web"].process;
+        assert_eq!(web.directory, Some(here.clone()));
+        assert_eq!(
+            web.stdout_logfile,
+            Some(LogTarget::Path(here.join("web.log")))
+        );
+        assert_eq!(config.here, Some(here));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn collapses_escapes_of_unrendered_paths() {
+        let config: Config = "
+taskmasterd:
+  childlogdir: /tmp/a%%b
+programs:
+  web:
+    command: ls
+    directory: /srv/100%%
+    stdout_logfile: /tmp/a%%b/web.log
+"
+        .parse()
+        .unwrap();
+        assert_eq!(config.taskmasterd.childlogdir, Path::new("/tmp/a%b"));
+        let web = &config.programs["web"].process;
+        assert_eq!(web.directory, Some(PathBuf::from("/srv/100%")));
+        // Collapsed when rendered, at spawn.
+        assert_eq!(
+            web.stdout_logfile,
+            Some(LogTarget::Path("/tmp/a%%b/web.log".into()))
+        );
+    }
+
+    #[test]
+    fn restart_policy() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let config: Config = "
+programs:
+  web:
+    command: ls
+    autostart: false
+    autorestart: always
+"
+        .parse()
+        .unwrap();
+        let expected = ProgramBuilder::new("ls")
+            .autostart(false)
+            .autorestart(Restart::Always)
+            .build();
+        assert_eq!(config.programs["web"], expected);
+
+        let (success, failure) = (ExitStatus::from_raw(0), ExitStatus::from_raw(1 << 8));
+        let killed = ExitStatus::from_raw(libc::SIGKILL);
+        assert!(Restart::Always.should_restart(success, &[0]));
+        assert!(!Restart::Never.should_restart(failure, &[0]));
+        assert!(!Restart::Unexpected.should_restart(success, &[0]));
+        assert!(Restart::Unexpected.should_restart(failure, &[0]));
+        assert!(!Restart::Unexpected.should_restart(failure, &[0, 1]));
+        assert!(Restart::Unexpected.should_restart(killed, &[0]));
+    }
+
+    #[test]
+    fn restart_booleans() {
+        let autorestart = |value: &str| {
+            format!(
+                "programs:\n  web:\n    command: ls\n    autorestart: {}\n",
+                value
+            )
+            .parse::<Config>()
+            .map(|config| config.programs["web"].process.autorestart)
+        };
+        assert_eq!(autorestart("true").unwrap(), Restart::Always);
+        assert_eq!(autorestart("false").unwrap(), Restart::Never);
+        assert_eq!(autorestart("unexpected").unwrap(), Restart::Unexpected);
+        assert_eq!(autorestart("'true'").unwrap(), Restart::Always);
+        assert_eq!(autorestart("Never").unwrap(), Restart::Never);
+
+        let e = autorestart("sometimes").unwrap_err().to_string();
+        assert!(
+            e.contains("one of never, always, unexpected, true or false"),
+            "{}",
+            e
+        );
+        assert!(autorestart("1").is_err());
+
+        let json: Restart = serde_json::from_str("false").unwrap();
+        assert_eq!(json, Restart::Never);
+        assert_eq!(
+            serde_json::to_string(&Restart::Always).unwrap(),
+            "\"always\""
+        );
+    }
+
+    #[test]
+    fn exitcodes_range() {
+        let config: Config = "programs:\n  web:\n    command: ls\n    exitcodes: [0, 2]\n"
+            .parse()
+            .unwrap();
+        assert_eq!(config.programs["web"].process.exitcodes, vec![0, 2]);
+        assert_eq!(
+            ProcessConfig::default().exitcodes,
+            vec![0],
+            "only 0 is expected by default"
+        );
+
+        let err = "programs:\n  web:\n    command: ls\n    exitcodes: [256]\n"
+            .parse::<Config>()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "web: exit code 256 in `exitcodes` is not within 0-255"
+        );
+    }
+
+    #[test]
+    fn environment_forms() {
+        let config: Config = r#"
+taskmasterd:
+  environment: 'KEY="val:123",KEY2="val,456", PLAIN=x'
+programs:
+  web:
+    command: ls
+    environment:
+      KEY: val
+"#
+        .parse()
+        .unwrap();
+        let daemon = &config.taskmasterd.environment;
+        assert_eq!(daemon.len(), 3);
+        assert_eq!(daemon["KEY"], "val:123");
+        assert_eq!(daemon["KEY2"], "val,456");
+        assert_eq!(daemon["PLAIN"], "x");
+        assert_eq!(config.programs["web"].process.environment["KEY"], "val");
+
+        assert_eq!(environment::parse("").unwrap(), HashMap::new());
+        assert_eq!(
+            environment::parse("A='it,s'").unwrap()["A"],
+            "it,s".to_string()
+        );
+        assert!(environment::parse("A=\"open").is_err());
+        assert!(environment::parse("novalue").is_err());
+        assert!(environment::parse("A=\"x\"y").is_err());
+    }
+
+    #[test]
+    fn accessors() {
+        let config = Config::from_path("./config_files/one_program.yaml").unwrap();
+        let ls = config.programs().get("ls").unwrap();
+        assert_eq!(ls.process().command(), "ls -l");
+        assert_eq!(ls.process().numprocs(), 1);
+        assert_eq!(ls.process().directory(), Some(Path::new("/tmp")));
+        assert_eq!(ls.extends(), None);
+        assert!(config.groups().is_empty());
+        assert!(config.inet_http_server().is_none());
+        assert_eq!(config.taskmasterctl().prompt(), "taskmaster> ");
+    }
+
+    #[test]
+    fn flattened_process_config() {
+        let config = Config::from_path("./config_files/one_program.yaml").unwrap();
+        let expected = Program {
+            process: ProcessConfig {
+                command: "ls -l".into(),
+                numprocs: 1,
+                directory: Some("/tmp".into()),
+                ..ProcessConfig::default()
+            },
+            ..Program::default()
+        };
+        assert_eq!(config.programs["ls"], expected);
+        let built = ProgramBuilder::new("ls -l").directory("/tmp").build();
+        assert_eq!(built, expected);
+
+        let unknown = "programs:\n  ls:\n    command: ls\n    bogus: 1\n"
+            .parse::<Config>()
+            .unwrap_err();
+        assert!(unknown.to_string().contains("unknown field `bogus`"));
+
+        let config: Config = "
+fcgi_programs:
+  php:
+    command: php-cgi
+    socket: tcp://localhost:9002
+eventlisteners:
+  mem:
+    command: memmon
+    events: [PROCESS_STATE]
+"
+        .parse()
+        .unwrap();
+        assert_eq!(config.fcgi_programs["php"].process.command, "php-cgi");
+        assert_eq!(config.fcgi_programs["php"].socket_mode, "0700");
+        assert_eq!(config.eventlisteners["mem"].events, vec!["PROCESS_STATE"]);
+        assert_eq!(config.eventlisteners["mem"].buffer_size, 10);
+        assert!(
+            "eventlisteners:\n  mem:\n    command: memmon\n    events: []\n    socket: x\n"
+                .parse::<Config>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn log_targets() {
+        let config: Config = "
+programs:
+  auto:
+    command: ls
+    stdout_logfile: AUTO
+  none:
+    command: ls
+    stdout_logfile: NONE
+  path:
+    command: ls
+    stdout_logfile: /tmp/ls.log
+"
+        .parse()
+        .unwrap();
+        let stdout = |name: &str| config.programs[name].process.stdout_logfile.clone();
+        assert_eq!(stdout("auto"), Some(LogTarget::Auto));
+        assert_eq!(stdout("none"), Some(LogTarget::None));
+        assert_eq!(stdout("path"), Some(LogTarget::Path("/tmp/ls.log".into())));
+        assert_eq!(
+            serde_json::to_value(LogTarget::None).unwrap(),
+            serde_json::json!("NONE")
+        );
+    }
+
+    #[test]
+    fn resource_minimums() {
+        let config: Config = "taskmasterd:\n  strip_ansi: true\n".parse().unwrap();
+        assert_eq!(config.taskmasterd.minfds, 1024);
+        assert_eq!(config.taskmasterd.minprocs, 200);
+
+        let config: Config = "taskmasterd:\n  minfds: 4096\n".parse().unwrap();
+        assert_eq!(config.taskmasterd.minfds, 4096);
+    }
+
+    #[test]
+    fn config_env() {
+        // Both cases share one test, since they set the same process-wide variable.
+        std::env::set_var(CONFIG_ENV, "config_files/one_program.yaml");
+        assert_eq!(
+            find_file().unwrap(),
+            Path::new("config_files/one_program.yaml")
+                .canonicalize()
+                .unwrap()
+        );
+        assert!(Config::parse(None).is_ok());
+        // Both binaries load their config through `find_file`.
+        assert_eq!(
+            crate::server::Options::default().config_file(),
+            Ok(find_file().unwrap())
+        );
+        assert!(crate::client::ctl_config(None).is_ok());
+
+        std::env::set_var(CONFIG_ENV, "config_files/bogus.yaml");
+        let e = find_file().unwrap_err();
+        assert!(matches!(e, ConfigError::NotFound(_)), "{:?}", e);
+        assert!(crate::server::Options::default().config_file().is_err());
+        assert!(crate::client::ctl_config(None).is_err());
+        std::env::remove_var(CONFIG_ENV);
+    }
+
+    #[test]
+    fn lookat_order() {
+        let dir = crate::temp_path("lookat");
+        std::fs::create_dir_all(dir.join("etc")).unwrap();
+        for candidate in &["config.yaml", "etc/taskmasterd.yaml"] {
+            std::fs::write(dir.join(candidate), "programs: {}").unwrap();
+        }
+        let dir = dir.canonicalize().unwrap();
+
+        assert_eq!(find_in(&dir).unwrap(), dir.join("config.yaml"));
+        std::fs::remove_file(dir.join("config.yaml")).unwrap();
+        assert_eq!(find_in(&dir).unwrap(), dir.join("etc/taskmasterd.yaml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn error_variants() {
+        let missing = Config::from_path("./config_files/missing.yaml").unwrap_err();
+        assert!(matches!(missing, ConfigError::NotFound(_)), "{:?}", missing);
+
+        let invalid = "programs: [".parse::<Config>().unwrap_err();
+        assert!(matches!(invalid, ConfigError::Yaml(_)), "{:?}", invalid);
+
+        let invalid = Config::from_json("{").unwrap_err();
+        assert!(matches!(invalid, ConfigError::Json(_)), "{:?}", invalid);
+    }
+
+    #[test]
+    fn json_matches_yaml() {
+        use std::convert::TryFrom;
+
+        let yaml = Config::try_from(Path::new("config_files/one_program.yaml")).unwrap();
+        let json = Config::try_from(Path::new("config_files/one_program.json")).unwrap();
+        assert_eq!(yaml, json);
+        assert!(!json.programs.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn toml_matches_yaml() {
+        let yaml = Config::from_path("config_files/one_program.yaml").unwrap();
+        let toml = Config::from_path("config_files/one_program.toml").unwrap();
+        assert_eq!(yaml, toml);
+    }
+
+    #[test]
+    fn diff_programs() {
+        let old: Config = "
+programs:
+  ls:
+    command: ls
+  cat:
+    command: cat
+    numprocs: 2
+"
+        .parse()
+        .unwrap();
+        let new: Config = "
+programs:
+  cat:
+    command: cat
+    numprocs: 3
+    directory: /tmp
+  top:
+    command: top
+"
+        .parse()
+        .unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, vec!["top".to_string()].into_iter().collect());
+        assert_eq!(diff.removed, vec!["ls".to_string()].into_iter().collect());
+        let fields = diff.changed["cat"]
+            .iter()
+            .map(|change| change.field.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(fields, vec!["directory", "numprocs"]);
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn diff_fcgi_programs() {
+        let old: Config = "
+fcgi_programs:
+  php:
+    command: php-cgi
+    socket: tcp://localhost:9002
+"
+        .parse()
+        .unwrap();
+        let new: Config = "
+fcgi_programs:
+  php:
+    command: php-cgi
+    socket: tcp://localhost:9003
+"
+        .parse()
+        .unwrap();
+
+        let diff = old.diff(&new);
+        let fields = diff.changed["php"]
+            .iter()
+            .map(|change| change.field.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(fields, vec!["socket"]);
+        assert!(diff.to_string().contains("~ php\n"));
+    }
+
+    #[test]
+    fn diff_sections() {
+        let old: Config = "
+taskmasterd:
+  command_timeout: 5
+inet_http_server:
+  port: 127.0.0.1:9001
+  password: secret
+"
+        .parse()
+        .unwrap();
+        let new: Config = "
+taskmasterd:
+  command_timeout: 10
+inet_http_server:
+  port: 127.0.0.1:9001
+  password: hunter2
+"
+        .parse()
+        .unwrap();
+
+        let diff = old.diff(&new);
+        assert!(diff.changed.is_empty());
+        let changes = &diff.sections["taskmasterd"];
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "command_timeout");
+        assert_eq!(changes[0].old, 5);
+        assert_eq!(changes[0].new, 10);
+        let text = diff.to_string();
+        assert!(text.contains("~ [taskmasterd]\n    command_timeout: 5 -> 10\n"));
+        assert!(text.contains("~ [inet_http_server]\n    password:"));
+        assert!(!text.contains("secret") && !text.contains("hunter2"));
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json["sections"]["taskmasterd"][0]["new"], 10);
+    }
+
+    #[test]
+    fn group_members_exist() {
+        let config: Config = "
+programs:
+  web:
+    command: ls
+  db:
+    command: ls
+group:
+  app:
+    programs: ' web , db'
+"
+        .parse()
+        .unwrap();
+        assert_eq!(config.group["app"].programs, vec!["web", "db"]);
+
+        let e = "
+programs:
+  web:
+    command: ls
+group:
+  app:
+    programs: web,nope
+"
+        .parse::<Config>()
+        .unwrap_err();
+        assert_eq!(e.to_string(), "group app: no program called `nope`");
+    }
+
+    #[test]
+    fn group_members_by_priority() {
+        let config: Config = "
+programs:
+  db:
+    command: ls
+    priority: 1
+  web:
+    command: ls
+    priority: 10
+  cache:
+    command: ls
+    priority: 5
+  worker:
+    command: ls
+    priority: 5
+group:
+  app:
+    programs: web, worker, db,cache
+"
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            config.group_members("app"),
+            Some(vec!["db", "cache", "worker", "web"])
+        );
+        assert_eq!(config.group_members("nope"), None);
+    }
+
+    #[test]
+    fn program_environment_wins() {
+        let config: Config = "
+taskmasterd:
+  environment:
+    FOO: base
+    BAR: base
+programs:
+  web:
+    command: ls
+    environment:
+      FOO: override
+  worker:
+    command: ls
+"
+        .parse()
+        .unwrap();
+
+        let web = config.environment("web");
+        assert_eq!((web["FOO"], web["BAR"]), ("override", "base"));
+        let worker = config.environment("worker");
+        assert_eq!((worker["FOO"], worker["BAR"]), ("base", "base"));
+    }
+
+    #[test]
+    fn password_file() {
+        let path = crate::temp_path("pw");
+        // SHA-1 of "secret".
+        std::fs::write(&path, "{SHA}e5e9fa1ba31ecd1ae84f75caaa474f3a663f05f4\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        let config: Config = format!(
+            "
+inet_http_server:
+  port: 127.0.0.1:9001
+  username: user
+  password_file: {}
+",
+            path.display()
+        )
+        .parse()
+        .unwrap();
+        let password = config.inet_http_server.unwrap().password().unwrap();
+        assert!(password.matches("secret"));
+        assert!(!password.matches("guess"));
+
+        let both = format!(
+            "
+taskmasterctl:
+  password: secret
+  password_file: {}
+",
+            path.display()
+        );
+        assert!(both.parse::<Config>().is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = "
+unix_http_server:
+  file: /tmp/taskmaster.sock
+  password_file: /nonexistent/password
+";
+        assert!(missing.parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn negative_counts() {
+        let config = |startretries: i64| {
+            format!(
+                "programs:\n  web:\n    command: sleep 1\n    startretries: {}\n",
+                startretries
+            )
+            .parse::<Config>()
+        };
+        let e = config(-1).unwrap_err();
+        assert!(matches!(e, ConfigError::Validation(_)), "{:?}", e);
+        assert_eq!(
+            e.to_string(),
+            "web: `startretries` must not be negative, got -1"
+        );
+        assert_eq!(config(0).unwrap().programs["web"].process.startretries, 0);
+
+        let e = "taskmasterd:\n  logfile_backups: -3\n"
+            .parse::<Config>()
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "taskmasterd: `logfile_backups` must not be negative, got -3"
+        );
+    }
+
+    #[test]
+    fn nothing_to_supervise() {
+        let config = "taskmasterd:\n  loglevel: debug\n"
+            .parse::<Config>()
+            .unwrap();
+        let warnings = config.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("nothing to supervise"));
+
+        let config = "programs:\n  web:\n    command: sleep 1\n"
+            .parse::<Config>()
+            .unwrap();
+        assert!(config.warnings().is_empty());
+    }
+
+    #[test]
+    fn empty_command() {
+        for command in &["\"\"", "\"  \\t \""] {
+            let config = format!("programs:\n  blank:\n    command: {}\n", command);
+            let e = config.parse::<Config>().unwrap_err();
+            assert!(matches!(e, ConfigError::Validation(_)), "{:?}", e);
+            assert_eq!(e.to_string(), "blank: `command` must not be empty");
+        }
+    }
+
+    #[test]
+    fn zero_timeout() {
+        let e = "taskmasterctl:\n  timeout: 0\n"
+            .parse::<Config>()
+            .unwrap_err();
+        assert!(matches!(e, ConfigError::Validation(_)), "{:?}", e);
+        assert_eq!(
+            e.to_string(),
+            "taskmasterctl: `timeout` must be at least 1 second"
+        );
+    }
+
+    #[test]
+    fn unix_chmod() {
+        let config = "unix_http_server:\n  file: /tmp/taskmaster.sock\n"
+            .parse::<Config>()
+            .unwrap();
+        assert_eq!(config.unix_http_server.unwrap().mode(), Ok(0o700));
+
+        for chmod in &["0999", "rw-rw----", "", "77777"] {
+            let config = format!(
+                "unix_http_server:\n  file: /tmp/taskmaster.sock\n  chmod: \"{}\"\n",
+                chmod
+            );
+            let e = config.parse::<Config>().unwrap_err();
+            assert!(
+                matches!(e, ConfigError::Validation(_)),
+                "{}: {:?}",
+                chmod,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn fcgi_socket_mode_and_owner() {
+        let fcgi = |settings: &str| {
+            format!(
+                "fcgi_programs:\n  php:\n    command: php-cgi\n    socket: unix:///tmp/php.sock\n{}",
+                settings
+            )
+            .parse::<Config>()
+        };
+        let config = fcgi("    socket_mode: '0660'\n").unwrap();
+        assert_eq!(config.fcgi_programs["php"].mode(), Ok(0o660));
+        assert_eq!(fcgi("").unwrap().fcgi_programs["php"].mode(), Ok(0o700));
+
+        for mode in &["0999", "00660", "rw", "+660", ""] {
+            let e = fcgi(&format!("    socket_mode: '{}'\n", mode)).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!(
+                    "php: invalid socket_mode `{}`: expected an octal mode like 0700",
+                    mode
+                )
+            );
+        }
+
+        let user = users::get_current_username().unwrap();
+        let user = user.to_str().unwrap();
+        let group = users::get_group_by_gid(users::get_current_gid()).unwrap();
+        let owner = format!("{}:{}", user, group.name().to_str().unwrap());
+        for owner in &[user, owner.as_str()] {
+            let config = fcgi(&format!("    socket_owner: '{}'\n", owner)).unwrap();
+            assert!(config.check_owners().is_empty());
+        }
+        for owner in &["", ":wheel", "root:", "root:wheel:staff"] {
+            let e = fcgi(&format!("    socket_owner: '{}'\n", owner)).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!(
+                    "php: socket_owner: invalid owner `{}`: expected a user or user:group",
+                    owner
+                )
+            );
+        }
+
+        // Only the daemon looks owners up: clients may not have them.
+        let config = fcgi("    socket_owner: no_such_user_tm\n").unwrap();
+        assert_eq!(
+            config.check_owners(),
+            vec!["php: socket_owner: Unknown user `no_such_user_tm`"]
+        );
+        let config = fcgi(&format!("    socket_owner: '{}:no_such_group_tm'\n", user)).unwrap();
+        assert_eq!(
+            config.check_owners(),
+            vec!["php: socket_owner: Unknown group `no_such_group_tm`"]
+        );
+    }
+
+    #[test]
+    fn chown_owner() {
+        assert_eq!(parse_chown("antoine"), Ok(("antoine".to_string(), None)));
+        assert_eq!(
+            parse_chown("antoine:wheel"),
+            Ok(("antoine".to_string(), Some("wheel".to_string())))
+        );
+        assert!(parse_chown("antoine::wheel").is_err());
+    }
+
+    #[test]
+    fn inet_port() {
+        let bind_addr = |port: &str| {
+            InetHttpServer {
+                port: port.into(),
+                username: None,
+                password: None,
+                password_file: None,
+            }
+            .bind_addr()
+        };
+        assert_eq!(
+            bind_addr("127.0.0.1:9001"),
+            Ok("127.0.0.1:9001".parse().unwrap())
+        );
+        assert_eq!(bind_addr(":9001"), Ok("0.0.0.0:9001".parse().unwrap()));
+        assert_eq!(bind_addr("*:9001"), Ok("0.0.0.0:9001".parse().unwrap()));
+        assert_eq!(bind_addr("[::1]:9001"), Ok("[::1]:9001".parse().unwrap()));
+
+        assert!(bind_addr("9001").is_err());
+        assert!(bind_addr("127.0.0.1:999999").is_err());
+        assert!(bind_addr("localhost:9001").is_err());
+        assert!("inet_http_server:\n  port: \"9001\"\n"
+            .parse::<Config>()
+            .is_err());
+    }
+
+    #[test]
+    fn server_url() {
+        let parse = |url: &str| ServerUrl::try_from(url.to_string());
+        assert_eq!(parse("AUTO"), Ok(ServerUrl::Auto));
+        assert_eq!(
+            parse("http://localhost:9001/"),
+            Ok(ServerUrl::Http("localhost:9001".into()))
+        );
+        assert_eq!(
+            parse("unix:///tmp/taskmaster.sock"),
+            Ok(ServerUrl::Unix("/tmp/taskmaster.sock".into()))
+        );
+        assert!(parse("unix://relative.sock").is_err());
+        assert!(parse("ftp://localhost").is_err());
+        assert!("taskmasterctl:\n  serverurl: localhost:9001\n"
+            .parse::<Config>()
+            .is_err());
+
+        let resolved = |yaml: &str| yaml.parse::<Config>().unwrap().server_url();
+        assert_eq!(
+            resolved("programs: {}\n"),
+            ServerUrl::Http(crate::DEFAULT_ADDR.to_string())
+        );
+        assert_eq!(
+            resolved("inet_http_server:\n  port: '*:9002'\n"),
+            ServerUrl::Http("127.0.0.1:9002".into())
+        );
+        // The UNIX socket is preferred.
+        let both = "
+inet_http_server:
+  port: 127.0.0.1:9002
+unix_http_server:
+  file: /tmp/taskmaster.sock
+";
+        assert_eq!(
+            resolved(both),
+            ServerUrl::Unix("/tmp/taskmaster.sock".into())
+        );
+        let explicit = format!(
+            "{}taskmasterctl:\n  serverurl: http://127.0.0.1:9002\n",
+            both
+        );
+        assert_eq!(
+            resolved(&explicit),
+            ServerUrl::Http("127.0.0.1:9002".into())
+        );
+    }
+
+    #[test]
+    fn prompt() {
+        assert_eq!(Taskmasterctl::default().prompt(), "taskmaster> ");
+        let ctl = Taskmasterctl {
+            prompt: "prod".into(),
+            ..Taskmasterctl::default()
+        };
+        assert_eq!(ctl.prompt(), "prod> ");
+    }
+
+    #[test]
+    fn stop_behavior() {
+        let program = ProcessConfig {
+            stopsignal: Signal::INT,
+            stopwaitsecs: 3,
+            ..ProcessConfig::default()
+        };
+        assert_eq!(
+            program.stop_behavior(),
+            "stop sends SIGINT to the process, then SIGKILL to the process after 3s"
+        );
+
+        let program = ProcessConfig {
+            stopsignal: Signal::KILL,
+            ..ProcessConfig::default()
+        };
+        assert_eq!(program.stop_behavior(), "stop sends SIGKILL to the process");
+
+        let program = ProcessConfig {
+            stopsignal: Signal::INT,
+            stopwaitsecs: 3,
+            killasgroup: true,
+            ..ProcessConfig::default()
+        };
+        assert_eq!(
+            program.stop_behavior(),
+            "stop sends SIGINT to the process, then SIGKILL to the process group after 3s"
+        );
+
+        let program = ProcessConfig {
+            stopsignal: Signal::INT,
+            stopwaitsecs: 3,
+            stopasgroup: true,
+            ..ProcessConfig::default()
+        };
+        assert_eq!(
+            program.stop_behavior(),
+            "stop sends SIGINT to the process group, then SIGKILL to the process group after 3s"
+        );
+
+        let program = ProcessConfig {
+            stopsignal: Signal::KILL,
+            stopasgroup: true,
+            ..ProcessConfig::default()
+        };
+        assert_eq!(
+            program.stop_behavior(),
+            "stop sends SIGKILL to the process group"
+        );
+    }
+
+    #[test]
+    fn extends_base_program() {
+        let config: Config = "
+programs:
+  base:
+    command: sleep 10
+    numprocs: 3
+    stopwaitsecs: 1
+  derived:
+    extends: base
+    command: sleep 20
+  derived_again:
+    extends: derived
+    numprocs: 1
+"
+        .parse()
+        .unwrap();
+
+        let derived = &config.programs["derived"];
+        assert_eq!(derived.process.command, "sleep 20");
+        assert_eq!(derived.process.numprocs, 3);
+        assert_eq!(derived.process.stopwaitsecs, 1);
+
+        let derived_again = &config.programs["derived_again"];
+        assert_eq!(derived_again.process.command, "sleep 20");
+        assert_eq!(derived_again.process.numprocs, 1);
+        assert_eq!(derived_again.extends.as_deref(), Some("derived"));
+    }
+
+    #[test]
+    fn extends_errors() {
+        let cycle = "
+programs:
+  a:
+    extends: b
+    command: ls
+  b:
+    extends: a
+    command: ls
+"
+        .parse::<Config>()
+        .unwrap_err()
+        .to_string();
+        assert!(cycle.starts_with("Cycle in `extends`"), "{}", cycle);
 
-    fn try_from(_file_name: &Path) -> Result<Self, Self::Error> {
-        // TODO: deserialize from yaml file
-        Ok(Self::default())
+        let unknown = "
+programs:
+  a:
+    extends: nope
+    command: ls
+"
+        .parse::<Config>()
+        .unwrap_err()
+        .to_string();
+        assert_eq!(unknown, "a extends unknown program nope");
     }
 }
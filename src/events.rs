@@ -0,0 +1,163 @@
+use crate::response::ProcessState;
+use std::collections::VecDeque;
+
+/// Something that happened to the managed processes, sent to the event listeners subscribed
+/// to it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A process went from one state to another.
+    ProcessStateChange {
+        /// Name of the process instance.
+        process: String,
+        /// Name of the group of its program.
+        group: String,
+        from: ProcessState,
+        to: ProcessState,
+        /// PID of the process, while it runs.
+        pid: Option<u32>,
+    },
+}
+
+impl Event {
+    /// Name of the type of the event, like `PROCESS_STATE_RUNNING`.
+    pub fn name(&self) -> String {
+        match self {
+            Self::ProcessStateChange { to, .. } => format!("PROCESS_STATE_{}", to),
+        }
+    }
+
+    /// Whether a listener subscribed to `subscription` gets the event: either its own name,
+    /// `PROCESS_STATE` for every state change, or `EVENT` for everything.
+    pub fn matches(&self, subscription: &str) -> bool {
+        match self {
+            Self::ProcessStateChange { .. } => {
+                subscription == "EVENT"
+                    || subscription == "PROCESS_STATE"
+                    || subscription == self.name()
+            }
+        }
+    }
+
+    /// Serializes the event the way supervisord does: a header line of `key:value` tokens,
+    /// followed by a payload of `len` bytes.
+    ///
+    /// `serial` numbers the events across all pools, `pool_serial` those sent to `pool`.
+    pub fn serialize(&self, serial: u64, pool: &str, pool_serial: u64) -> Vec<u8> {
+        let payload = match self {
+            Self::ProcessStateChange {
+                process,
+                group,
+                from,
+                pid,
+                ..
+            } => {
+                let mut payload = format!(
+                    "processname:{} groupname:{} from_state:{}",
+                    process, group, from
+                );
+                if let Some(pid) = pid {
+                    payload.push_str(&format!(" pid:{}", pid));
+                }
+                payload
+            }
+        };
+        format!(
+            "ver:3.0 server:taskmaster serial:{} pool:{} poolserial:{} eventname:{} len:{}\n{}",
+            serial,
+            pool,
+            pool_serial,
+            self.name(),
+            payload.len(),
+            payload
+        )
+        .into_bytes()
+    }
+}
+
+/// Events waiting to be sent to an event listener pool. Past `capacity`, the oldest are
+/// dropped.
+#[derive(Debug)]
+pub struct EventQueue {
+    events: VecDeque<Event>,
+    capacity: usize,
+    /// How many events were sent to the pool so far.
+    pub sent: u64,
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            sent: 0,
+        }
+    }
+
+    /// Queues `event`, and returns the event dropped to make room for it, if any.
+    pub fn push(&mut self, event: Event) -> Option<Event> {
+        if self.capacity == 0 {
+            return Some(event);
+        }
+        let dropped = if self.events.len() >= self.capacity {
+            self.events.pop_front()
+        } else {
+            None
+        };
+        self.events.push_back(event);
+        dropped
+    }
+
+    /// Oldest queued event.
+    pub fn front(&self) -> Option<&Event> {
+        self.events.front()
+    }
+
+    /// Removes the oldest queued event, once sent.
+    pub fn pop(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running(process: &str) -> Event {
+        Event::ProcessStateChange {
+            process: process.into(),
+            group: process.into(),
+            from: ProcessState::Stopped,
+            to: ProcessState::Running,
+            pid: Some(42),
+        }
+    }
+
+    #[test]
+    fn serializes() {
+        let event = running("web");
+        assert!(event.matches("PROCESS_STATE"));
+        assert!(event.matches("PROCESS_STATE_RUNNING"));
+        assert!(!event.matches("PROCESS_STATE_EXITED"));
+        let payload = "processname:web groupname:web from_state:STOPPED pid:42";
+        assert_eq!(
+            String::from_utf8(event.serialize(3, "listener", 1)).unwrap(),
+            format!(
+                "ver:3.0 server:taskmaster serial:3 pool:listener poolserial:1 \
+                 eventname:PROCESS_STATE_RUNNING len:{}\n{}",
+                payload.len(),
+                payload
+            )
+        );
+    }
+
+    #[test]
+    fn drops_oldest() {
+        let mut queue = EventQueue::new(2);
+        assert_eq!(queue.push(running("a")), None);
+        assert_eq!(queue.push(running("b")), None);
+        assert_eq!(queue.push(running("c")), Some(running("a")));
+        assert_eq!(queue.pop(), Some(running("b")));
+        assert_eq!(queue.pop(), Some(running("c")));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::config::{self, FcgiProgram};
+use std::convert::TryFrom;
+use std::fs::{self, DirBuilder, Permissions};
+use std::net::TcpListener;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Numbers the directories UNIX sockets are staged in, see `bind_unix_socket`.
+static STAGING: AtomicUsize = AtomicUsize::new(0);
+
+/// Address a FastCGI program listens on, parsed from its `socket`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FcgiSocket {
+    /// `tcp://host:port`.
+    Tcp { host: String, port: u16 },
+    /// `unix:///path/to/socket`.
+    Unix(PathBuf),
+}
+
+/// Parses the `socket` of a FastCGI program, like `tcp://localhost:9002` or
+/// `unix:///tmp/fcgi.sock`.
+///
+/// # Errors
+///
+/// Errors if `socket` is neither a `tcp://` URL with a port, nor a `unix://` URL with an
+/// absolute path.
+pub fn parse_fcgi_socket(socket: &str) -> Result<FcgiSocket, String> {
+    if let Some(addr) = socket.strip_prefix("tcp://") {
+        let (host, port) = addr
+            .rsplit_once(':')
+            .ok_or_else(|| format!("`{}` has no port", socket))?;
+        let port = port
+            .parse()
+            .map_err(|_| format!("`{}` has an invalid port", socket))?;
+        // IPv6 addresses are bracketed, like `tcp://[::1]:9002`.
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(format!("`{}` has no host", socket));
+        }
+        Ok(FcgiSocket::Tcp {
+            host: host.to_string(),
+            port,
+        })
+    } else if let Some(path) = socket.strip_prefix("unix://") {
+        if !path.starts_with('/') {
+            return Err(format!("`{}` must hold an absolute path", socket));
+        }
+        Ok(FcgiSocket::Unix(path.into()))
+    } else {
+        Err(format!(
+            "`{}` is neither a tcp:// nor a unix:// URL",
+            socket
+        ))
+    }
+}
+
+/// Listening socket shared by every process of a FastCGI program, as their stdin. The file
+/// of a UNIX socket is removed once the listener is dropped.
+#[derive(Debug)]
+pub struct FcgiListener {
+    fd: OwnedFd,
+    /// File of a UNIX socket.
+    path: Option<PathBuf>,
+}
+
+impl FcgiListener {
+    /// Binds the `socket` of `program`, with its `socket_backlog`, and for a UNIX socket its
+    /// `socket_mode` and `socket_owner`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `socket` is invalid, or if the socket could not be bound or set up.
+    pub fn open(program: &FcgiProgram) -> Result<Self, String> {
+        let listener = match parse_fcgi_socket(&program.socket)? {
+            FcgiSocket::Tcp { host, port } => Self {
+                fd: TcpListener::bind((host.as_str(), port))
+                    .map_err(|e| format!("Could not bind {}: {:?}", program.socket, e))?
+                    .into(),
+                path: None,
+            },
+            FcgiSocket::Unix(path) => Self {
+                fd: bind_unix_socket(&path, program.mode()?, program.socket_owner.as_deref())?
+                    .into(),
+                path: Some(path),
+            },
+        };
+        if let Some(backlog) = program.socket_backlog {
+            let backlog = libc::c_int::try_from(backlog).unwrap_or(libc::c_int::MAX);
+            // Listening again on a listening socket only changes its backlog.
+            // SAFETY: listen(2) has no memory safety requirements.
+            if unsafe { libc::listen(listener.fd.as_raw_fd(), backlog) } != 0 {
+                return Err(format!(
+                    "Could not set the backlog of {}: {:?}",
+                    program.socket,
+                    std::io::Error::last_os_error()
+                ));
+            }
+        }
+        Ok(listener)
+    }
+
+    /// Duplicate of the socket, for a child to inherit.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the file descriptor could not be duplicated.
+    pub fn try_clone_fd(&self) -> Result<OwnedFd, String> {
+        self.fd
+            .try_clone()
+            .map_err(|e| format!("Could not duplicate the FastCGI socket: {:?}", e))
+    }
+}
+
+/// Binds a UNIX socket at `path`, with permissions `mode` and given to `owner` if set,
+/// replacing a socket left there.
+///
+/// The socket is bound and set up in a private directory next to `path`, then moved in
+/// place, so that it is never reachable with the permissions of the umask.
+///
+/// # Errors
+///
+/// Errors if the socket could not be bound, set up or moved in place, if `owner` doesn't
+/// exist, or if the daemon is not allowed to give the socket away, which takes root.
+pub(crate) fn bind_unix_socket(
+    path: &Path,
+    mode: u32,
+    owner: Option<&str>,
+) -> Result<UnixListener, String> {
+    // Looked up here rather than when validating the config, which clients do too, on hosts
+    // that may not have this user.
+    let owner = owner
+        .map(|owner| {
+            config::resolve_owner(owner)
+                .map(|ids| (owner, ids))
+                .map_err(|e| format!("Could not chown {} to {}: {}", path.display(), owner, e))
+        })
+        .transpose()?;
+    // Left behind by a daemon that didn't exit cleanly, it would fail the bind.
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)
+            .map_err(|e| format!("Could not remove {}: {:?}", path.display(), e))?;
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("Could not bind {}: not a file path", path.display()))?;
+    let staging = path.with_file_name(format!(
+        ".taskmaster-{}-{}",
+        std::process::id(),
+        STAGING.fetch_add(1, Ordering::Relaxed)
+    ));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .map_err(|e| format!("Could not create {}: {:?}", staging.display(), e))?;
+    let staged = staging.join(name);
+    let bound = UnixListener::bind(&staged)
+        .map_err(|e| format!("Could not bind {}: {:?}", path.display(), e))
+        .and_then(|listener| {
+            fs::set_permissions(&staged, Permissions::from_mode(mode))
+                .map_err(|e| format!("Could not chmod {}: {:?}", path.display(), e))?;
+            if let Some((owner, (uid, gid))) = owner {
+                std::os::unix::fs::chown(&staged, Some(uid), gid).map_err(|e| match e.kind() {
+                    std::io::ErrorKind::PermissionDenied => format!(
+                        "Could not chown {} to {}: permission denied, taskmasterd must run as \
+                         root to give files away",
+                        path.display(),
+                        owner
+                    ),
+                    _ => format!("Could not chown {}: {:?}", path.display(), e),
+                })?;
+            }
+            fs::rename(&staged, path)
+                .map_err(|e| format!("Could not bind {}: {:?}", path.display(), e))?;
+            Ok(listener)
+        });
+    if bound.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+    if let Err(e) = fs::remove_dir(&staging) {
+        warn!("Could not remove {}: {:?}", staging.display(), e);
+    }
+    bound
+}
+
+impl Drop for FcgiListener {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Could not remove {}: {:?}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sockets() {
+        assert_eq!(
+            parse_fcgi_socket("tcp://localhost:9002"),
+            Ok(FcgiSocket::Tcp {
+                host: "localhost".into(),
+                port: 9002
+            })
+        );
+        assert_eq!(
+            parse_fcgi_socket("tcp://[::1]:9002"),
+            Ok(FcgiSocket::Tcp {
+                host: "::1".into(),
+                port: 9002
+            })
+        );
+        assert_eq!(
+            parse_fcgi_socket("unix:///tmp/fcgi.sock"),
+            Ok(FcgiSocket::Unix("/tmp/fcgi.sock".into()))
+        );
+        assert!(parse_fcgi_socket("tcp://localhost").is_err());
+        assert!(parse_fcgi_socket("tcp://localhost:http").is_err());
+        assert!(parse_fcgi_socket("unix://fcgi.sock").is_err());
+        assert!(parse_fcgi_socket("localhost:9002").is_err());
+    }
+
+    #[test]
+    fn opens_unix_socket() {
+        let dir = crate::temp_dir("fcgi");
+        let path = dir.join("fcgi.sock");
+        let program: FcgiProgram = serde_yaml::from_str(&format!(
+            "{{socket: 'unix://{}', socket_mode: '0660', socket_backlog: 16, command: php-cgi}}",
+            path.display()
+        ))
+        .unwrap();
+
+        let listener = FcgiListener::open(&program).unwrap();
+        let meta = fs::metadata(&path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o660);
+        std::os::unix::net::UnixStream::connect(&path).unwrap();
+        drop(listener);
+        assert!(!path.exists());
+        // Nothing is left of where it was staged either.
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_socket_owner() {
+        let dir = crate::temp_dir("owner");
+        let path = dir.join("fcgi.sock");
+        let user = users::get_current_username().unwrap();
+        for owner in &[
+            "no_such_user_tm".to_string(),
+            format!("{}:no_such_group_tm", user.to_string_lossy()),
+        ] {
+            let program: FcgiProgram = serde_yaml::from_str(&format!(
+                "{{socket: 'unix://{}', socket_owner: '{}', command: php-cgi}}",
+                path.display(),
+                owner
+            ))
+            .unwrap();
+            let e = FcgiListener::open(&program).unwrap_err();
+            assert!(
+                e.starts_with(&format!(
+                    "Could not chown {} to {}: ",
+                    path.display(),
+                    owner
+                )),
+                "{}",
+                e
+            );
+            assert!(e.contains("Unknown "), "{}", e);
+        }
+        // Nothing was bound, nor staged.
+        fs::remove_dir(&dir).unwrap();
+    }
+}
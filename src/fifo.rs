@@ -0,0 +1,193 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::{ffi::OsStrExt, fs::OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+
+/// Size of the buffer used when forwarding bytes from the FIFO to the process.
+const FORWARD_BUF_SIZE: usize = 4096;
+
+/// Named pipe whose writes are forwarded to a process' stdin.
+///
+/// Writers may connect and disconnect as many times as they want: the reader thread
+/// simply waits for the next writer once the current one is gone.
+/// The FIFO is removed from the filesystem when closed or dropped.
+pub struct StdinFifo {
+    path: PathBuf,
+    running: Arc<AtomicBool>,
+}
+
+impl StdinFifo {
+    /// Creates the FIFO at `path` and starts forwarding everything written to it into `stdin`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the FIFO could not be created, or if the reader thread failed to spawn.
+    pub fn new<W: Write + Send + 'static>(path: &Path, stdin: W) -> Result<Self, String> {
+        mkfifo(path)?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = Arc::clone(&running);
+        let thread_path = path.to_path_buf();
+        thread::Builder::new()
+            .name(format!("fifo-{}", path.display()))
+            .spawn(move || forward(&thread_path, &thread_running, stdin))
+            .map_err(|e| format!("Could not spawn FIFO reader: {:?}", e))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            running,
+        })
+    }
+
+    /// Writes `bytes` to the FIFO, for the reader to forward, without waiting for room in it.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the FIFO could not be opened or written to.
+    pub fn write(&self, bytes: &[u8]) -> std::io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)?
+            .write_all(bytes)
+    }
+
+    /// Stops forwarding and removes the FIFO.
+    pub fn close(&mut self) {
+        if !self.running.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        // The reader might be blocked waiting for a writer: connect to it so it wakes up,
+        // notices it should stop, and exits. Failing here means no reader is waiting.
+        let _ = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path);
+
+        if let Err(e) = fs::remove_file(&self.path) {
+            error!("Could not remove FIFO {}: {:?}", self.path.display(), e);
+        }
+    }
+}
+
+impl Drop for StdinFifo {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Creates a named pipe at `path`, replacing any stale FIFO left behind by a previous run.
+fn mkfifo(path: &Path) -> Result<(), String> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        use std::os::unix::fs::FileTypeExt;
+        if !metadata.file_type().is_fifo() {
+            return Err(format!("{} exists and is not a FIFO", path.display()));
+        }
+        fs::remove_file(path).map_err(|e| format!("{:?}", e))?;
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| format!("{:?}", e))?;
+    // SAFETY: `c_path` is a valid, nul-terminated C string.
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(format!(
+            "Could not create FIFO {}: {:?}",
+            path.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// Reader loop: waits for a writer, forwards its bytes until it disconnects, and starts over.
+fn forward<W: Write>(path: &Path, running: &AtomicBool, mut stdin: W) {
+    let mut buf = [0; FORWARD_BUF_SIZE];
+
+    while running.load(Ordering::SeqCst) {
+        // Blocks until a writer connects.
+        let mut fifo = match File::open(path) {
+            Ok(fifo) => fifo,
+            Err(e) => {
+                error!("Could not open FIFO {}: {:?}", path.display(), e);
+                return;
+            }
+        };
+
+        loop {
+            if !running.load(Ordering::SeqCst) {
+                return;
+            }
+            let bytes = match fifo.read(&mut buf) {
+                // The writer disconnected: wait for the next one.
+                Ok(0) => break,
+                Ok(bytes) => bytes,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("Could not read from FIFO {}: {:?}", path.display(), e);
+                    break;
+                }
+            };
+            if let Err(e) = stdin.write_all(&buf[..bytes]).and_then(|_| stdin.flush()) {
+                // The process is gone, there's nobody left to forward to.
+                error!("Could not forward FIFO input: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    /// Writer sharing what it receives, so the test can inspect what got forwarded.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn wait_for(buf: &SharedBuf, expected: &[u8]) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while buf.0.lock().unwrap().as_slice() != expected {
+            assert!(Instant::now() < deadline, "FIFO input was not forwarded");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn forwards_successive_writers_and_cleans_up() {
+        let path = crate::temp_path("fifo");
+        let buf = SharedBuf::default();
+        let mut fifo = StdinFifo::new(&path, buf.clone()).unwrap();
+
+        for (line, expected) in &[
+            (&b"hello\n"[..], &b"hello\n"[..]),
+            (b"world\n", b"hello\nworld\n"),
+        ] {
+            let mut writer = OpenOptions::new().write(true).open(&path).unwrap();
+            writer.write_all(line).unwrap();
+            drop(writer);
+            wait_for(&buf, expected);
+        }
+
+        fifo.close();
+        assert!(!path.exists());
+    }
+}
@@ -1,8 +1,38 @@
+#[macro_use]
+mod logger;
+
+mod capture;
 pub mod client;
 pub mod command;
 pub mod config;
+mod events;
+mod fcgi;
+mod fifo;
+mod logfile;
+pub mod process;
+mod registry;
+pub mod response;
 pub mod server;
-mod threadpool;
+mod syslog;
+pub mod template;
+pub mod threadpool;
+#[cfg(feature = "notify")]
+mod watch;
+mod webhook;
 
 /// Default address and port of the taskmaster daemon.
 pub const DEFAULT_ADDR: &str = "127.0.0.1:2121";
+
+/// Path in the temporary directory for the test called `name`, unique to this test run.
+#[cfg(test)]
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("taskmaster-{}-{}", name, std::process::id()))
+}
+
+/// Directory at `temp_path(name)`, created if need be.
+#[cfg(test)]
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = temp_path(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
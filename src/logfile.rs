@@ -0,0 +1,506 @@
+use crate::command::Stream;
+use crate::logger;
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::SystemTime;
+
+/// Size of the buffer used when forwarding a child's output to its log file.
+const FORWARD_BUF_SIZE: usize = 4096;
+
+/// Bumped by `reopen_all`: writers opened before reopen their file on their next write.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Log file of a child's stdout or stderr, rotated once it grows past `maxbytes`.
+///
+/// Rotation renames `path` to `path.1`, `path.1` to `path.2`, and so on, keeping at most
+/// `backups` old files.
+pub struct RotatingLogWriter {
+    path: PathBuf,
+    file: File,
+    /// Bytes written to `file` so far.
+    written: u64,
+    /// Size past which the file is rotated. 0 disables rotation.
+    maxbytes: u64,
+    backups: u32,
+    /// Whether to remove ANSI escape sequences before writing.
+    strip_ansi: bool,
+    /// Value of `GENERATION` when `file` was opened.
+    generation: usize,
+    /// Whether to start each line with a timestamp.
+    timestamps: bool,
+    /// Whether the last write ended a line, so the next one starts a new one.
+    line_start: bool,
+}
+
+impl RotatingLogWriter {
+    /// Opens `path` for appending, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the file could not be opened.
+    pub fn new(path: &Path, maxbytes: u64, backups: u32, strip_ansi: bool) -> io::Result<Self> {
+        let file = open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            written,
+            maxbytes,
+            backups,
+            strip_ansi,
+            generation: GENERATION.load(Ordering::SeqCst),
+            timestamps: false,
+            line_start: true,
+        })
+    }
+
+    /// Starts each line written with its ISO 8601 timestamp, see `logger::iso8601`.
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// `bytes`, with a timestamp in front of every line they start. A line split across
+    /// writes only gets one, at its start.
+    fn timestamp_lines(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let stamp = format!("{} ", logger::iso8601(SystemTime::now()));
+        let mut stamped = Vec::with_capacity(bytes.len() + stamp.len());
+        for line in bytes.split_inclusive(|&byte| byte == b'\n') {
+            if self.line_start {
+                stamped.extend_from_slice(stamp.as_bytes());
+            }
+            stamped.extend_from_slice(line);
+            self.line_start = line.ends_with(b"\n");
+        }
+        stamped
+    }
+
+    /// Opens `path` again, so that writes go to a new file once it was renamed or deleted.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the file could not be opened.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.generation = GENERATION.load(Ordering::SeqCst);
+        self.file = open(&self.path)?;
+        self.written = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.backups == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for i in (1..self.backups).rev() {
+                let from = backup_path(&self.path, i);
+                if from.exists() {
+                    fs::rename(&from, backup_path(&self.path, i + 1))?;
+                }
+            }
+            fs::rename(&self.path, backup_path(&self.path, 1))?;
+            self.file = open(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.generation != GENERATION.load(Ordering::SeqCst) {
+            self.reopen()?;
+        }
+        let stripped;
+        let bytes = if self.strip_ansi {
+            stripped = strip_ansi_escapes(buf);
+            &stripped[..]
+        } else {
+            buf
+        };
+        let stamped;
+        let bytes = if self.timestamps {
+            stamped = self.timestamp_lines(bytes);
+            &stamped[..]
+        } else {
+            bytes
+        };
+        if self.maxbytes > 0 && self.written + bytes.len() as u64 > self.maxbytes {
+            // The file may have been emptied by `clear` since.
+            self.written = self.file.metadata()?.len();
+            if self.written > 0 && self.written + bytes.len() as u64 > self.maxbytes {
+                self.rotate()?;
+            }
+        }
+        self.file.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        // Report the whole input as written, even though escapes were dropped from it or
+        // timestamps added.
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Makes every `RotatingLogWriter` reopen its file before its next write, for external
+/// tools that rotate the logs by renaming them.
+pub fn reopen_all() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Writes everything to each of its writers in turn.
+pub struct Tee(pub Vec<Box<dyn Write + Send>>);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for writer in &mut self.0 {
+            writer.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(Write::flush)
+    }
+}
+
+/// Reads what was written to the log at `path` past `offset`, but no more than its last
+/// `max` bytes. Returns the bytes read and the offset they end at.
+///
+/// If the log shrank below `offset`, it got rotated or truncated, and is read from the start.
+pub fn read_from(path: &Path, offset: u64, max: u64) -> io::Result<(Vec<u8>, u64)> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let offset = if len < offset { 0 } else { offset };
+    let start = offset.max(len.saturating_sub(max));
+
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.take(len - start).read_to_end(&mut bytes)?;
+    Ok((bytes, len))
+}
+
+/// Empties the log at `path`, and removes the backups its rotation left behind. A missing
+/// log is left missing.
+///
+/// The log may stay open for writing: writers opened by `RotatingLogWriter` append, so they
+/// carry on from the start of the emptied file, and only rotate it once it grows past
+/// `maxbytes` again.
+///
+/// # Errors
+///
+/// Errors if the log could not be truncated, or a backup could not be removed.
+pub fn clear(path: &Path) -> io::Result<()> {
+    match OpenOptions::new().write(true).open(path) {
+        Ok(file) => file.set_len(0)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    // Rotation numbers backups from 1, without gaps.
+    for i in 1.. {
+        match fs::remove_file(backup_path(path, i)) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Path of an `AUTO` log file, like `<childlogdir>/web-stdout---taskmaster-1a2b3c4d.log`.
+///
+/// The random suffix keeps apart the files of programs sharing a name across daemons.
+pub fn auto_logfile_path(
+    childlogdir: &Path,
+    program_name: &str,
+    stream: Stream,
+    identifier: &str,
+) -> PathBuf {
+    let stream = match stream {
+        Stream::Stdout => "stdout",
+        Stream::Stderr => "stderr",
+    };
+    // Hashers built by `RandomState` are randomly seeded.
+    let suffix = RandomState::new().build_hasher().finish() as u32;
+    childlogdir.join(format!(
+        "{}-{}---{}-{:08x}.log",
+        program_name, stream, identifier, suffix
+    ))
+}
+
+/// Removes the `AUTO` log files of the daemons called `identifier` from `childlogdir`.
+/// Returns how many were removed.
+///
+/// # Errors
+///
+/// Errors if `childlogdir` could not be read, or a file could not be removed.
+pub fn clear_auto_logfiles(childlogdir: &Path, identifier: &str) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(childlogdir)? {
+        let path = entry?.path();
+        let is_auto = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| is_auto_logfile(name, identifier));
+        if is_auto && path.is_file() {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether `name` is the file name of an `AUTO` log file of the daemons called `identifier`,
+/// as `auto_logfile_path` makes them. Those of an identifier merely starting the same don't
+/// count.
+fn is_auto_logfile(name: &str, identifier: &str) -> bool {
+    let (head, suffix) = match name
+        .strip_suffix(".log")
+        .and_then(|stem| stem.rsplit_once('-'))
+    {
+        Some(parts) => parts,
+        None => return false,
+    };
+    suffix.len() == 8
+        && suffix.bytes().all(|b| b.is_ascii_hexdigit())
+        && head
+            .strip_suffix(identifier)
+            .and_then(|head| head.strip_suffix("---"))
+            .is_some_and(|head| head.ends_with("-stdout") || head.ends_with("-stderr"))
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn backup_path(path: &Path, i: u32) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(format!(".{}", i));
+    PathBuf::from(path)
+}
+
+/// Removes ANSI escape sequences from `input`: CSI sequences like the `ESC [ 31 m` SGR
+/// color codes, OSC sequences, and two-byte escapes.
+///
+/// A sequence split across two calls is not recognized.
+pub fn strip_ansi_escapes(input: &[u8]) -> Vec<u8> {
+    const ESC: u8 = 0x1b;
+    const BEL: u8 = 0x07;
+
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] != ESC {
+            output.push(input[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        match input.get(i) {
+            // CSI: parameters and intermediates, up to a final byte in 0x40..=0x7e.
+            Some(b'[') => {
+                i += 1;
+                while i < input.len() && !(0x40..=0x7e).contains(&input[i]) {
+                    i += 1;
+                }
+                i += 1;
+            }
+            // OSC: up to BEL or ESC \.
+            Some(b']') => {
+                i += 1;
+                while i < input.len() {
+                    if input[i] == BEL {
+                        i += 1;
+                        break;
+                    }
+                    if input[i] == ESC && input.get(i + 1) == Some(&b'\\') {
+                        i += 2;
+                        break;
+                    }
+                    i += 1;
+                }
+            }
+            Some(_) => i += 1,
+            None => {}
+        }
+    }
+    output
+}
+
+/// Forwards everything read from `output` to `writer` on a background thread, until the
+/// child closes its end.
+///
+/// # Errors
+///
+/// Errors if the thread failed to spawn.
+pub fn forward<R, W>(name: String, mut output: R, mut writer: W) -> Result<(), String>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::Builder::new()
+        .name(name.clone())
+        .spawn(move || {
+            let mut buf = [0; FORWARD_BUF_SIZE];
+            loop {
+                match output.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(bytes) => {
+                        if let Err(e) = writer.write_all(&buf[..bytes]).and_then(|_| writer.flush())
+                        {
+                            error!("{}: could not write log: {:?}", name, e);
+                            break;
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        error!("{}: could not read output: {:?}", name, e);
+                        break;
+                    }
+                }
+            }
+        })
+        .map(|_| ())
+        .map_err(|e| format!("Could not spawn log forwarder: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{temp_dir, temp_path};
+
+    #[test]
+    fn strips_ansi() {
+        assert_eq!(strip_ansi_escapes(b"\x1b[31mred\x1b[0m"), b"red");
+        assert_eq!(strip_ansi_escapes(b"\x1b[1;32mbold\x1b[K!"), b"bold!");
+        assert_eq!(strip_ansi_escapes(b"\x1b]0;title\x07text"), b"text");
+        assert_eq!(strip_ansi_escapes(b"plain 100%"), b"plain 100%");
+
+        let path = temp_path("strip-ansi");
+        let mut writer = RotatingLogWriter::new(&path, 0, 0, true).unwrap();
+        writer.write_all(b"\x1b[31mred\x1b[0m").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"red");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_tail() {
+        let path = temp_path("tail");
+        fs::write(&path, "0123456789").unwrap();
+        assert_eq!(read_from(&path, 0, 4).unwrap(), (b"6789".to_vec(), 10));
+        assert_eq!(read_from(&path, 8, 4).unwrap(), (b"89".to_vec(), 10));
+
+        // Truncated since last read: start over.
+        fs::write(&path, "ab").unwrap();
+        assert_eq!(read_from(&path, 10, 4).unwrap(), (b"ab".to_vec(), 2));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn auto_logfiles() {
+        let dir = temp_dir("childlogdir");
+        let path = auto_logfile_path(&dir, "web", Stream::Stderr, "test");
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("web-stderr---test-"), "{}", name);
+        assert!(name.ends_with(".log"), "{}", name);
+        assert_eq!(path.parent(), Some(dir.as_path()));
+
+        fs::write(&path, "").unwrap();
+        fs::write(dir.join("web.log"), "").unwrap();
+        // Another daemon's, whose identifier starts like ours.
+        let other = auto_logfile_path(&dir, "web", Stream::Stdout, "test-prod");
+        fs::write(&other, "").unwrap();
+        assert_eq!(clear_auto_logfiles(&dir, "other").unwrap(), 0);
+        assert_eq!(clear_auto_logfiles(&dir, "test").unwrap(), 1);
+        assert!(!path.exists());
+        assert!(other.exists());
+        assert_eq!(clear_auto_logfiles(&dir, "test-prod").unwrap(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clears() {
+        let path = temp_path("clear");
+        let mut writer = RotatingLogWriter::new(&path, 4, 2, false).unwrap();
+        for chunk in &["aaa", "bbb", "ccc"] {
+            writer.write_all(chunk.as_bytes()).unwrap();
+        }
+        assert!(backup_path(&path, 2).exists());
+
+        clear(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(!backup_path(&path, 1).exists());
+        assert!(!backup_path(&path, 2).exists());
+        // Writers still open keep writing to the emptied log, counting from its start.
+        writer.write_all(b"d").unwrap();
+        writer.write_all(b"ee").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dee");
+        assert!(!backup_path(&path, 1).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn timestamps_lines() {
+        let path = temp_path("timestamps");
+        let mut writer = RotatingLogWriter::new(&path, 0, 0, false)
+            .unwrap()
+            .timestamps(true);
+        writer.write_all(b"one li").unwrap();
+        writer.write_all(b"ne\n").unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        let (stamp, line) = log.split_once(' ').unwrap();
+        assert_eq!(line, "one line\n");
+        // Like 2021-03-04T12:34:56.789+01:00.
+        assert_eq!(stamp.len(), 29, "{}", stamp);
+        assert_eq!(&stamp[10..11], "T");
+
+        writer.write_all(b"two\nthree\nfour").unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        let lines = log
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, ["one line", "two", "three", "four"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reopens() {
+        let path = temp_path("reopen");
+        let renamed = temp_path("reopen-renamed");
+        let mut writer = RotatingLogWriter::new(&path, 0, 0, false).unwrap();
+        writer.write_all(b"before").unwrap();
+
+        // Like logrotate does.
+        fs::rename(&path, &renamed).unwrap();
+        writer.write_all(b" still").unwrap();
+        assert!(!path.exists());
+        reopen_all();
+        writer.write_all(b"after").unwrap();
+
+        assert_eq!(fs::read_to_string(&renamed).unwrap(), "before still");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after");
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&renamed).unwrap();
+    }
+
+    #[test]
+    fn rotates() {
+        let path = temp_path("rotate");
+        let mut writer = RotatingLogWriter::new(&path, 4, 1, false).unwrap();
+        for chunk in &["aaa", "bbb", "ccc"] {
+            writer.write_all(chunk.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ccc");
+        assert_eq!(fs::read_to_string(backup_path(&path, 1)).unwrap(), "bbb");
+        assert!(!backup_path(&path, 2).exists());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(backup_path(&path, 1)).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+use crate::config::LogLevel;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Window during which identical messages are collapsed into a single
+/// "last message repeated N times" line.
+const REPEAT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Writes a formatted message to the daemon's activity log at `level`.
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::logger::log($level, &format!($($arg)*))
+    };
+}
+
+/// Logs something that went wrong.
+macro_rules! error {
+    ($($arg:tt)*) => {
+        log!($crate::config::LogLevel::Error, $($arg)*)
+    };
+}
+
+/// Logs something that may go wrong.
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        log!($crate::config::LogLevel::Warn, $($arg)*)
+    };
+}
+
+/// Logs a significant event, like a process starting or stopping.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        log!($crate::config::LogLevel::Info, $($arg)*)
+    };
+}
+
+/// Logs details only useful when debugging.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        log!($crate::config::LogLevel::Debug, $($arg)*)
+    };
+}
+
+/// Activity log of the daemon, the one every module writes to.
+static LOGGER: OnceLock<Mutex<Logger<Box<dyn Write + Send>>>> = OnceLock::new();
+
+fn global() -> &'static Mutex<Logger<Box<dyn Write + Send>>> {
+    // Until the daemon opens its log file.
+    LOGGER.get_or_init(|| Mutex::new(Logger::new(Box::new(io::stderr()), LogLevel::default())))
+}
+
+/// Writes `message` to the activity log, if `level` is not filtered out.
+pub fn log(level: LogLevel, message: &str) {
+    if let Ok(mut logger) = global().lock() {
+        logger.log(level, message, Instant::now());
+    }
+}
+
+/// Sends the activity log to `out` from now on, keeping only messages at `level` or more
+/// severe.
+pub fn redirect(out: Box<dyn Write + Send>, level: LogLevel) {
+    if let Ok(mut logger) = global().lock() {
+        *logger = Logger::new(out, level);
+    }
+}
+
+/// Writes out the count of suppressed messages once the repeat window is over, even if
+/// no other message came in. Meant to be called periodically.
+pub fn tick() {
+    if let Ok(mut logger) = global().lock() {
+        logger.tick(Instant::now());
+    }
+}
+
+/// Line-oriented log collapsing runs of identical messages, like syslog does. Lines are
+/// prefixed with the time and level of their message.
+pub struct Logger<W: Write> {
+    out: W,
+    /// Least severe level written.
+    level: LogLevel,
+    /// Last message written, if any.
+    last: Option<Last>,
+}
+
+struct Last {
+    level: LogLevel,
+    message: String,
+    /// When `message` was last actually written.
+    written_at: Instant,
+    /// How many times `message` was suppressed since then.
+    repeated: usize,
+}
+
+impl<W: Write> Logger<W> {
+    pub fn new(out: W, level: LogLevel) -> Self {
+        Self {
+            out,
+            level,
+            last: None,
+        }
+    }
+
+    fn log(&mut self, level: LogLevel, message: &str, now: Instant) {
+        if level > self.level {
+            return;
+        }
+        if let Some(last) = &mut self.last {
+            if last.level == level
+                && last.message == message
+                && now.duration_since(last.written_at) < REPEAT_WINDOW
+            {
+                last.repeated += 1;
+                return;
+            }
+        }
+
+        self.flush_repeated();
+        self.write(level, message);
+        self.last = Some(Last {
+            level,
+            message: message.into(),
+            written_at: now,
+            repeated: 0,
+        });
+    }
+
+    fn tick(&mut self, now: Instant) {
+        let expired = self
+            .last
+            .as_ref()
+            .is_some_and(|last| now.duration_since(last.written_at) >= REPEAT_WINDOW);
+        if expired {
+            self.flush_repeated();
+        }
+    }
+
+    /// Writes the number of times the last message got suppressed, if it was.
+    fn flush_repeated(&mut self) {
+        let (level, repeated) = match self.last.take() {
+            Some(Last {
+                level, repeated, ..
+            }) if repeated > 0 => (level, repeated),
+            _ => return,
+        };
+        self.write(level, &format!("last message repeated {} times", repeated));
+    }
+
+    fn write(&mut self, level: LogLevel, message: &str) {
+        let line = format!("{} {} {}", timestamp(SystemTime::now()), level, message);
+        // There's nowhere left to report a failure to write to the log.
+        let _ = writeln!(self.out, "{}", line).and_then(|_| self.out.flush());
+    }
+}
+
+/// Abbreviated month names, for `short_date`.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Breaks `time` down in the local time zone.
+fn local_time(time: SystemTime) -> libc::tm {
+    #[allow(clippy::cast_possible_wrap)] // Not before year 292 billion.
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    // SAFETY: `tm` is plain data, that localtime_r fills in.
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    // SAFETY: both pointers are valid for the duration of the call.
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    tm
+}
+
+/// Formats `time` in the local time zone, like `2021-03-04 12:34:56,789`.
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let tm = local_time(time);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02},{:03}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Formats `time` in the local time zone as ISO 8601, like `2021-03-04T12:34:56.789+01:00`.
+pub fn iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let tm = local_time(time);
+    let offset = tm.tm_gmtoff / 60;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}{}{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        since_epoch.subsec_millis(),
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+/// Formats `time` in the local time zone, like `Mar 04 12:34 PM`.
+pub fn short_date(time: SystemTime) -> String {
+    let tm = local_time(time);
+    let hour = match tm.tm_hour % 12 {
+        0 => 12,
+        hour => hour,
+    };
+    #[allow(clippy::cast_sign_loss)] // `tm_mon` is within 0-11.
+    let month = MONTHS[tm.tm_mon as usize % 12];
+    format!(
+        "{} {:02} {}:{:02} {}",
+        month,
+        tm.tm_mday,
+        hour,
+        tm.tm_min,
+        if tm.tm_hour < 12 { "AM" } else { "PM" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines written by `logger`, without their timestamp.
+    fn lines(logger: &Logger<Vec<u8>>) -> Vec<&str> {
+        std::str::from_utf8(&logger.out)
+            .unwrap()
+            .lines()
+            .map(|line| line.splitn(3, ' ').nth(2).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn collapses_repeated_messages() {
+        let start = Instant::now();
+        let mut logger = Logger::new(Vec::new(), LogLevel::Info);
+
+        for _ in 0..4 {
+            logger.log(LogLevel::Error, "crashed", start);
+        }
+        logger.log(LogLevel::Error, "something else", start);
+        assert_eq!(
+            lines(&logger),
+            vec![
+                "ERROR crashed",
+                "ERROR last message repeated 3 times",
+                "ERROR something else"
+            ]
+        );
+    }
+
+    #[test]
+    fn flushes_after_window() {
+        let start = Instant::now();
+        let mut logger = Logger::new(Vec::new(), LogLevel::Info);
+
+        logger.log(LogLevel::Error, "crashed", start);
+        logger.log(LogLevel::Error, "crashed", start);
+        logger.tick(start + REPEAT_WINDOW / 2);
+        assert_eq!(lines(&logger), vec!["ERROR crashed"]);
+
+        logger.tick(start + REPEAT_WINDOW);
+        assert_eq!(
+            lines(&logger),
+            vec!["ERROR crashed", "ERROR last message repeated 1 times"]
+        );
+
+        // The window is over, so the message is written again.
+        logger.log(LogLevel::Error, "crashed", start + REPEAT_WINDOW);
+        assert_eq!(
+            lines(&logger),
+            vec![
+                "ERROR crashed",
+                "ERROR last message repeated 1 times",
+                "ERROR crashed"
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_level() {
+        let start = Instant::now();
+        let mut logger = Logger::new(Vec::new(), LogLevel::Warn);
+
+        logger.log(LogLevel::Info, "spawned", start);
+        logger.log(LogLevel::Error, "crashed", start);
+        assert_eq!(lines(&logger), vec!["ERROR crashed"]);
+
+        let line = std::str::from_utf8(&logger.out).unwrap();
+        let timestamp = line.splitn(3, ' ').take(2).collect::<Vec<&str>>().join(" ");
+        assert_eq!(timestamp.len(), "2021-03-04 12:34:56,789".len(), "{}", line);
+    }
+}
@@ -0,0 +1,1093 @@
+use crate::{
+    capture::SharedCapture,
+    command::Stream,
+    config::{LogTarget, ProcessConfig, Signal, Taskmasterd},
+    fifo::StdinFifo,
+    logfile::{self, RotatingLogWriter, Tee},
+    response::{ExitInfo, ProcessState},
+    syslog::{Severity, SyslogWriter},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often to check whether a process exited while waiting for it to stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A running instance of a task, along with the resources attached to it.
+pub struct Process {
+    /// Name of this instance, rendered from the program's `process_name`.
+    name: String,
+    /// Index of this instance among the program's `numprocs`.
+    num: u16,
+    child: Child,
+    /// Pipe to the child's stdin, unless its stdin FIFO forwards to it.
+    stdin: Option<File>,
+    /// Pipes to and from the child, wherever they are read and written, to hand over.
+    pipes: Pipes,
+    /// When the child was spawned.
+    started: Instant,
+    /// Named pipe forwarding to the child's stdin, if the task asked for one.
+    stdin_fifo: Option<StdinFifo>,
+    /// Signal used to ask the process to stop.
+    stopsignal: Signal,
+    /// How long to wait for the process to exit after `stopsignal` before killing it.
+    stopwaitsecs: u64,
+    /// Whether `stopsignal` is sent to the child's whole process group.
+    stopasgroup: bool,
+    /// Whether the final SIGKILL is sent to the child's whole process group.
+    killasgroup: bool,
+    /// How the child exited, once it has been reaped.
+    exit_status: Option<ExitStatus>,
+    /// When the child was reaped.
+    exited_at: Option<SystemTime>,
+    /// Whether the child exited on its own, rather than being stopped.
+    exited: bool,
+    /// Output captured from stdout, if capture mode is on for it.
+    stdout_capture: Option<SharedCapture>,
+    /// Output captured from stderr, if capture mode is on for it.
+    stderr_capture: Option<SharedCapture>,
+    /// Event being written to the child's stdin, and how many of its bytes were, while the
+    /// pipe didn't take all of it yet.
+    event: Option<(Vec<u8>, usize)>,
+    /// When the process gets killed with SIGKILL if it is still running, once it was asked
+    /// to stop.
+    kill_at: Option<Instant>,
+}
+
+impl Process {
+    /// Spawns the `num`-th instance of the task described by `program`, called `name`.
+    ///
+    /// The child inherits the daemon's environment, with `environment` set on top of it.
+    /// `daemon` holds the daemon-wide settings that apply to every child. If set, `stdin` is
+    /// what the child reads as its stdin, like the listening socket of a FastCGI program, or
+    /// a pipe fed with `write_stdin`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the command is empty, if spawning fails, or if the stdin FIFO or the log files
+    /// could not be set up.
+    pub fn spawn(
+        name: String,
+        program: &ProcessConfig,
+        num: u16,
+        environment: &HashMap<String, String>,
+        daemon: &Taskmasterd,
+        stdin: Option<Stdio>,
+    ) -> Result<Self, String> {
+        let mut args = program.command.split_ascii_whitespace();
+        let executable = args
+            .next()
+            .ok_or_else(|| "Cannot spawn an empty command".to_string())?;
+
+        let mut command = Command::new(executable);
+        command.args(args).envs(environment);
+        if let Some(directory) = &program.directory {
+            // Otherwise spawning fails with a bare ENOENT, as if the command was missing.
+            if !directory.is_dir() {
+                return Err(format!("Directory {} does not exist", directory.display()));
+            }
+            command.current_dir(directory);
+        }
+        if let Some(user) = &program.user {
+            let (uid, gid) = credentials(user, users::get_effective_uid())?;
+            // The group has to be switched first, we won't be allowed to once we dropped root.
+            command.gid(gid).uid(uid);
+        }
+        let stopasgroup = program.stopasgroup;
+        // Stopping as a group implies killing as a group, like supervisord does.
+        let killasgroup = program.killasgroup || stopasgroup;
+        if killasgroup {
+            // Lead a process group of our own, whose ID is our PID, so it can be signaled.
+            command.process_group(0);
+        }
+        if let Some(stdin) = stdin {
+            command.stdin(stdin);
+        } else if program.stdin_fifo.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        let (stdout, stdout_capture) =
+            Output::of(program, Stream::Stdout).open(&name, daemon.strip_ansi)?;
+        let (stderr, stderr_capture) =
+            Output::of(program, Stream::Stderr).open(&name, daemon.strip_ansi)?;
+        if stdout.is_some() {
+            command.stdout(Stdio::piped());
+        } else if program.stdout_logfile == Some(LogTarget::None) {
+            command.stdout(Stdio::null());
+        }
+        if stderr.is_some() {
+            command.stderr(Stdio::piped());
+        } else if program.stderr_logfile == Some(LogTarget::None) {
+            command.stderr(Stdio::null());
+        }
+
+        let mut spawned = command
+            .spawn()
+            .map_err(|e| format!("Could not spawn `{}`: {:?}", program.command, e))?;
+        let stdin = spawned
+            .stdin
+            .take()
+            .map(|stdin| File::from(OwnedFd::from(stdin)));
+        if let (Some(stdin), None) = (&stdin, &program.stdin_fifo) {
+            // So that `write_stdin` never blocks the daemon on a child that doesn't read.
+            // SAFETY: fcntl(2) has no memory safety requirements.
+            unsafe {
+                let fd = stdin.as_raw_fd();
+                libc::fcntl(
+                    fd,
+                    libc::F_SETFL,
+                    libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK,
+                );
+            }
+        }
+        let outputs = [
+            (
+                spawned
+                    .stdout
+                    .take()
+                    .map(|out| File::from(OwnedFd::from(out))),
+                stdout,
+            ),
+            (
+                spawned
+                    .stderr
+                    .take()
+                    .map(|out| File::from(OwnedFd::from(out))),
+                stderr,
+            ),
+        ];
+
+        let mut process = Self {
+            name,
+            num,
+            child: Child {
+                pid: spawned.id(),
+                status: None,
+            },
+            stdin: None,
+            pipes: Pipes::default(),
+            started: Instant::now(),
+            stdin_fifo: None,
+            stopsignal: program.stopsignal,
+            stopwaitsecs: program.stopwaitsecs,
+            stopasgroup,
+            killasgroup,
+            exit_status: None,
+            exited_at: None,
+            exited: false,
+            stdout_capture,
+            stderr_capture,
+            event: None,
+            kill_at: None,
+        };
+        process.connect(program, stdin, outputs)?;
+        Ok(process)
+    }
+
+    /// Takes over `inherited`, an instance of `program` left running by the daemon that
+    /// re-executed into this one, reading its output and feeding its stdin again. Its
+    /// expansions must already be rendered, like for `spawn`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if it is not a child of this daemon, or if its stdin FIFO or log files could
+    /// not be set up.
+    pub fn adopt(
+        inherited: &Inherited,
+        program: &ProcessConfig,
+        daemon: &Taskmasterd,
+    ) -> Result<Self, String> {
+        let take = |fd: Option<RawFd>| -> Result<Option<File>, String> {
+            fd.map(|fd| {
+                // Not for the children spawned from now on.
+                set_cloexec(fd, true).map_err(|e| format!("fd {}: {}", fd, e))?;
+                // SAFETY: the descriptor was handed over for this process alone, so nothing
+                // else owns it.
+                Ok(unsafe { File::from_raw_fd(fd) })
+            })
+            .transpose()
+        };
+        let stdin = take(inherited.stdin)?;
+        let stdout = take(inherited.stdout)?;
+        let stderr = take(inherited.stderr)?;
+        let mut child = Child {
+            pid: inherited.pid,
+            status: None,
+        };
+        child
+            .try_wait()
+            .map_err(|e| format!("Could not adopt pid {}: {}", inherited.pid, e))?;
+
+        let (stdout_sinks, stdout_capture) =
+            Output::of(program, Stream::Stdout).open(&inherited.name, daemon.strip_ansi)?;
+        let (stderr_sinks, stderr_capture) =
+            Output::of(program, Stream::Stderr).open(&inherited.name, daemon.strip_ansi)?;
+        let stopasgroup = program.stopasgroup;
+        let mut process = Self {
+            name: inherited.name.clone(),
+            num: inherited.num,
+            child,
+            stdin: None,
+            pipes: Pipes::default(),
+            started: Instant::now()
+                .checked_sub(inherited.uptime)
+                .unwrap_or_else(Instant::now),
+            stdin_fifo: None,
+            stopsignal: program.stopsignal,
+            stopwaitsecs: program.stopwaitsecs,
+            stopasgroup,
+            killasgroup: program.killasgroup || stopasgroup,
+            exit_status: None,
+            exited_at: None,
+            exited: false,
+            stdout_capture,
+            stderr_capture,
+            event: None,
+            kill_at: None,
+        };
+        process.connect(
+            program,
+            stdin,
+            [(stdout, stdout_sinks), (stderr, stderr_sinks)],
+        )?;
+        Ok(process)
+    }
+
+    /// Hands `stdin`, the pipe to the child's stdin, to the stdin FIFO of `program` if it has
+    /// one, and starts forwarding `outputs`, the pipes from its stdout and stderr, to where
+    /// they are logged. Output with nowhere to go is discarded. The child is killed if any of
+    /// this fails.
+    fn connect(
+        &mut self,
+        program: &ProcessConfig,
+        stdin: Option<File>,
+        outputs: [(Option<File>, Option<Tee>); 2],
+    ) -> Result<(), String> {
+        self.pipes = Pipes {
+            stdin: stdin.as_ref().map(AsRawFd::as_raw_fd),
+            stdout: outputs[0].0.as_ref().map(AsRawFd::as_raw_fd),
+            stderr: outputs[1].0.as_ref().map(AsRawFd::as_raw_fd),
+        };
+        let connected = match (&program.stdin_fifo, stdin) {
+            (Some(path), Some(stdin)) => {
+                StdinFifo::new(&fifo_path(path, self.num, program.numprocs), stdin)
+                    .map(|fifo| self.stdin_fifo = Some(fifo))
+            }
+            (_, stdin) => {
+                self.stdin = stdin;
+                Ok(())
+            }
+        };
+        // The forwarders stop by themselves once the child closes its output.
+        let forwarded = IntoIterator::into_iter(outputs)
+            .zip(&["stdout", "stderr"])
+            .try_for_each(|((output, sinks), stream)| match (output, sinks) {
+                (Some(output), Some(sinks)) => {
+                    logfile::forward(format!("{}-{}", self.name, stream), output, sinks)
+                }
+                (Some(output), None) => {
+                    logfile::forward(format!("{}-{}", self.name, stream), output, io::sink())
+                }
+                (None, _) => Ok(()),
+            });
+        let connected = connected.and(forwarded);
+        if connected.is_err() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+        connected
+    }
+
+    /// Describes the child for the daemon `reexec` is about to run to adopt it, and lets its
+    /// pipes be inherited by that daemon. Returns `None` if it is not running.
+    ///
+    /// # Errors
+    ///
+    /// Errors if its pipes could not be kept open across the exec.
+    pub fn hand_over(&mut self) -> io::Result<Option<Inherited>> {
+        if !self.is_running() {
+            return Ok(None);
+        }
+        let Pipes {
+            stdin,
+            stdout,
+            stderr,
+        } = self.pipes;
+        for fd in [stdin, stdout, stderr].iter().flatten() {
+            set_cloexec(*fd, false)?;
+        }
+        Ok(Some(Inherited {
+            name: self.name.clone(),
+            num: self.num,
+            pid: self.id(),
+            uptime: self.started.elapsed(),
+            stdin,
+            stdout,
+            stderr,
+        }))
+    }
+
+    /// Name of this instance.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Index of this instance among the program's `numprocs`.
+    pub fn num(&self) -> u16 {
+        self.num
+    }
+
+    /// When the child was spawned.
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// PID of the underlying child.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Last payload captured from stdout, if capture mode is on for it.
+    pub fn stdout_capture(&self) -> Option<Vec<u8>> {
+        self.stdout_capture
+            .as_ref()
+            .and_then(SharedCapture::captured)
+    }
+
+    /// Last payload captured from stderr, if capture mode is on for it.
+    pub fn stderr_capture(&self) -> Option<Vec<u8>> {
+        self.stderr_capture
+            .as_ref()
+            .and_then(SharedCapture::captured)
+    }
+
+    /// How the child exited, if it was stopped.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status
+    }
+
+    /// How and when the child exited, if it was reaped.
+    pub fn exit_info(&self) -> Option<ExitInfo> {
+        let status = self.exit_status?;
+        Some(ExitInfo {
+            code: status.code(),
+            signal: status.signal(),
+            at: self.exited_at?,
+        })
+    }
+
+    /// Writes `bytes` to what the child reads as its stdin: the pipe `write_stdin` writes to,
+    /// or its stdin FIFO.
+    ///
+    /// # Errors
+    ///
+    /// Errors if stdin is neither, or could not be written to.
+    pub fn send_input(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match &self.stdin_fifo {
+            Some(fifo) if self.stdin.is_none() => fifo.write(bytes),
+            _ => self.write_stdin(bytes),
+        }
+    }
+
+    /// Writes `bytes` to the child's stdin, if it is a pipe. Fails with `WouldBlock` instead
+    /// of waiting when the pipe is full; up to `PIPE_BUF` bytes are then written all at once,
+    /// or not at all.
+    ///
+    /// # Errors
+    ///
+    /// Errors if stdin is not a pipe, or it could not be written to at once.
+    pub fn write_stdin(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "stdin is not a pipe"))?;
+        let written = stdin.write(bytes)?;
+        if written == bytes.len() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("only {} of {} bytes were written", written, bytes.len()),
+            ))
+        }
+    }
+
+    /// Starts writing `event` to the child's stdin, like `write_stdin`. What the pipe doesn't
+    /// take at once is written by the next calls to `flush_event`, so that the child never
+    /// reads part of an event twice.
+    ///
+    /// # Errors
+    ///
+    /// Errors if stdin is not a pipe, if none of `event` could be written to it, or if the
+    /// previous event is not fully written yet.
+    pub fn write_event(&mut self, event: &[u8]) -> io::Result<()> {
+        self.flush_event()?;
+        if self.event.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "the previous event is not fully written yet",
+            ));
+        }
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "stdin is not a pipe"))?;
+        let written = stdin.write(event)?;
+        if written < event.len() {
+            self.event = Some((event.to_vec(), written));
+        }
+        Ok(())
+    }
+
+    /// Writes what the pipe didn't take yet of the event last passed to `write_event`, as much
+    /// of it as it takes now.
+    ///
+    /// # Errors
+    ///
+    /// Errors if stdin could not be written to for another reason than being full.
+    pub fn flush_event(&mut self) -> io::Result<()> {
+        if let (Some((event, written)), Some(stdin)) = (&mut self.event, &mut self.stdin) {
+            while *written < event.len() {
+                match stdin.write(&event[*written..]) {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => *written += n,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                    Err(e) => return Err(e),
+                }
+            }
+            self.event = None;
+        }
+        Ok(())
+    }
+
+    /// Whether the child is still running.
+    pub fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Whether the child was asked to stop, and wasn't reaped yet.
+    pub fn is_stopping(&self) -> bool {
+        self.kill_at.is_some() && self.exit_status.is_none()
+    }
+
+    /// Whether the child is running, is stopping, was stopped, or exited on its own.
+    pub fn state(&mut self) -> ProcessState {
+        if self.is_running() && self.is_stopping() {
+            ProcessState::Stopping
+        } else if self.is_running() {
+            ProcessState::Running
+        } else if self.exited || self.exit_status.is_none() {
+            // Not reaped yet means it wasn't stopped either.
+            ProcessState::Exited
+        } else {
+            ProcessState::Stopped
+        }
+    }
+
+    /// Reaps the child if it exited on its own, and returns how it exited. Returns `None`
+    /// while it runs, and once it has been reaped.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the child could not be waited for.
+    pub fn try_reap(&mut self) -> Result<Option<ExitStatus>, String> {
+        if self.exit_status.is_some() {
+            return Ok(None);
+        }
+        let status = match self.child.try_wait().map_err(|e| format!("{:?}", e))? {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        if let Some(mut fifo) = self.stdin_fifo.take() {
+            fifo.close();
+        }
+        self.exit_status = Some(status);
+        self.exited_at = Some(SystemTime::now());
+        self.exited = true;
+        Ok(Some(status))
+    }
+
+    /// Stops the process and releases everything attached to it, returning how it exited.
+    ///
+    /// The process is first sent its `stopsignal`; if it is still alive after
+    /// `stopwaitsecs`, it gets killed with SIGKILL. With `stopasgroup` and `killasgroup`,
+    /// these go to the child's whole process group, so its own children are stopped too.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the child could not be signaled, killed or waited for.
+    pub fn stop(&mut self) -> Result<ExitStatus, String> {
+        if let Some(status) = self.exit_status {
+            return Ok(status);
+        }
+        self.request_stop()?;
+        loop {
+            if let Some(status) = self.poll_stop(Instant::now())? {
+                return Ok(status);
+            }
+            thread::sleep(STOP_POLL_INTERVAL);
+        }
+    }
+
+    /// Sends the process its `stopsignal`, without waiting for it to exit: `poll_stop` reaps
+    /// it, or kills it once `stopwaitsecs` elapsed. Does nothing if it was already asked to.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the child could not be signaled.
+    pub fn request_stop(&mut self) -> Result<(), String> {
+        if self.exit_status.is_some() || self.kill_at.is_some() {
+            return Ok(());
+        }
+        if let Some(mut fifo) = self.stdin_fifo.take() {
+            fifo.close();
+        }
+        if self.is_running() {
+            self.signal(self.stopsignal, self.stopasgroup)?;
+        }
+        self.kill_at = Some(Instant::now() + Duration::from_secs(self.stopwaitsecs));
+        Ok(())
+    }
+
+    /// Reaps the process asked to stop by `request_stop` once it exited, killing it with
+    /// SIGKILL first if it is still running at `now`, past its `stopwaitsecs`. Returns how it
+    /// exited, or `None` while it is given time to.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the child could not be killed or waited for.
+    pub fn poll_stop(&mut self, now: Instant) -> Result<Option<ExitStatus>, String> {
+        let kill_at = match self.kill_at {
+            Some(kill_at) if self.exit_status.is_none() => kill_at,
+            _ => return Ok(None),
+        };
+        if self.is_running() {
+            if now < kill_at {
+                return Ok(None);
+            }
+            if self.killasgroup {
+                self.signal(Signal::KILL, true)?;
+            } else {
+                self.child.kill().map_err(|e| format!("{:?}", e))?;
+            }
+        }
+        self.reap().map(Some)
+    }
+
+    /// Sends `signal` to the child, leaving it running unless the signal makes it exit.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the signal could not be delivered.
+    pub fn send_signal(&self, signal: Signal) -> Result<(), String> {
+        self.signal(signal, false)
+    }
+
+    /// Sends `signal` to the child, or to its whole process group if `group` is set.
+    fn signal(&self, signal: Signal, group: bool) -> Result<(), String> {
+        #[allow(clippy::cast_possible_wrap)] // PIDs always fit in a pid_t.
+        let pid = self.child.id() as libc::pid_t;
+        // A negative PID designates the process group of that ID.
+        let pid = if group { -pid } else { pid };
+        // SAFETY: kill(2) has no memory safety requirements.
+        if unsafe { libc::kill(pid, signal.as_raw()) } == 0 {
+            Ok(())
+        } else {
+            Err(format!(
+                "Could not send {:?} to {}: {:?}",
+                signal,
+                pid,
+                std::io::Error::last_os_error()
+            ))
+        }
+    }
+
+    /// Waits for the child so it doesn't linger as a zombie.
+    fn reap(&mut self) -> Result<ExitStatus, String> {
+        let status = self.child.wait().map_err(|e| format!("{:?}", e))?;
+        self.exit_status = Some(status);
+        self.exited_at = Some(SystemTime::now());
+        Ok(status)
+    }
+}
+
+/// A spawned child, waited for by PID rather than through `std::process::Child`, so that
+/// those a re-executed daemon inherits are handled the same way.
+struct Child {
+    pid: u32,
+    /// How it exited, once waited for.
+    status: Option<ExitStatus>,
+}
+
+impl Child {
+    fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// How the child exited, without waiting for it if it still runs.
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.waitpid(libc::WNOHANG)
+    }
+
+    /// Waits for the child to exit.
+    fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.waitpid(0)?
+            .ok_or_else(|| io::Error::other("waitpid returned without a status"))
+    }
+
+    /// Kills the child with SIGKILL, unless it was already waited for.
+    fn kill(&mut self) -> io::Result<()> {
+        if self.status.is_some() {
+            return Ok(());
+        }
+        #[allow(clippy::cast_possible_wrap)] // PIDs always fit in a pid_t.
+        // SAFETY: kill(2) has no memory safety requirements.
+        if unsafe { libc::kill(self.pid as libc::pid_t, libc::SIGKILL) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn waitpid(&mut self, options: libc::c_int) -> io::Result<Option<ExitStatus>> {
+        if self.status.is_some() {
+            return Ok(self.status);
+        }
+        let mut status = 0;
+        loop {
+            #[allow(clippy::cast_possible_wrap)] // PIDs always fit in a pid_t.
+            // SAFETY: `status` is a valid pointer for waitpid(2) to write to.
+            match unsafe { libc::waitpid(self.pid as libc::pid_t, &mut status, options) } {
+                0 => return Ok(None),
+                -1 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+                _ => {
+                    self.status = Some(ExitStatus::from_raw(status));
+                    return Ok(self.status);
+                }
+            }
+        }
+    }
+}
+
+/// Descriptors of the pipes to and from a child, see `Process::hand_over`.
+#[derive(Debug, Default, Clone, Copy)]
+struct Pipes {
+    stdin: Option<RawFd>,
+    stdout: Option<RawFd>,
+    stderr: Option<RawFd>,
+}
+
+/// What a daemon about to re-execute hands over about one of its running processes, for the
+/// new one to `adopt` it: the child itself survives the exec, and so do its pipes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Inherited {
+    /// Name of the instance.
+    pub name: String,
+    /// Index of the instance among the program's `numprocs`.
+    pub num: u16,
+    pub pid: u32,
+    /// How long it had been running.
+    pub uptime: Duration,
+    /// Pipe to its stdin, if it has one.
+    pub stdin: Option<RawFd>,
+    /// Pipe from its stdout, if it is logged.
+    pub stdout: Option<RawFd>,
+    /// Pipe from its stderr, if it is logged.
+    pub stderr: Option<RawFd>,
+}
+
+/// Sets or clears the close-on-exec flag of `fd`, which decides whether it survives `execv`.
+///
+/// # Errors
+///
+/// Errors if `fd` is not an open file descriptor.
+pub fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    // SAFETY: fcntl only reads and writes the flags of the descriptor.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = if cloexec {
+        flags | libc::FD_CLOEXEC
+    } else {
+        flags & !libc::FD_CLOEXEC
+    };
+    // SAFETY: same as above.
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Where one of a task's output streams goes.
+struct Output<'a> {
+    logfile: Option<&'a LogTarget>,
+    maxbytes: u64,
+    backups: u32,
+    syslog: bool,
+    /// Severity of the lines sent to syslog.
+    severity: Severity,
+    /// Size of the capture buffer. 0 disables capture mode.
+    capture_maxbytes: u64,
+    /// Whether each line logged starts with a timestamp.
+    timestamps: bool,
+}
+
+impl<'a> Output<'a> {
+    /// Where `stream` of `program` goes.
+    fn of(program: &'a ProcessConfig, stream: Stream) -> Self {
+        match stream {
+            Stream::Stdout => Self {
+                logfile: program.stdout_logfile.as_ref(),
+                maxbytes: program.stdout_logfile_maxbytes,
+                backups: program.stdout_logfile_backups,
+                syslog: program.stdout_syslog,
+                severity: Severity::Info,
+                capture_maxbytes: program.stdout_capture_maxbytes,
+                timestamps: program.log_timestamps,
+            },
+            Stream::Stderr => Self {
+                logfile: program.stderr_logfile.as_ref(),
+                maxbytes: program.stderr_logfile_maxbytes,
+                backups: program.stderr_logfile_backups,
+                syslog: program.stderr_syslog,
+                severity: Severity::Err,
+                capture_maxbytes: program.stderr_capture_maxbytes,
+                timestamps: program.log_timestamps,
+            },
+        }
+    }
+
+    /// Opens the log file, the syslog connection and the capture buffer the stream is
+    /// written to, if any. No writer means the stream is inherited from the daemon.
+    fn open(
+        &self,
+        name: &str,
+        strip_ansi: bool,
+    ) -> Result<(Option<Tee>, Option<SharedCapture>), String> {
+        let mut sinks: Vec<Box<dyn Write + Send>> = Vec::new();
+        match self.logfile {
+            Some(LogTarget::Path(path)) => {
+                let log = RotatingLogWriter::new(path, self.maxbytes, self.backups, strip_ansi)
+                    .map_err(|e| format!("Could not open {}: {:?}", path.display(), e))?
+                    .timestamps(self.timestamps);
+                sinks.push(Box::new(log));
+            }
+            Some(LogTarget::Auto) => return Err("AUTO log file was not resolved".to_string()),
+            Some(LogTarget::None) | None => {}
+        }
+        if self.syslog {
+            let syslog = SyslogWriter::new(name, self.severity)
+                .map_err(|e| format!("Could not connect to syslog: {:?}", e))?;
+            sinks.push(Box::new(syslog));
+        }
+        let capture = match self.capture_maxbytes {
+            0 => None,
+            maxbytes => Some(SharedCapture::new(
+                usize::try_from(maxbytes).unwrap_or(usize::MAX),
+            )),
+        };
+        if let Some(capture) = &capture {
+            sinks.push(Box::new(capture.clone()));
+        }
+
+        let sinks = if sinks.is_empty() {
+            None
+        } else {
+            Some(Tee(sinks))
+        };
+        Ok((sinks, capture))
+    }
+}
+
+/// Resolves `user` into the uid and primary gid a child should run as, given the
+/// effective uid `euid` of the daemon.
+fn credentials(user: &str, euid: u32) -> Result<(u32, u32), String> {
+    let account =
+        users::get_user_by_name(user).ok_or_else(|| format!("Unknown user `{}`", user))?;
+    if euid != 0 {
+        return Err(format!(
+            "Cannot run as `{}`: the daemon is not running as root",
+            user
+        ));
+    }
+    Ok((account.uid(), account.primary_group_id()))
+}
+
+/// Each instance of a task needs its own FIFO, so suffix the path with the instance number
+/// when running more than one.
+fn fifo_path(path: &Path, num: u16, numprocs: u16) -> PathBuf {
+    if numprocs > 1 {
+        let mut path = path.as_os_str().to_os_string();
+        path.push(format!(".{}", num));
+        PathBuf::from(path)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait_for_exit(process: &mut Process) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while process.is_running() {
+            assert!(Instant::now() < deadline, "process did not exit");
+            thread::sleep(STOP_POLL_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn runs_in_directory() {
+        let dir = crate::temp_dir("cwd");
+        let program = ProcessConfig {
+            // Relative redirection, so the file lands in the child's working directory.
+            command: "/bin/sh -c /bin/pwd>cwd".into(),
+            directory: Some(dir.clone()),
+            ..ProcessConfig::default()
+        };
+
+        let mut process = Process::spawn(
+            "pwd".into(),
+            &program,
+            0,
+            &HashMap::new(),
+            &Taskmasterd::default(),
+            None,
+        )
+        .unwrap();
+        wait_for_exit(&mut process);
+        process.stop().unwrap();
+
+        let cwd = std::fs::read_to_string(dir.join("cwd")).unwrap();
+        assert_eq!(Path::new(cwd.trim()), dir.canonicalize().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sets_environment() {
+        let dir = crate::temp_dir("env");
+        let program = ProcessConfig {
+            command: "/bin/sh -c /usr/bin/env>env".into(),
+            directory: Some(dir.clone()),
+            ..ProcessConfig::default()
+        };
+        let environment = vec![("FOO".to_string(), "bar".to_string())]
+            .into_iter()
+            .collect();
+
+        let mut process = Process::spawn(
+            "env".into(),
+            &program,
+            0,
+            &environment,
+            &Taskmasterd::default(),
+            None,
+        )
+        .unwrap();
+        wait_for_exit(&mut process);
+        process.stop().unwrap();
+
+        let env = std::fs::read_to_string(dir.join("env")).unwrap();
+        assert!(env.lines().any(|line| line == "FOO=bar"), "{}", env);
+        // The daemon's own environment is still inherited.
+        assert!(env.lines().any(|line| line.starts_with("PATH=")), "{}", env);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn logs_stdout() {
+        let path = crate::temp_path("stdout");
+        let program = ProcessConfig {
+            command: "echo hello".into(),
+            stdout_logfile: Some(LogTarget::Path(path.clone())),
+            ..ProcessConfig::default()
+        };
+
+        let mut process = Process::spawn(
+            "echo".into(),
+            &program,
+            0,
+            &HashMap::new(),
+            &Taskmasterd::default(),
+            None,
+        )
+        .unwrap();
+        wait_for_exit(&mut process);
+        process.stop().unwrap();
+
+        // The forwarder may still be catching up with the child's output.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while std::fs::read_to_string(&path).unwrap() != "hello\n" {
+            assert!(Instant::now() < deadline, "output was not logged");
+            thread::sleep(STOP_POLL_INTERVAL);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn writes_events_in_full() {
+        let dir = crate::temp_dir("event");
+        // Only starts reading once the pipe is full.
+        std::fs::write(dir.join("listen"), "sleep 1; head -c 200000 >events").unwrap();
+        let program = ProcessConfig {
+            command: "/bin/sh listen".into(),
+            directory: Some(dir.clone()),
+            ..ProcessConfig::default()
+        };
+        let mut process = Process::spawn(
+            "listener".into(),
+            &program,
+            0,
+            &HashMap::new(),
+            &Taskmasterd::default(),
+            Some(Stdio::piped()),
+        )
+        .unwrap();
+
+        let event = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        process.write_event(&event).unwrap();
+        assert!(process.event.is_some());
+        let e = process.write_event(b"next").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while process.event.is_some() {
+            assert!(Instant::now() < deadline, "event was not written");
+            process.flush_event().unwrap();
+            thread::sleep(STOP_POLL_INTERVAL);
+        }
+        wait_for_exit(&mut process);
+        process.stop().unwrap();
+        assert_eq!(std::fs::read(dir.join("events")).unwrap(), event);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn captures_stdout() {
+        let program = ProcessConfig {
+            command: "echo <!--XSUPERVISOR:BEGIN-->done<!--XSUPERVISOR:END-->".into(),
+            stdout_capture_maxbytes: 64,
+            ..ProcessConfig::default()
+        };
+
+        let mut process = Process::spawn(
+            "echo".into(),
+            &program,
+            0,
+            &HashMap::new(),
+            &Taskmasterd::default(),
+            None,
+        )
+        .unwrap();
+        wait_for_exit(&mut process);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while process.stdout_capture().is_none() {
+            assert!(Instant::now() < deadline, "output was not captured");
+            thread::sleep(STOP_POLL_INTERVAL);
+        }
+        assert_eq!(process.stdout_capture(), Some(b"done".to_vec()));
+        assert_eq!(process.stderr_capture(), None);
+        process.stop().unwrap();
+    }
+
+    #[test]
+    fn kills_as_group() {
+        let dir = crate::temp_dir("group");
+        // The shell ignores SIGTERM, and so does the sleep it forks: only the group-wide
+        // SIGKILL gets rid of both.
+        let program = ProcessConfig {
+            command:
+                "/bin/sh -c trap${IFS}''${IFS}TERM;sleep${IFS}100&echo${IFS}$!>grandchild;wait"
+                    .into(),
+            directory: Some(dir.clone()),
+            stopwaitsecs: 1,
+            killasgroup: true,
+            ..ProcessConfig::default()
+        };
+
+        let mut process = Process::spawn(
+            "group".into(),
+            &program,
+            0,
+            &HashMap::new(),
+            &Taskmasterd::default(),
+            None,
+        )
+        .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let grandchild = loop {
+            match std::fs::read_to_string(dir.join("grandchild")) {
+                Ok(pid) if pid.ends_with('\n') => break pid.trim().to_string(),
+                _ => {
+                    assert!(Instant::now() < deadline, "grandchild did not start");
+                    thread::sleep(STOP_POLL_INTERVAL);
+                }
+            }
+        };
+
+        process.stop().unwrap();
+        assert!(!process.is_running());
+        // Once killed, the grandchild either is gone or lingers as a zombie until reaped.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", grandchild)) {
+            if stat
+                .rsplit(')')
+                .next()
+                .unwrap()
+                .trim_start()
+                .starts_with('Z')
+            {
+                break;
+            }
+            assert!(Instant::now() < deadline, "grandchild survived");
+            thread::sleep(STOP_POLL_INTERVAL);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_directory() {
+        let program = ProcessConfig {
+            directory: Some(PathBuf::from("/no/such/directory")),
+            ..ProcessConfig::default()
+        };
+        let e = Process::spawn(
+            "ls".into(),
+            &program,
+            0,
+            &HashMap::new(),
+            &Taskmasterd::default(),
+            None,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(e, "Directory /no/such/directory does not exist");
+    }
+
+    #[test]
+    fn credentials_of_user() {
+        assert_eq!(credentials("root", 0), Ok((0, 0)));
+        assert!(credentials("no-such-user-here", 0).is_err());
+    }
+
+    #[test]
+    fn credentials_need_root() {
+        let e = credentials("root", 1000).unwrap_err();
+        assert!(e.contains("not running as root"), "{}", e);
+    }
+}
@@ -0,0 +1,1740 @@
+use crate::{
+    command::Stream,
+    config::{Config, ConfigDiff, LogTarget, ProcessConfig, Signal},
+    events::{Event, EventQueue},
+    fcgi::FcgiListener,
+    logfile,
+    process::{Inherited, Process},
+    response::{ProcessState, StatusReport},
+    template::{self, Context},
+    webhook,
+};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// How many exit statuses are remembered for each program.
+const EXIT_HISTORY: usize = 10;
+
+/// Wait before starting again a process that failed to start once.
+const BACKOFF_DELAY: Duration = Duration::from_secs(1);
+/// Longest wait between two start attempts of a process.
+const MAX_BACKOFF_DELAY: Duration = Duration::from_secs(60);
+
+/// How many restarts of a program within `RESTART_STORM_WINDOW` are reported to the webhook
+/// as a `RestartStorm`.
+const RESTART_STORM_RESTARTS: u32 = 5;
+const RESTART_STORM_WINDOW: Duration = Duration::from_secs(60);
+
+/// Every process managed by the daemon, along with the config they were started from.
+pub struct Registry {
+    config: Config,
+    /// Running instances of each program, by program name.
+    processes: HashMap<String, Vec<Process>>,
+    /// Restart and failure bookkeeping of each program, by program name.
+    counters: HashMap<String, Counters>,
+    /// Paths generated for the `AUTO` log files, by program name and stream.
+    auto_logfiles: HashMap<(String, Stream), PathBuf>,
+    /// Instances that recently failed to start, by program name and index.
+    backoffs: HashMap<(String, usize), Backoff>,
+    /// Listening sockets of the FastCGI programs, by program name.
+    sockets: HashMap<String, FcgiListener>,
+    /// State of each process instance when it was last recorded, by instance name.
+    states: HashMap<String, ProcessState>,
+    /// Events waiting to be sent to each event listener pool, by pool name.
+    event_queues: HashMap<String, EventQueue>,
+    /// How many events were sent to the pools so far.
+    events_sent: u64,
+    /// Staged starts carried on in the background, see `defer_start`.
+    deferred_starts: Vec<DeferredStart>,
+    /// When the current restart storm window of each program began, and how many restarts
+    /// it had then, by program name.
+    storm_windows: HashMap<String, (Instant, u32)>,
+}
+
+/// Rest of a staged start, or of a restart, its client stopped waiting for.
+#[derive(Debug)]
+struct DeferredStart {
+    /// Programs asked to stop, that have to be done stopping before anything is started.
+    stopping: Vec<String>,
+    /// Programs started, and not done starting yet.
+    pending: Vec<String>,
+    /// Programs still to start, one priority at a time.
+    tiers: VecDeque<Vec<String>>,
+}
+
+/// Failed start attempts of a process instance, see `startsecs` and `startretries`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Backoff {
+    /// How many times in a row the instance exited before `startsecs`.
+    failures: u32,
+    /// When to start the instance again, while waiting to.
+    retry_at: Option<Instant>,
+    /// Whether `startretries` are exhausted, so the instance is left exited.
+    fatal: bool,
+}
+
+impl Backoff {
+    /// State reported for an instance that is not running.
+    fn state(&self) -> Option<ProcessState> {
+        if self.fatal {
+            Some(ProcessState::Fatal)
+        } else if self.retry_at.is_some() {
+            Some(ProcessState::Backoff)
+        } else {
+            None
+        }
+    }
+}
+
+/// Wait before the next start attempt of an instance that failed to start `failures` times
+/// in a row: `BACKOFF_DELAY`, doubled after each failure, up to `MAX_BACKOFF_DELAY`.
+fn backoff_delay(failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    BACKOFF_DELAY
+        .checked_mul(factor)
+        .map_or(MAX_BACKOFF_DELAY, |delay| delay.min(MAX_BACKOFF_DELAY))
+}
+
+/// Restart and failure bookkeeping of a program, kept across respawns.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Counters {
+    /// How many times the program was started again after its first start.
+    pub restarts: u32,
+    /// How many instances failed to spawn.
+    pub retries: u32,
+    /// How the last instances exited, oldest first.
+    pub exits: Vec<ExitStatus>,
+}
+
+impl fmt::Display for Counters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let exits = self
+            .exits
+            .iter()
+            .map(|status| match (status.code(), status.signal()) {
+                (Some(code), _) => code.to_string(),
+                (None, Some(signal)) => format!("signal {}", signal),
+                (None, None) => "?".to_string(),
+            })
+            .collect::<Vec<String>>();
+        write!(
+            f,
+            "restarts {}, retries {}, exits [{}]",
+            self.restarts,
+            self.retries,
+            exits.join(", ")
+        )
+    }
+}
+
+impl Registry {
+    /// Starts every program of `config` set to `autostart`, in priority order.
+    pub fn new(config: Config) -> Self {
+        let mut registry = Self::empty(config);
+        for name in registry.program_names() {
+            if registry
+                .config
+                .process_config(&name)
+                .is_some_and(|process| process.autostart)
+            {
+                registry.spawn(&name);
+            }
+        }
+        registry
+    }
+
+    /// Takes over `children`, the processes handed over by the daemon that re-executed into
+    /// this one, by program name, then starts the rest of `config` like `new` does. Instances
+    /// `config` no longer has are stopped.
+    pub fn adopt(config: Config, mut children: HashMap<String, Vec<Inherited>>) -> Self {
+        let mut registry = Self::empty(config);
+        for name in registry.program_names() {
+            let autostart = registry
+                .config
+                .process_config(&name)
+                .is_some_and(|process| process.autostart);
+            match children.remove(&name) {
+                Some(children) => registry.adopt_program(&name, children),
+                None if autostart => {
+                    registry.spawn(&name);
+                }
+                None => {}
+            }
+        }
+        for (name, children) in children {
+            for child in &children {
+                registry.stop_orphan(&name, child);
+            }
+        }
+        registry
+    }
+
+    fn empty(config: Config) -> Self {
+        Self {
+            config,
+            processes: HashMap::new(),
+            counters: HashMap::new(),
+            auto_logfiles: HashMap::new(),
+            backoffs: HashMap::new(),
+            sockets: HashMap::new(),
+            states: HashMap::new(),
+            event_queues: HashMap::new(),
+            events_sent: 0,
+            deferred_starts: Vec::new(),
+            storm_windows: HashMap::new(),
+        }
+    }
+
+    /// Takes over `children`, instances of the program called `name` handed over by the
+    /// previous daemon. Its other instances were not running, and are left stopped.
+    fn adopt_program(&mut self, name: &str, mut children: Vec<Inherited>) {
+        let program = match self.resolved_program(name) {
+            Some(program) => program,
+            None => return,
+        };
+        // In the order they were spawned in.
+        children.sort_by_key(|child| child.num);
+        let (children, surplus): (Vec<_>, Vec<_>) = children
+            .into_iter()
+            .partition(|child| child.num < program.numprocs);
+        let mut processes = Vec::new();
+        for child in &children {
+            let ctx = context(&self.config, name, child.num);
+            match render(&program, &ctx)
+                .and_then(|rendered| Process::adopt(child, &rendered, &self.config.taskmasterd))
+            {
+                Ok(process) => {
+                    info!("adopted: {} with pid {}", process.name(), process.id());
+                    processes.push(process);
+                }
+                Err(e) => error!("Could not adopt {}: {}", child.name, e),
+            }
+        }
+        self.processes.insert(name.to_string(), processes);
+        // Beyond `numprocs` now.
+        for child in &surplus {
+            self.stop_orphan(name, child);
+        }
+    }
+
+    /// Stops `child`, a process of the program called `name` handed over by the previous
+    /// daemon that `config` has no place for anymore.
+    fn stop_orphan(&self, name: &str, child: &Inherited) {
+        // Its output has nowhere to go anymore.
+        let program = ProcessConfig {
+            stdout_logfile: Some(LogTarget::None),
+            stderr_logfile: Some(LogTarget::None),
+            ..ProcessConfig::default()
+        };
+        match Process::adopt(child, &program, &self.config.taskmasterd) {
+            Ok(mut process) => {
+                info!("stopping: {}, no longer in the config", child.name);
+                stop(
+                    name,
+                    std::slice::from_mut(&mut process),
+                    &mut Counters::default(),
+                );
+            }
+            Err(e) => error!("Could not stop {}: {}", child.name, e),
+        }
+    }
+
+    /// Hands over the running instances of every program, by program name, for the daemon
+    /// `reexec` runs next to `adopt`. FastCGI programs and event listener pools are stopped
+    /// instead: their sockets and event queues can't be handed over.
+    pub fn hand_over(&mut self) -> HashMap<String, Vec<Inherited>> {
+        let mut children = HashMap::new();
+        for (name, processes) in &mut self.processes {
+            if !self.config.programs.contains_key(name) {
+                stop(
+                    name,
+                    processes,
+                    self.counters.entry(name.clone()).or_default(),
+                );
+                continue;
+            }
+            let inherited = processes
+                .iter_mut()
+                .filter_map(|process| match process.hand_over() {
+                    Ok(inherited) => inherited,
+                    Err(e) => {
+                        error!("Could not hand {} over: {}", process.name(), e);
+                        None
+                    }
+                })
+                .collect::<Vec<Inherited>>();
+            children.insert(name.clone(), inherited);
+        }
+        self.sockets.clear();
+        children
+    }
+
+    /// Switches to `config`: removed programs, FastCGI programs and event listener pools are
+    /// stopped, added ones started and changed ones restarted, if set to `autostart`.
+    /// Unchanged ones are left alone.
+    pub fn update(&mut self, config: Config) -> ConfigDiff {
+        let diff = self.config.diff(&config);
+
+        let to_stop = self
+            .config
+            .start_order(
+                diff.removed
+                    .iter()
+                    .chain(diff.changed.keys())
+                    .filter(|name| self.config.process_config(name).is_some())
+                    .map(String::as_str),
+            )
+            .into_iter()
+            .rev()
+            .map(String::from)
+            .collect::<Vec<String>>();
+        for name in &to_stop {
+            if let Some(processes) = self.processes.get_mut(name) {
+                stop(
+                    name,
+                    processes,
+                    self.counters.entry(name.clone()).or_default(),
+                );
+            }
+            self.record_states(name);
+            self.processes.remove(name);
+            // Bound again from the new settings when started.
+            self.sockets.remove(name);
+        }
+        for name in &diff.removed {
+            self.counters.remove(name);
+            self.storm_windows.remove(name);
+            self.clear_backoffs(name);
+            self.event_queues.remove(name);
+        }
+        for name in diff.changed.keys() {
+            if let Some(counters) = self.counters.get_mut(name) {
+                counters.restarts += 1;
+            }
+        }
+
+        self.config = config;
+        let to_start = self
+            .config
+            .start_order(
+                diff.added
+                    .iter()
+                    .chain(diff.changed.keys())
+                    .filter(|name| {
+                        self.config
+                            .process_config(name)
+                            .is_some_and(|process| process.autostart)
+                    })
+                    .map(String::as_str),
+            )
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<String>>();
+        for name in to_start {
+            self.spawn(&name);
+        }
+        diff
+    }
+
+    /// Starts managing the program or group called `name` in `config`, a freshly read config,
+    /// like `Add` asks. Those set to `autostart` are started.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `config` has no such program or group, if it is managed already, or if a
+    /// program failed to start.
+    pub fn add(&mut self, name: &str, config: &Config) -> Result<(), String> {
+        let members = match config.group_members(name) {
+            Some(members) => members,
+            None if config.programs.contains_key(name) => vec![name],
+            None => return Err("no such process".into()),
+        };
+        if self.config.process_config(name).is_some() || self.config.group.contains_key(name) {
+            return Err("already active".into());
+        }
+        if let Some(member) = members
+            .iter()
+            .find(|member| self.config.process_config(member).is_some())
+        {
+            return Err(format!("`{}` is already active", member));
+        }
+
+        if let Some(group) = config.group.get(name) {
+            self.config.group.insert(name.to_string(), group.clone());
+        }
+        for member in &members {
+            if let Some(program) = config.programs.get(*member) {
+                self.config
+                    .programs
+                    .insert(member.to_string(), program.clone());
+            }
+        }
+        let to_start = members
+            .into_iter()
+            .filter(|member| self.config.programs[*member].process.autostart)
+            .collect::<Vec<&str>>();
+        let failed = to_start
+            .into_iter()
+            .filter(|member| self.spawn(member) == 0)
+            .collect::<Vec<&str>>();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("spawn error ({})", failed.join(", ")))
+        }
+    }
+
+    /// Stops managing the program or group called `name`, like `Remove` asks. Only stopped
+    /// programs may be removed.
+    ///
+    /// # Errors
+    ///
+    /// Errors if there is no such program or group, or if any of its processes still runs.
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        let members = self
+            .resolve(name)
+            .map_err(|_| "no such process".to_string())?;
+        if members
+            .iter()
+            .any(|member| self.processes(member).iter_mut().any(Process::is_running))
+        {
+            return Err("still running, stop it first".into());
+        }
+
+        for member in &members {
+            if let Some(mut processes) = self.processes.remove(member) {
+                // Reaps those that exited on their own.
+                stop(member, &mut processes, &mut Counters::default());
+            }
+            self.counters.remove(member);
+            self.storm_windows.remove(member);
+            self.clear_backoffs(member);
+            self.auto_logfiles
+                .retain(|(program, _), _| program != member);
+            self.sockets.remove(member);
+            self.config.programs.remove(member);
+            self.config.fcgi_programs.remove(member);
+            self.config.eventlisteners.remove(member);
+            self.event_queues.remove(member);
+            for group in self.config.group.values_mut() {
+                group.programs.retain(|program| program != member);
+            }
+        }
+        self.config.group.remove(name);
+        Ok(())
+    }
+
+    /// Stops every managed process, then starts every program of `config` from scratch,
+    /// as if the daemon had been restarted with it.
+    pub fn restart_all(&mut self, config: Config) {
+        self.stop_all();
+        *self = Self::new(config);
+    }
+
+    /// Stops every managed process in reverse priority order, escalating to SIGKILL for those
+    /// that don't exit in time.
+    pub fn stop_all(&mut self) {
+        self.backoffs.clear();
+        self.deferred_starts.clear();
+        let names = self
+            .config
+            .start_order(self.processes.keys().map(String::as_str))
+            .into_iter()
+            .rev()
+            .map(String::from)
+            .collect::<Vec<String>>();
+        for name in names {
+            if let Some(processes) = self.processes.get_mut(&name) {
+                stop(
+                    &name,
+                    processes,
+                    self.counters.entry(name.clone()).or_default(),
+                );
+            }
+            self.record_states(&name);
+        }
+        self.sockets.clear();
+    }
+
+    /// Reaps the processes that exited on their own since the last call, so they don't
+    /// linger as zombies, and records how they exited. Returns how many were reaped.
+    ///
+    /// Those that exited before `startsecs` are started again once their backoff delay
+    /// elapsed, until they fail `startretries` times in a row. Those that ran long enough
+    /// are started again right away if their `autorestart` says so.
+    pub fn reap(&mut self) -> usize {
+        self.reap_at(Instant::now())
+    }
+
+    /// `reap`, as if it were `now`.
+    fn reap_at(&mut self, now: Instant) -> usize {
+        let mut reaped = 0;
+        let mut to_restart = Vec::new();
+        for (name, processes) in &mut self.processes {
+            let counters = self.counters.entry(name.clone()).or_default();
+            let program = match self.config.process_config(name) {
+                Some(program) => program,
+                None => continue,
+            };
+            let startsecs = Duration::from_secs(program.startsecs);
+            for (index, process) in processes.iter_mut().enumerate() {
+                if process.is_stopping() {
+                    // Asked to stop: not to be started again, killed if it takes too long.
+                    match process.poll_stop(now) {
+                        Ok(Some(status)) => {
+                            info!("stopped: {} ({})", process.name(), status);
+                            counters.exits.push(status);
+                            reaped += 1;
+                        }
+                        Ok(None) => {}
+                        Err(e) => error!("Could not stop {} ({}): {}", name, process.id(), e),
+                    }
+                    continue;
+                }
+                let key = (name.clone(), index);
+                let uptime = now.saturating_duration_since(process.started());
+                match process.try_reap() {
+                    Ok(Some(status)) if uptime < startsecs => {
+                        info!("exited: {} ({}, too quickly)", process.name(), status);
+                        counters.exits.push(status);
+                        reaped += 1;
+                        let failures = self.backoffs.get(&key).map_or(0, |b| b.failures) + 1;
+                        let backoff = if failures > program.startretries {
+                            warn!(
+                                "gave up: {} entered FATAL state, too many start retries too quickly",
+                                process.name()
+                            );
+                            if let Some(webhook) = self.config.taskmasterd.webhook() {
+                                webhook.notify(
+                                    webhook::Event::ProcessFatal,
+                                    Some(name),
+                                    "too many start retries too quickly",
+                                );
+                            }
+                            Backoff {
+                                failures,
+                                retry_at: None,
+                                fatal: true,
+                            }
+                        } else {
+                            let delay = backoff_delay(failures);
+                            info!(
+                                "{} entered BACKOFF state, retrying in {}s",
+                                process.name(),
+                                delay.as_secs()
+                            );
+                            Backoff {
+                                failures,
+                                retry_at: Some(now + delay),
+                                fatal: false,
+                            }
+                        };
+                        self.backoffs.insert(key, backoff);
+                    }
+                    Ok(Some(status)) => {
+                        info!("exited: {} ({})", process.name(), status);
+                        counters.exits.push(status);
+                        reaped += 1;
+                        self.backoffs.remove(&key);
+                        if program
+                            .autorestart
+                            .should_restart(status, &program.exitcodes)
+                        {
+                            to_restart.push(key);
+                        }
+                    }
+                    Ok(None) => {
+                        // Up for long enough: the last start succeeded.
+                        if uptime >= startsecs && process.is_running() {
+                            self.backoffs.remove(&key);
+                        }
+                    }
+                    Err(e) => error!("Could not reap {} ({}): {}", name, process.id(), e),
+                }
+            }
+            let excess = counters.exits.len().saturating_sub(EXIT_HISTORY);
+            counters.exits.drain(..excess);
+        }
+        for (key, backoff) in &mut self.backoffs {
+            if backoff.retry_at.is_some_and(|retry_at| retry_at <= now) {
+                backoff.retry_at = None;
+                to_restart.push(key.clone());
+            }
+        }
+        for name in self.program_names() {
+            self.record_states(&name);
+        }
+        for (name, index) in to_restart {
+            self.respawn(&name, index);
+        }
+        self.detect_restart_storms(now);
+        self.advance_deferred_starts();
+        self.send_events();
+        reaped
+    }
+
+    /// Reports to the webhook the programs restarted `RESTART_STORM_RESTARTS` times or more
+    /// since their window began, then starts a new window for them. Windows last
+    /// `RESTART_STORM_WINDOW`.
+    fn detect_restart_storms(&mut self, now: Instant) {
+        for (name, counters) in &self.counters {
+            let (began, restarts) = self.storm_windows.entry(name.clone()).or_insert((now, 0));
+            // Counters that were reset start over too.
+            if now.saturating_duration_since(*began) >= RESTART_STORM_WINDOW
+                || counters.restarts < *restarts
+            {
+                *began = now;
+                *restarts = counters.restarts;
+            } else if counters.restarts - *restarts >= RESTART_STORM_RESTARTS {
+                let message = format!(
+                    "restarted {} times in {}s",
+                    counters.restarts - *restarts,
+                    now.saturating_duration_since(*began).as_secs()
+                );
+                warn!("{}: {}", name, message);
+                if let Some(webhook) = self.config.taskmasterd.webhook() {
+                    webhook.notify(webhook::Event::RestartStorm, Some(name), &message);
+                }
+                *began = now;
+                *restarts = counters.restarts;
+            }
+        }
+    }
+
+    /// Queues a `ProcessStateChange` event for every instance of the program called `name`
+    /// whose state changed since it was last recorded, for the event listener pools
+    /// subscribed to it. Called wherever states change, so that none goes unnoticed between
+    /// two calls to `reap`.
+    fn record_states(&mut self, name: &str) {
+        let group = self.config.group_name(name).to_string();
+        for report in self.status(name) {
+            let from = self
+                .states
+                .insert(report.name.clone(), report.state)
+                .unwrap_or(ProcessState::Stopped);
+            if from != report.state {
+                self.queue_event(Event::ProcessStateChange {
+                    process: report.name,
+                    group: group.clone(),
+                    from,
+                    to: report.state,
+                    pid: report.pid,
+                });
+            }
+        }
+    }
+
+    /// Queues `event` for the event listener pools subscribed to it.
+    fn queue_event(&mut self, event: Event) {
+        for (pool, listener) in &self.config.eventlisteners {
+            if listener.events.iter().any(|events| event.matches(events)) {
+                let queue = self
+                    .event_queues
+                    .entry(pool.clone())
+                    .or_insert_with(|| EventQueue::new(listener.buffer_size));
+                if let Some(dropped) = queue.push(event.clone()) {
+                    warn!("{}: event buffer full, dropped {}", pool, dropped.name());
+                }
+            }
+        }
+    }
+
+    /// Sends what is queued to any listener of each pool that is ready to read it. A
+    /// listener whose stdin took only part of an event gets the rest of it first.
+    fn send_events(&mut self) {
+        for (pool, listener) in &self.config.eventlisteners {
+            let queue = self
+                .event_queues
+                .entry(pool.clone())
+                .or_insert_with(|| EventQueue::new(listener.buffer_size));
+            let processes = self
+                .processes
+                .get_mut(pool)
+                .map_or(&mut [][..], Vec::as_mut_slice);
+            for process in processes.iter_mut() {
+                if let Err(e) = process.flush_event() {
+                    warn!("{}: could not send event: {}", process.name(), e);
+                }
+            }
+            while let Some(event) = queue.front() {
+                let bytes = event.serialize(self.events_sent + 1, pool, queue.sent + 1);
+                if !processes
+                    .iter_mut()
+                    .any(|process| process.write_event(&bytes).is_ok())
+                {
+                    break;
+                }
+                self.events_sent += 1;
+                queue.sent += 1;
+                queue.pop();
+            }
+        }
+    }
+
+    /// Names of the programs `name` refers to, in start order: either the program itself,
+    /// or the members of the group called `name` or `name:*`. `all` refers to every program.
+    pub fn resolve(&self, name: &str) -> Result<Vec<String>, String> {
+        let group = name.strip_suffix(":*").unwrap_or(name);
+        if name == "all" {
+            Ok(self.program_names())
+        } else if group == name && self.config.process_config(name).is_some() {
+            Ok(vec![name.to_string()])
+        } else if let Some(members) = self.config.group_members(group) {
+            Ok(members.into_iter().map(String::from).collect())
+        } else {
+            Err(format!("{}: ERROR (no such process)", name))
+        }
+    }
+
+    /// Starts every instance of the program called `name`.
+    pub fn start(&mut self, name: &str) -> Result<(), String> {
+        if self.config.process_config(name).is_none() {
+            return Err("no such process".into());
+        }
+        let processes = self.processes.entry(name.to_string()).or_default();
+        if processes.iter_mut().any(Process::is_running) {
+            return Err("already started".into());
+        }
+
+        // Reap the instances that exited on their own before replacing them.
+        let counters = self.counters.entry(name.to_string()).or_default();
+        if !processes.is_empty() {
+            counters.restarts += 1;
+        }
+        stop(name, processes, counters);
+        if self.spawn(name) == 0 {
+            Err("spawn error".into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the instances of the program called `name` are done starting: `Ok` once each
+    /// of them stayed up for `startsecs`, an error once one of them went `FATAL`, and `None`
+    /// while some are still within `startsecs` or backing off.
+    pub fn start_result(&self, name: &str) -> Option<Result<(), String>> {
+        let startsecs = match self.config.process_config(name) {
+            Some(program) => Duration::from_secs(program.startsecs),
+            None => return Some(Err("no such process".into())),
+        };
+        let processes = self.processes.get(name).map_or(&[][..], Vec::as_slice);
+        let mut settled = true;
+        for (index, process) in processes.iter().enumerate() {
+            match self
+                .backoffs
+                .get(&(name.to_string(), index))
+                .and_then(Backoff::state)
+            {
+                Some(ProcessState::Fatal) => return Some(Err("spawn error".into())),
+                Some(_) => settled = false,
+                None => settled &= process.started().elapsed() >= startsecs,
+            }
+        }
+        if settled {
+            Some(Ok(()))
+        } else {
+            None
+        }
+    }
+
+    /// Carries on with a staged start in the background, from `reap`: once each program of
+    /// `pending` is done starting, see `start_result`, those of the next of `tiers` are
+    /// started.
+    pub fn defer_start(&mut self, pending: Vec<String>, tiers: Vec<Vec<String>>) {
+        self.deferred_starts.push(DeferredStart {
+            stopping: Vec::new(),
+            pending,
+            tiers: tiers.into(),
+        });
+    }
+
+    /// Starts the next priority of the deferred starts whose current one is done starting.
+    fn advance_deferred_starts(&mut self) {
+        let mut deferred = std::mem::take(&mut self.deferred_starts);
+        for start in &mut deferred {
+            if start.stopping.iter().any(|name| self.is_stopping(name)) {
+                continue;
+            }
+            start.stopping.clear();
+            while start
+                .pending
+                .iter()
+                .all(|name| self.start_result(name).is_some())
+            {
+                let tier = match start.tiers.pop_front() {
+                    Some(tier) => tier,
+                    None => {
+                        start.pending.clear();
+                        break;
+                    }
+                };
+                start.pending = tier
+                    .into_iter()
+                    .filter(|name| match self.start(name) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            error!("Could not start {}: {}", name, e);
+                            false
+                        }
+                    })
+                    .collect();
+            }
+        }
+        deferred.retain(|start| !start.pending.is_empty() || !start.tiers.is_empty());
+        self.deferred_starts = deferred;
+    }
+
+    /// Asks every instance of the program called `name` to stop with its `stopsignal`,
+    /// without waiting for them to: `reap` reaps them, and kills those that are still running
+    /// after `stopwaitsecs`. See `is_stopping`.
+    pub fn stop(&mut self, name: &str) -> Result<(), String> {
+        if self.config.process_config(name).is_none() {
+            return Err("no such process".into());
+        }
+        let backing_off = self
+            .backoffs
+            .iter()
+            .any(|((program, _), backoff)| program == name && backoff.retry_at.is_some());
+        self.clear_backoffs(name);
+        let processes = self
+            .processes
+            .get_mut(name)
+            .map_or(&mut [][..], Vec::as_mut_slice);
+        if !processes.iter_mut().any(Process::is_running) && !backing_off {
+            return Err("not running".into());
+        }
+        self.request_stop(name);
+        Ok(())
+    }
+
+    /// Asks every instance of the program called `name` to stop, and records their states.
+    fn request_stop(&mut self, name: &str) {
+        for process in self.processes(name) {
+            if let Err(e) = process.request_stop() {
+                error!("Could not stop {} ({}): {}", name, process.id(), e);
+            }
+        }
+        self.record_states(name);
+    }
+
+    /// Whether an instance of the program called `name` was asked to stop and wasn't reaped
+    /// yet.
+    pub fn is_stopping(&self, name: &str) -> bool {
+        self.processes
+            .get(name)
+            .is_some_and(|processes| processes.iter().any(Process::is_stopping))
+    }
+
+    /// Asks every instance of the program or group called `name` to stop, like `Restart`
+    /// asks, in reverse start order. Returns its programs, in start order, for
+    /// `finish_restart` to start again once they stopped.
+    pub fn begin_restart(&mut self, name: &str) -> Result<Vec<String>, String> {
+        let programs = self
+            .resolve(name)
+            .map_err(|_| "no such process".to_string())?;
+        for program in programs.iter().rev() {
+            self.clear_backoffs(program);
+            self.request_stop(program);
+        }
+        Ok(programs)
+    }
+
+    /// Starts `programs` from `begin_restart` again, if none is still stopping. Otherwise
+    /// `reap` starts them once they all stopped, and this returns `false`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if one of them failed to start.
+    pub fn finish_restart(&mut self, programs: Vec<String>) -> Result<bool, String> {
+        if programs.iter().any(|program| self.is_stopping(program)) {
+            self.deferred_starts.push(DeferredStart {
+                stopping: programs.clone(),
+                pending: Vec::new(),
+                tiers: vec![programs].into(),
+            });
+            return Ok(false);
+        }
+        let failed = programs
+            .iter()
+            .filter(|program| self.start(program).is_err())
+            .map(String::as_str)
+            .collect::<Vec<&str>>();
+        if failed.is_empty() {
+            Ok(true)
+        } else {
+            Err(format!("spawn error ({})", failed.join(", ")))
+        }
+    }
+
+    /// Sends `signal` to every running instance of the program called `name`.
+    pub fn signal(&mut self, name: &str, signal: Signal) -> Result<(), String> {
+        if self.config.process_config(name).is_none() {
+            return Err("no such process".into());
+        }
+        let running = self
+            .processes(name)
+            .iter_mut()
+            .filter_map(|process| {
+                if process.is_running() {
+                    Some(process)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<&mut Process>>();
+        if running.is_empty() {
+            return Err("not running".into());
+        }
+        running
+            .iter()
+            .try_for_each(|process| process.send_signal(signal))
+    }
+
+    /// Clears the restart and failure bookkeeping of the program called `name`, and returns
+    /// what it was. Its processes are left alone.
+    pub fn reset_counters(&mut self, name: &str) -> Result<Counters, String> {
+        if self.config.process_config(name).is_none() {
+            return Err("no such process".into());
+        }
+        Ok(self
+            .counters
+            .get_mut(name)
+            .map(std::mem::take)
+            .unwrap_or_default())
+    }
+
+    /// State of every instance of the program called `name`.
+    pub fn status(&mut self, name: &str) -> Vec<StatusReport> {
+        let processes = self
+            .processes
+            .get_mut(name)
+            .map_or(&mut [][..], Vec::as_mut_slice);
+        if processes.is_empty() {
+            return vec![StatusReport {
+                name: name.to_string(),
+                state: ProcessState::Stopped,
+                pid: None,
+                uptime: None,
+                exit: None,
+            }];
+        }
+
+        let backoffs = &self.backoffs;
+        processes
+            .iter_mut()
+            .enumerate()
+            .map(|(index, process)| {
+                let state = match process.state() {
+                    state @ (ProcessState::Running | ProcessState::Stopping) => state,
+                    state => backoffs
+                        .get(&(name.to_string(), index))
+                        .and_then(Backoff::state)
+                        .unwrap_or(state),
+                };
+                let running = matches!(state, ProcessState::Running | ProcessState::Stopping);
+                StatusReport {
+                    name: process.name().to_string(),
+                    state,
+                    pid: if running { Some(process.id()) } else { None },
+                    uptime: if running {
+                        Some(process.started().elapsed())
+                    } else {
+                        None
+                    },
+                    exit: process.exit_info(),
+                }
+            })
+            .collect()
+    }
+
+    /// PID of every instance of the program called `name`, or why it has none.
+    pub fn pids(&mut self, name: &str) -> Vec<(String, Result<u32, String>)> {
+        let processes = self.processes(name);
+        if processes.is_empty() {
+            return vec![(name.to_string(), Err("not running".to_string()))];
+        }
+
+        processes
+            .iter_mut()
+            .map(|process| {
+                let pid = if process.is_running() {
+                    Ok(process.id())
+                } else {
+                    Err("not running".to_string())
+                };
+                (process.name().to_string(), pid)
+            })
+            .collect()
+    }
+
+    /// Describes what stopping the program called `name` does, signal-wise.
+    pub fn stop_behavior(&self, name: &str) -> Result<String, String> {
+        self.config
+            .process_config(name)
+            .map(|process| format!("{}: {}", name, process.stop_behavior()))
+            .ok_or_else(|| "no such process".into())
+    }
+
+    /// Log files the `stream` of the instances of the program called `name` are written to,
+    /// rendered the way spawning them does. Instances sharing a file list it once.
+    pub fn logfile(&self, name: &str, stream: Stream) -> Result<Vec<PathBuf>, String> {
+        let process = self
+            .config
+            .process_config(name)
+            .ok_or_else(|| "no such process".to_string())?;
+        match process.logfile(stream) {
+            Some(LogTarget::Path(path)) => {
+                let mut paths = (0..process.numprocs)
+                    .map(|num| {
+                        let ctx = context(&self.config, name, num);
+                        template::render(&path.to_string_lossy(), &ctx).map(PathBuf::from)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                paths.dedup();
+                Ok(paths)
+            }
+            Some(LogTarget::Auto) => self
+                .auto_logfiles
+                .get(&(name.to_string(), stream))
+                .map(|path| vec![path.clone()])
+                .ok_or_else(|| "no log file yet".to_string()),
+            Some(LogTarget::None) | None => Err("no log file".to_string()),
+        }
+    }
+
+    /// Log files the stdout and stderr of the program called `name` are written to, leaving
+    /// out those it has none of.
+    ///
+    /// # Errors
+    ///
+    /// Errors if it has neither.
+    pub fn logfiles(&self, name: &str) -> Result<Vec<PathBuf>, String> {
+        let mut paths = match (
+            self.logfile(name, Stream::Stdout),
+            self.logfile(name, Stream::Stderr),
+        ) {
+            (Err(e), Err(_)) => return Err(e),
+            (stdout, stderr) => stdout
+                .into_iter()
+                .chain(stderr)
+                .flatten()
+                .collect::<Vec<_>>(),
+        };
+        // Both streams may go to the same file.
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    /// Sends `bytes` to the stdin of every running instance of the program called `name`,
+    /// see `Process::send_input`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if none is running, or if writing to one of them failed.
+    pub fn send_input(&mut self, name: &str, bytes: &[u8]) -> Result<(), String> {
+        let mut running = self
+            .processes(name)
+            .iter_mut()
+            .filter_map(|process| {
+                if process.is_running() {
+                    Some(process)
+                } else {
+                    None
+                }
+            })
+            .peekable();
+        if running.peek().is_none() {
+            return Err("not running".into());
+        }
+        running.try_for_each(|process| {
+            process
+                .send_input(bytes)
+                .map_err(|e| format!("{}: {}", process.name(), e))
+        })
+    }
+
+    /// Empties the stdout and stderr log files of the program called `name`, and removes
+    /// their rotated backups.
+    pub fn clear_logs(&self, name: &str) -> Result<(), String> {
+        if self.config.process_config(name).is_none() {
+            return Err("no such process".into());
+        }
+        for &stream in &[Stream::Stdout, Stream::Stderr] {
+            // Streams without a log file, or whose `AUTO` one wasn't created yet, have
+            // nothing to clear.
+            for path in self.logfile(name, stream).unwrap_or_default() {
+                logfile::clear(&path)
+                    .map_err(|e| format!("could not clear {}: {:?}", path.display(), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Config the processes were started from.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Names of every configured program, in start order.
+    pub fn program_names(&self) -> Vec<String> {
+        self.config
+            .start_order(self.config.process_names())
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    /// Instances of the program called `name`.
+    pub fn processes(&mut self, name: &str) -> &mut [Process] {
+        self.processes
+            .get_mut(name)
+            .map_or(&mut [], Vec::as_mut_slice)
+    }
+
+    /// Replaces the instances of the program called `name` with fresh ones, counting those
+    /// that failed to spawn. Returns how many were spawned.
+    fn spawn(&mut self, name: &str) -> usize {
+        let program = match self.resolved_program(name) {
+            Some(program) => program,
+            None => return 0,
+        };
+        self.clear_backoffs(name);
+        let processes = match self.open_socket(name) {
+            Ok(()) => spawn(&self.config, name, &program, self.sockets.get(name)),
+            Err(e) => {
+                error!("Could not start {}: {}", name, e);
+                Vec::new()
+            }
+        };
+        let spawned = processes.len();
+        if let Some(process) = self.config.process_config(name) {
+            let failed = usize::from(process.numprocs).saturating_sub(spawned);
+            let counters = self.counters.entry(name.to_string()).or_default();
+            counters.retries += u32::try_from(failed).unwrap_or(u32::MAX);
+        }
+        self.processes.insert(name.to_string(), processes);
+        self.record_states(name);
+        spawned
+    }
+
+    /// Starts the `index`-th instance of the program called `name` again, in place.
+    fn respawn(&mut self, name: &str, index: usize) {
+        let program = match self.resolved_program(name) {
+            Some(program) => program,
+            None => return,
+        };
+        let num = match self
+            .processes
+            .get(name)
+            .and_then(|processes| processes.get(index))
+        {
+            Some(process) => process.num(),
+            None => return,
+        };
+        let counters = self.counters.entry(name.to_string()).or_default();
+        counters.restarts += 1;
+        let socket = self.sockets.get(name);
+        match spawn_instance(&self.config, name, &program, num, socket) {
+            Some(process) => {
+                if let Some(slot) = self
+                    .processes
+                    .get_mut(name)
+                    .and_then(|processes| processes.get_mut(index))
+                {
+                    *slot = process;
+                }
+                self.record_states(name);
+            }
+            None => counters.retries += 1,
+        }
+    }
+
+    /// Binds the listening socket of the FastCGI program called `name`, unless it already is.
+    /// Other programs have none.
+    fn open_socket(&mut self, name: &str) -> Result<(), String> {
+        if let Some(program) = self.config.fcgi_programs.get(name) {
+            if !self.sockets.contains_key(name) {
+                let socket = FcgiListener::open(program)?;
+                self.sockets.insert(name.to_string(), socket);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the failed start attempts of every instance of the program called `name`.
+    fn clear_backoffs(&mut self, name: &str) {
+        self.backoffs.retain(|(program, _), _| program != name);
+    }
+
+    /// Settings of the program called `name`, with its `AUTO` log files resolved.
+    fn resolved_program(&mut self, name: &str) -> Option<ProcessConfig> {
+        let mut program = self.config.process_config(name)?.clone();
+        program.stdout_logfile = self.resolve_logfile(name, Stream::Stdout);
+        program.stderr_logfile = self.resolve_logfile(name, Stream::Stderr);
+        Some(program)
+    }
+
+    /// Log file the `stream` of the program called `name` should be written to, generating
+    /// the path of an `AUTO` one the first time.
+    fn resolve_logfile(&mut self, name: &str, stream: Stream) -> Option<LogTarget> {
+        let target = self.config.process_config(name)?.logfile(stream)?;
+        if *target != LogTarget::Auto {
+            return Some(target.clone());
+        }
+        let daemon = &self.config.taskmasterd;
+        let path = self
+            .auto_logfiles
+            .entry((name.to_string(), stream))
+            .or_insert_with(|| {
+                logfile::auto_logfile_path(&daemon.childlogdir, name, stream, &daemon.identifier)
+            });
+        Some(LogTarget::Path(path.clone()))
+    }
+}
+
+/// Copy of `program` with the expansions of its command and log file paths substituted.
+fn render(program: &ProcessConfig, ctx: &Context) -> Result<ProcessConfig, String> {
+    let render_logfile = |target: &Option<LogTarget>| -> Result<_, String> {
+        match target {
+            Some(LogTarget::Path(path)) => {
+                let path = template::render(&path.to_string_lossy(), ctx)?;
+                Ok(Some(LogTarget::Path(path.into())))
+            }
+            other => Ok(other.clone()),
+        }
+    };
+    Ok(ProcessConfig {
+        command: template::render(&program.command, ctx)?,
+        stdout_logfile: render_logfile(&program.stdout_logfile)?,
+        stderr_logfile: render_logfile(&program.stderr_logfile)?,
+        ..program.clone()
+    })
+}
+
+/// Expansions available to the templates of the `num`-th instance of the program called
+/// `name` in `config`.
+fn context(config: &Config, name: &str, num: u16) -> Context {
+    Context {
+        program_name: name.to_string(),
+        process_num: num,
+        group_name: config.group_name(name).to_string(),
+        host_node_name: template::host_node_name(),
+        here: config.here.clone(),
+    }
+}
+
+/// Renders the templates of every instance of every program of `config` without spawning
+/// anything, returning what fails to render, by program name.
+pub fn check_templates(config: &Config) -> Vec<String> {
+    let mut names = config.process_names().collect::<Vec<&str>>();
+    names.sort_unstable();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let program = config.process_config(name)?;
+            // Every instance fails alike, so the first error is enough.
+            (0..program.numprocs).find_map(|num| {
+                let ctx = context(config, name, num);
+                config
+                    .environment(name)
+                    .values()
+                    .try_for_each(|value| template::render(value, &ctx).map(drop))
+                    .and_then(|_| template::render_process_name(&program.process_name, &ctx))
+                    .and_then(|_| render(program, &ctx))
+                    .err()
+                    .map(|e| format!("{}: {}", name, e))
+            })
+        })
+        .collect()
+}
+
+/// Spawns every instance of `program`, called `name` in `config`, naming each of them after
+/// the program's `process_name` template. They all get `socket` as their stdin, if any.
+fn spawn(
+    config: &Config,
+    name: &str,
+    program: &ProcessConfig,
+    socket: Option<&FcgiListener>,
+) -> Vec<Process> {
+    (0..program.numprocs)
+        .filter_map(|num| spawn_instance(config, name, program, num, socket))
+        .collect()
+}
+
+/// Spawns the `num`-th instance of `program`, called `name` in `config`, with `socket` as
+/// its stdin if any. Failures are logged.
+fn spawn_instance(
+    config: &Config,
+    name: &str,
+    program: &ProcessConfig,
+    num: u16,
+    socket: Option<&FcgiListener>,
+) -> Option<Process> {
+    let ctx = context(config, name, num);
+    let spawned = config
+        .environment(name)
+        .iter()
+        .map(|(key, value)| Ok((key.to_string(), template::render(value, &ctx)?)))
+        .collect::<Result<HashMap<String, String>, String>>()
+        .and_then(|environment| {
+            let process_name = template::render_process_name(&program.process_name, &ctx)?;
+            let stdin = match socket {
+                Some(socket) => Some(Stdio::from(socket.try_clone_fd()?)),
+                // Fed with the events the pool subscribed to.
+                None if config.eventlisteners.contains_key(name) => Some(Stdio::piped()),
+                None => None,
+            };
+            Process::spawn(
+                process_name,
+                &render(program, &ctx)?,
+                num,
+                &environment,
+                &config.taskmasterd,
+                stdin,
+            )
+        });
+    match spawned {
+        Ok(process) => {
+            info!("spawned: {} with pid {}", process.name(), process.id());
+            Some(process)
+        }
+        Err(e) => {
+            error!("Could not start {}: {}", name, e);
+            None
+        }
+    }
+}
+
+/// Stops every instance of a program, recording how they exited in `counters`.
+fn stop(name: &str, processes: &mut [Process], counters: &mut Counters) {
+    for process in processes {
+        let already_stopped = process.exit_status().is_some();
+        match process.stop() {
+            Ok(status) if !already_stopped => {
+                info!("stopped: {} ({})", process.name(), status);
+                counters.exits.push(status);
+            }
+            Ok(_) => {}
+            Err(e) => error!("Could not stop {} ({}): {}", name, process.id(), e),
+        }
+    }
+    let excess = counters.exits.len().saturating_sub(EXIT_HISTORY);
+    counters.exits.drain(..excess);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Reaps `registry` until some process exits.
+    fn wait_for_exit(registry: &mut Registry) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while registry.reap() == 0 {
+            assert!(Instant::now() < deadline, "nothing exited");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn backs_off_then_gives_up() {
+        assert_eq!(
+            (1..=8).map(backoff_delay).collect::<Vec<_>>(),
+            [1, 2, 4, 8, 16, 32, 60, 60].map(Duration::from_secs)
+        );
+
+        let config: Config = "
+programs:
+  flapping:
+    command: 'false'
+    startsecs: 10
+    startretries: 2
+"
+        .parse()
+        .unwrap();
+        let mut registry = Registry::new(config);
+        let key = ("flapping".to_string(), 0);
+
+        let mut delays = Vec::new();
+        for _ in 0..2 {
+            wait_for_exit(&mut registry);
+            assert_eq!(registry.status("flapping")[0].state, ProcessState::Backoff);
+            let retry_at = registry.backoffs[&key].retry_at.unwrap();
+            delays.push(retry_at.saturating_duration_since(Instant::now()));
+
+            // Not started again before the delay elapsed.
+            let pid = registry.processes("flapping")[0].id();
+            registry.reap();
+            assert_eq!(registry.processes("flapping")[0].id(), pid);
+            registry.reap_at(retry_at);
+            assert_ne!(registry.processes("flapping")[0].id(), pid);
+        }
+        assert!(delays[0] < delays[1], "{:?}", delays);
+
+        wait_for_exit(&mut registry);
+        assert_eq!(registry.status("flapping")[0].state, ProcessState::Fatal);
+        let pid = registry.processes("flapping")[0].id();
+        registry.reap_at(Instant::now() + MAX_BACKOFF_DELAY);
+        assert_eq!(registry.processes("flapping")[0].id(), pid);
+        assert_eq!(registry.reset_counters("flapping").unwrap().restarts, 2);
+
+        // Starting it by hand gives it a fresh set of retries.
+        registry.start("flapping").unwrap();
+        assert!(registry.backoffs.is_empty());
+        registry.stop_all();
+    }
+
+    #[test]
+    fn children_see_daemon_environment() {
+        let dir = crate::temp_dir("daemon-env");
+        let config: Config = format!(
+            "
+taskmasterd:
+  environment:
+    FOO: base
+    RATE: 100%%
+programs:
+  overriding:
+    command: /bin/sh -c env>overriding.env
+    directory: {dir}
+    startsecs: 0
+    environment:
+      FOO: override
+  plain:
+    command: /bin/sh -c env>plain.env
+    directory: {dir}
+    startsecs: 0
+eventlisteners:
+  listener:
+    command: /bin/sh -c env>listener.env
+    directory: {dir}
+    startsecs: 0
+    events: [PROCESS_STATE]
+",
+            dir = dir.display()
+        )
+        .parse()
+        .unwrap();
+        let mut registry = Registry::new(config);
+
+        let environment = |name: &str| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                let env = std::fs::read_to_string(dir.join(name)).unwrap_or_default();
+                if env.contains("RATE=") {
+                    return env;
+                }
+                assert!(Instant::now() < deadline, "{} never ran", name);
+                thread::sleep(Duration::from_millis(50));
+            }
+        };
+        let overriding = environment("overriding.env");
+        assert!(overriding.contains("FOO=override\n"), "{}", overriding);
+        assert!(overriding.contains("RATE=100%\n"), "{}", overriding);
+        for name in &["plain.env", "listener.env"] {
+            let env = environment(name);
+            assert!(env.contains("FOO=base\n"), "{}", env);
+        }
+        registry.stop_all();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sends_state_changes() {
+        let dir = crate::temp_dir("events");
+        let config: Config = format!(
+            "
+programs:
+  web:
+    command: sleep 100
+eventlisteners:
+  listener:
+    command: /bin/sh -c cat>events
+    directory: {}
+    events: [PROCESS_STATE]
+  queued:
+    command: 'true'
+    autostart: false
+    events: [PROCESS_STATE_RUNNING]
+    buffer_size: 1
+",
+            dir.display()
+        )
+        .parse()
+        .unwrap();
+        let mut registry = Registry::new(config);
+        registry.reap();
+
+        // Nothing reads the events of `queued`: only the last one is kept.
+        let queue = registry.event_queues.get_mut("queued").unwrap();
+        assert!(
+            matches!(
+                queue.pop(),
+                Some(Event::ProcessStateChange { process, to: ProcessState::Running, .. })
+                    if process == "web"
+            ),
+            "{:?}",
+            queue
+        );
+        assert_eq!(queue.pop(), None);
+        assert_eq!(registry.event_queues["listener"].front(), None);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let events = std::fs::read_to_string(dir.join("events")).unwrap_or_default();
+            if events.contains("eventname:PROCESS_STATE_RUNNING len:")
+                && events.contains("processname:web groupname:web from_state:STOPPED pid:")
+            {
+                break;
+            }
+            assert!(Instant::now() < deadline, "{}", events);
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        // Known states don't change again.
+        registry.reap();
+        assert_eq!(registry.event_queues["queued"].front(), None);
+
+        // Neither does a restart between two reaps go unnoticed.
+        let programs = registry.begin_restart("web").unwrap();
+        while registry.is_stopping("web") {
+            registry.reap();
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(registry.finish_restart(programs), Ok(true));
+        registry.reap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let events = std::fs::read_to_string(dir.join("events")).unwrap_or_default();
+            if events.contains("eventname:PROCESS_STATE_STOPPED len:")
+                && events.contains("processname:web groupname:web from_state:RUNNING")
+                && events
+                    .matches("processname:web groupname:web from_state:STOPPED")
+                    .count()
+                    == 2
+            {
+                break;
+            }
+            assert!(Instant::now() < deadline, "{}", events);
+            thread::sleep(Duration::from_millis(50));
+        }
+        registry.stop_all();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn renders_logfiles_per_instance() {
+        let dir = crate::temp_dir("logs");
+        let config: Config = format!(
+            "
+programs:
+  echo:
+    command: echo hello
+    numprocs: 2
+    process_name: echo_%(process_num)s
+    startsecs: 0
+    autorestart: never
+    stdout_logfile: {}/x-%(process_num)s.log
+",
+            dir.display()
+        )
+        .parse()
+        .unwrap();
+        let mut registry = Registry::new(config);
+
+        let paths = registry.logfile("echo", Stream::Stdout).unwrap();
+        assert_eq!(paths, [dir.join("x-0.log"), dir.join("x-1.log")]);
+        // Written by the forwarders, in the background.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while paths
+            .iter()
+            .any(|path| std::fs::read_to_string(path).unwrap_or_default() != "hello\n")
+        {
+            assert!(Instant::now() < deadline, "{:?} not written", paths);
+            thread::sleep(Duration::from_millis(50));
+        }
+        wait_for_exit(&mut registry);
+        assert_eq!(registry.logfiles("echo").unwrap(), paths);
+        registry.clear_logs("echo").unwrap();
+        assert!(paths
+            .iter()
+            .all(|path| std::fs::metadata(path).unwrap().len() == 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fcgi_processes_share_socket() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config: Config = format!(
+            "
+fcgi_programs:
+  php:
+    command: sleep 100
+    numprocs: 2
+    process_name: php_%(process_num)s
+    socket: tcp://127.0.0.1:{}
+",
+            port
+        )
+        .parse()
+        .unwrap();
+        let mut registry = Registry::new(config);
+
+        let stdins = registry
+            .processes("php")
+            .iter()
+            .map(|process| std::fs::read_link(format!("/proc/{}/fd/0", process.id())).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(stdins.len(), 2);
+        assert!(
+            stdins[0].to_string_lossy().starts_with("socket:"),
+            "{:?}",
+            stdins
+        );
+        assert_eq!(stdins[0], stdins[1]);
+        std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        assert_eq!(registry.status("php")[1].state, ProcessState::Running);
+
+        registry.stop_all();
+        assert!(std::net::TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn reports_restart_storms() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config: Config = format!(
+            "
+taskmasterd:
+  webhook_url: http://{}/hook
+  webhook_events: [restart_storm, process_fatal]
+programs:
+  flappy:
+    command: 'true'
+    startsecs: 0
+    autorestart: always
+  missing:
+    command: /no/such/command
+",
+            listener.local_addr().unwrap()
+        )
+        .parse()
+        .unwrap();
+        let (sender, requests) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let bytes = stream.read(&mut buf).unwrap();
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .unwrap();
+                let _ = sender.send(String::from_utf8_lossy(&buf[..bytes]).into_owned());
+            }
+        });
+
+        // Failing to spawn isn't fatal, giving up on starts is.
+        let mut registry = Registry::new(config);
+        for _ in 0..RESTART_STORM_RESTARTS {
+            wait_for_exit(&mut registry);
+        }
+        let request = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(
+            request.contains(r#""event":"restart_storm","process":"flappy""#),
+            "{}",
+            request
+        );
+        registry.stop_all();
+        assert!(requests.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn removes_event_listener_pool() {
+        let config: Config = "
+programs:
+  web:
+    command: sleep 100
+eventlisteners:
+  mem:
+    command: sleep 100
+    events: [PROCESS_STATE]
+"
+        .parse()
+        .unwrap();
+        let mut registry = Registry::new(config);
+        registry.processes("mem")[0].stop().unwrap();
+
+        registry.remove("mem").unwrap();
+        assert!(!registry.config().eventlisteners.contains_key("mem"));
+        assert!(!registry.event_queues.contains_key("mem"));
+        // Not queued for anymore.
+        registry.stop("web").unwrap();
+        wait_for_exit(&mut registry);
+        assert!(!registry.event_queues.contains_key("mem"));
+        assert_eq!(
+            registry.resolve("mem"),
+            Err("mem: ERROR (no such process)".into())
+        );
+        registry.stop_all();
+    }
+
+    #[test]
+    fn updates_fcgi_programs_and_listeners() {
+        let port = || {
+            std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port()
+        };
+        let (old_port, new_port) = (port(), port());
+        let config = |port: u16, listener: &str| -> Config {
+            format!(
+                "
+fcgi_programs:
+  php:
+    command: sleep 100
+    socket: tcp://127.0.0.1:{}
+{}",
+                port, listener
+            )
+            .parse()
+            .unwrap()
+        };
+        let listener = "
+eventlisteners:
+  mem:
+    command: sleep 100
+    events: [PROCESS_STATE]
+";
+        let mut registry = Registry::new(config(old_port, listener));
+        let php = registry.processes("php")[0].id();
+        assert!(registry.processes("mem")[0].is_running());
+
+        let diff = registry.update(config(new_port, ""));
+        assert!(diff.changed.contains_key("php"));
+        assert!(diff.removed.contains("mem"));
+        assert_ne!(registry.processes("php")[0].id(), php);
+        assert!(registry.processes("php")[0].is_running());
+        assert!(std::net::TcpStream::connect(("127.0.0.1", old_port)).is_err());
+        std::net::TcpStream::connect(("127.0.0.1", new_port)).unwrap();
+        assert!(registry.processes("mem").is_empty());
+        assert!(!registry.event_queues.contains_key("mem"));
+
+        let diff = registry.update(config(new_port, listener));
+        assert!(diff.added.contains("mem"));
+        assert!(registry.processes("mem")[0].is_running());
+        registry.stop_all();
+    }
+}
@@ -0,0 +1,350 @@
+use crate::logger;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// Answer of the daemon to a `Command`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    /// The command succeeded.
+    Ok(String),
+    /// The command failed, at least partly.
+    Error(String),
+    /// State of the processes asked about, or why a name asked about designates none, in the
+    /// order asked. Then how many are in each state, when all of them were asked about.
+    Status(
+        Vec<Result<StatusReport, String>>,
+        Vec<(ProcessState, usize)>,
+    ),
+    /// PID of each process asked about, or why it has none.
+    Pid(HashMap<String, Result<u32, String>>),
+}
+
+/// State of a process instance.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ProcessState {
+    Running,
+    /// Asked to stop, and given `stopwaitsecs` to before being killed.
+    Stopping,
+    /// Stopped on request, or never started.
+    Stopped,
+    /// Exited on its own.
+    Exited,
+    /// Exited too quickly after starting, and waiting to be started again.
+    Backoff,
+    /// Exited too quickly after starting too many times in a row, and given up on.
+    Fatal,
+}
+
+impl ProcessState {
+    /// Every state, in the order the status summary lists them.
+    const ALL: [Self; 6] = [
+        Self::Running,
+        Self::Stopping,
+        Self::Backoff,
+        Self::Fatal,
+        Self::Exited,
+        Self::Stopped,
+    ];
+}
+
+impl fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_ascii_uppercase())
+    }
+}
+
+/// State of one process instance, as reported by `status`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub name: String,
+    pub state: ProcessState,
+    /// Only set while running.
+    pub pid: Option<u32>,
+    /// How long the process has been running. Only set while running.
+    pub uptime: Option<Duration>,
+    /// How the last process exited, if one did.
+    pub exit: Option<ExitInfo>,
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.state, self.pid, &self.exit) {
+            (ProcessState::Running, Some(pid), _) => {
+                write!(f, "{:<24} RUNNING   pid {}", self.name, pid)?;
+                match self.uptime {
+                    Some(uptime) => write!(f, ", uptime {}", format_uptime(uptime)),
+                    None => Ok(()),
+                }
+            }
+            (ProcessState::Stopping, Some(pid), _) => {
+                write!(f, "{:<24} STOPPING  pid {}", self.name, pid)
+            }
+            (ProcessState::Exited, _, Some(exit)) => write!(
+                f,
+                "{:<24} EXITED    {} ({})",
+                self.name,
+                logger::short_date(exit.at),
+                exit
+            ),
+            (ProcessState::Stopped, _, Some(exit)) => write!(
+                f,
+                "{:<24} STOPPED   {}",
+                self.name,
+                logger::short_date(exit.at)
+            ),
+            (state, _, _) => write!(f, "{:<24} {}", self.name, state),
+        }
+    }
+}
+
+/// How many of `reports` are in each state, leaving out the states none of them are in.
+pub fn count_states(reports: &[StatusReport]) -> Vec<(ProcessState, usize)> {
+    ProcessState::ALL
+        .iter()
+        .map(|state| {
+            let count = reports
+                .iter()
+                .filter(|report| report.state == *state)
+                .count();
+            (*state, count)
+        })
+        .filter(|&(_, count)| count > 0)
+        .collect()
+}
+
+/// How and when a process exited.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ExitInfo {
+    /// Exit code, unless it was killed by a signal.
+    pub code: Option<i32>,
+    /// Signal that killed it, if any.
+    pub signal: Option<i32>,
+    /// When the daemon noticed it exited.
+    pub at: SystemTime,
+}
+
+impl fmt::Display for ExitInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exit code {}", code),
+            (None, Some(signal)) => write!(f, "terminated by signal {}", signal),
+            (None, None) => write!(f, "unknown exit status"),
+        }
+    }
+}
+
+/// Formats `uptime` like `1:23:45`, with the days in front once there are some.
+pub fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (days, hours, minutes, seconds) =
+        (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    let time = format!("{}:{:02}:{:02}", hours, minutes, seconds);
+    match days {
+        0 => time,
+        1 => format!("1 day, {}", time),
+        days => format!("{} days, {}", days, time),
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ok(message) => write!(f, "{}", message),
+            Self::Error(message) => write!(f, "ERROR: {}", message),
+            Self::Status(reports, counts) => {
+                let mut lines = reports
+                    .iter()
+                    .map(|report| match report {
+                        Ok(report) => report.to_string(),
+                        Err(e) => e.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                if !counts.is_empty() {
+                    let summary = counts
+                        .iter()
+                        .map(|(state, count)| {
+                            format!("{} {}", count, state.to_string().to_ascii_lowercase())
+                        })
+                        .collect::<Vec<_>>();
+                    lines.push(summary.join(", "));
+                }
+                write!(f, "{}", lines.join("\n"))
+            }
+            Self::Pid(pids) => {
+                let mut lines = pids
+                    .iter()
+                    .map(|(name, pid)| match pid {
+                        Ok(pid) => format!("{}: {}", name, pid),
+                        Err(reason) => format!("{}: {}", name, reason),
+                    })
+                    .collect::<Vec<_>>();
+                lines.sort();
+                write!(f, "{}", lines.join("\n"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(response: Response) {
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(serde_json::from_str::<Response>(&json).unwrap(), response);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        round_trip(Response::Ok("web: started".into()));
+        round_trip(Response::Error("nope: ERROR (no such process)".into()));
+        round_trip(Response::Status(
+            vec![
+                Ok(StatusReport {
+                    name: "web_00".into(),
+                    state: ProcessState::Running,
+                    pid: Some(4123),
+                    uptime: Some(Duration::from_secs(5025)),
+                    exit: None,
+                }),
+                Err("nope: ERROR (no such process)".into()),
+                Ok(StatusReport {
+                    name: "web_01".into(),
+                    state: ProcessState::Stopped,
+                    pid: None,
+                    uptime: None,
+                    exit: Some(ExitInfo {
+                        code: None,
+                        signal: Some(15),
+                        at: SystemTime::UNIX_EPOCH,
+                    }),
+                }),
+            ],
+            vec![(ProcessState::Running, 1), (ProcessState::Stopped, 1)],
+        ));
+        round_trip(Response::Pid(
+            vec![
+                ("web_00".to_string(), Ok(4123)),
+                ("web_01".to_string(), Err("not running".to_string())),
+            ]
+            .into_iter()
+            .collect(),
+        ));
+    }
+
+    #[test]
+    fn renders() {
+        let status = Response::Status(
+            vec![
+                Ok(StatusReport {
+                    name: "web".into(),
+                    state: ProcessState::Running,
+                    pid: Some(42),
+                    uptime: None,
+                    exit: None,
+                }),
+                Err("nope: ERROR (no such process)".into()),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            status.to_string(),
+            format!(
+                "{:<24} RUNNING   pid 42\nnope: ERROR (no such process)",
+                "web"
+            )
+        );
+        let running = StatusReport {
+            name: "web".into(),
+            state: ProcessState::Running,
+            pid: Some(4123),
+            uptime: Some(Duration::from_secs(5025)),
+            exit: None,
+        };
+        assert_eq!(
+            running.to_string(),
+            format!("{:<24} RUNNING   pid 4123, uptime 1:23:45", "web")
+        );
+        let at = SystemTime::now();
+        let exited = StatusReport {
+            name: "web".into(),
+            state: ProcessState::Exited,
+            pid: None,
+            uptime: None,
+            exit: Some(ExitInfo {
+                code: Some(1),
+                signal: None,
+                at,
+            }),
+        };
+        assert_eq!(
+            exited.to_string(),
+            format!(
+                "{:<24} EXITED    {} (exit code 1)",
+                "web",
+                logger::short_date(at)
+            )
+        );
+        let pids = Response::Pid(
+            vec![
+                ("b".to_string(), Err("not running".into())),
+                ("a".to_string(), Ok(1)),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(pids.to_string(), "a: 1\nb: not running");
+    }
+
+    #[test]
+    fn counts_states() {
+        let report = |name: &str, state| StatusReport {
+            name: name.into(),
+            state,
+            pid: None,
+            uptime: None,
+            exit: None,
+        };
+        let reports = vec![
+            report("a", ProcessState::Stopped),
+            report("b", ProcessState::Running),
+            report("c", ProcessState::Fatal),
+            report("d", ProcessState::Running),
+            report("e", ProcessState::Stopped),
+            report("f", ProcessState::Running),
+        ];
+        let counts = count_states(&reports);
+        assert_eq!(
+            counts,
+            [
+                (ProcessState::Running, 3),
+                (ProcessState::Fatal, 1),
+                (ProcessState::Stopped, 2)
+            ]
+        );
+        assert!(count_states(&[]).is_empty());
+
+        let status = Response::Status(vec![Ok(reports[0].clone())], counts).to_string();
+        assert_eq!(
+            status,
+            format!("{:<24} STOPPED\n3 running, 1 fatal, 2 stopped", "a")
+        );
+    }
+
+    #[test]
+    fn formats_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(5)), "0:00:05");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 60 + 7)), "0:03:07");
+        assert_eq!(format_uptime(Duration::from_millis(5_025_900)), "1:23:45");
+        assert_eq!(
+            format_uptime(Duration::from_secs(86400 + 60)),
+            "1 day, 0:01:00"
+        );
+        assert_eq!(
+            format_uptime(Duration::from_secs(3 * 86400)),
+            "3 days, 0:00:00"
+        );
+    }
+}
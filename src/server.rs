@@ -1,86 +1,2851 @@
-use crate::{command::Command, config::Config, threadpool::ThreadPool, DEFAULT_ADDR};
+#[cfg(feature = "notify")]
+use crate::watch;
+use crate::{
+    command::{Command, Credentials},
+    config::{self, Config, ConfigDiff, Taskmasterd, UnixHttpServer},
+    fcgi,
+    logfile::{self, RotatingLogWriter, Tee},
+    logger,
+    process::{self, Inherited},
+    registry::{self, Registry},
+    response::{self, Response},
+    threadpool::ThreadPool,
+    webhook, DEFAULT_ADDR,
+};
 use daemonize::Daemonize;
+use serde::Deserialize;
 use std::{
-    convert::TryFrom,
+    collections::HashMap,
     env,
-    fs::File,
-    io::{Read, Write},
-    net::{TcpListener, TcpStream},
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::unix::{
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+        process::CommandExt,
+    },
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use users::{get_current_gid, get_current_uid};
 
 /// Number of threads in the `ThreadPool`.
 const NUM_THREADS: usize = 4;
 
-/// Runs the server.
+/// Name of the file holding the daemon's PID, in the daemon's home directory.
+const PID_FILE: &str = "taskmasterd.pid";
+
+/// How long the accept loop waits when no client is connecting, before checking
+/// whether it should shut down.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How much of the end of a log file `tail` shows.
+const TAIL_BYTES: u64 = 1600;
+
+/// How often the reaper looks for processes that exited.
+const REAP_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long before taskmasterctl would give up waiting a slow `start`, `stop` or `restart`
+/// answers anyway.
+const REPLY_MARGIN: Duration = Duration::from_secs(1);
+
+/// Largest command a client may send, so that one can't make the daemon allocate without
+/// bound.
+const MAX_COMMAND_BYTES: u64 = 1024 * 1024;
+
+/// How often `tail -f` checks for new output.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Environment variable telling a re-executed daemon which inherited file descriptor is its
+/// TCP listener.
+const LISTEN_FD_ENV: &str = "TASKMASTER_LISTEN_FD";
+
+/// Environment variable telling a re-executed daemon which processes it inherited, by
+/// program name, as JSON.
+const CHILDREN_ENV: &str = "TASKMASTER_CHILDREN";
+
+/// Flags raised by signal handlers and commands, checked by the accept loop.
+#[derive(Default, Clone)]
+struct SignalFlags {
+    /// Set on SIGTERM or SIGINT: the daemon should stop.
+    shutdown: Arc<AtomicBool>,
+    /// Set on SIGHUP: the config file should be reloaded.
+    reload: Arc<AtomicBool>,
+    /// Set by the `reload` command: every process should be restarted with a freshly read
+    /// config file.
+    restart: Arc<AtomicBool>,
+    /// Set by the `reexec` command: once stopped, the daemon should execute itself again,
+    /// handing its processes over.
+    reexec: Arc<AtomicBool>,
+    /// Set on SIGUSR2: the log files should be reopened, as they were rotated externally.
+    reopen: Arc<AtomicBool>,
+}
+
+impl SignalFlags {
+    /// Installs the signal handlers raising the flags.
+    fn register() -> Result<Self, String> {
+        use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR2};
+
+        let flags = Self::default();
+        for (signal, flag) in &[
+            (SIGTERM, &flags.shutdown),
+            (SIGINT, &flags.shutdown),
+            (SIGHUP, &flags.reload),
+            (SIGUSR2, &flags.reopen),
+        ] {
+            signal_hook::flag::register(*signal, Arc::clone(flag))
+                .map_err(|e| format!("Could not install signal handler: {:?}", e))?;
+        }
+        Ok(flags)
+    }
+}
+
+/// A connection from a client, over TCP or the UNIX socket.
+trait Stream: Read + Write + Send + 'static {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    /// Who is at the other end, for the logs.
+    fn peer(&self) -> String;
+    /// Whether a client sending `credentials` may use the server of `config` it connected
+    /// to.
+    fn authenticates(config: &Config, credentials: Option<&Credentials>) -> bool;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn peer(&self) -> String {
+        self.peer_addr()
+            .map_or_else(|_| "unknown peer".to_string(), |addr| addr.to_string())
+    }
+
+    fn authenticates(config: &Config, credentials: Option<&Credentials>) -> bool {
+        config
+            .inet_http_server
+            .as_ref()
+            .is_none_or(|server| server.authenticates(credentials))
+    }
+}
+
+impl Stream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn peer(&self) -> String {
+        "a UNIX socket client".to_string()
+    }
+
+    fn authenticates(config: &Config, credentials: Option<&Credentials>) -> bool {
+        config
+            .unix_http_server
+            .as_ref()
+            .is_none_or(|server| server.authenticates(credentials))
+    }
+}
+
+/// UNIX socket clients connect to, as `unix_http_server` asks. Its file is removed when
+/// dropped.
+struct UnixSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl UnixSocket {
+    /// Binds the socket of `server` and gives its file the configured `chmod` and `chown`.
+    fn bind(server: &UnixHttpServer) -> Result<Self, String> {
+        Ok(Self {
+            listener: fcgi::bind_unix_socket(
+                &server.file,
+                server.mode()?,
+                server.chown.as_deref(),
+            )?,
+            path: server.file.clone(),
+        })
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Could not remove {}: {:?}", self.path.display(), e);
+        }
+    }
+}
+
+/// Command line options of `taskmasterd`, overriding the config file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    /// Stay in the foreground, like the `nodaemon` setting.
+    pub nodaemon: bool,
+    /// Config file to use instead of looking one up.
+    pub config: Option<PathBuf>,
+    /// Don't log to stderr, like the `silent` setting.
+    pub silent: bool,
+}
+
+impl Options {
+    /// Parses the arguments of `taskmasterd`, without the program name.
+    ///
+    /// # Errors
+    ///
+    /// Errors with the usage if an argument is unknown or `-c` has no path.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let usage = || "Usage: taskmasterd [-n|--nodaemon] [-s|--silent] [-c <config>]".to_string();
+        let mut options = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-n" | "--nodaemon" => options.nodaemon = true,
+                "-s" | "--silent" => options.silent = true,
+                "-c" | "--configuration" => {
+                    options.config = Some(args.next().ok_or_else(usage)?.into());
+                }
+                _ => return Err(usage()),
+            }
+        }
+        Ok(options)
+    }
+
+    /// The config file to use: the one given, or the one found by `config::find_file`.
+    pub(crate) fn config_file(&self) -> Result<PathBuf, String> {
+        match &self.config {
+            // Made absolute, since daemonizing changes the working directory.
+            Some(path) => path
+                .canonicalize()
+                .map_err(|e| format!("{}: {}", path.display(), e)),
+            None => config::find_file().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Checks the config file of `options` the way `run` would load it, without starting
+/// anything: parsing, validation, and the templates of every program instance.
 ///
 /// # Errors
 ///
-/// Errors if it parsing the config file errors, or if binding to the default address fails.
-pub fn run() -> Result<(), String> {
+/// Errors with every problem found.
+pub fn check_config(options: &Options) -> Result<(), Vec<String>> {
+    let path = options.config_file().map_err(|e| vec![e])?;
+    let config =
+        Config::from_path(&path).map_err(|e| vec![format!("{}: {}", path.display(), e)])?;
+    let mut errors = registry::check_templates(&config);
+    errors.extend(config.check_owners());
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Runs the server, with the settings of the config file overridden by `options`.
+///
+/// # Errors
+///
+/// Errors if parsing the config file errors, or if binding to the configured address fails.
+pub fn run(options: &Options) -> Result<(), String> {
+    // Found now: once the binary is replaced on disk, /proc/self/exe says it was deleted.
+    let exe = env::current_exe();
     let dir = env::var_os("HOME")
         .map(PathBuf::from)
         .ok_or_else(|| "Impossible to get user home directory".to_string())?;
 
-    daemonize(&dir)?;
+    // Read before daemonizing, which changes the working directory the config file is
+    // looked up from. Errors also show up in the terminal this way.
+    let path = options.config_file()?;
+    let mut config = Config::from_path(&path).map_err(|e| e.to_string())?;
+    if let Some(e) = config.check_owners().into_iter().next() {
+        return Err(e);
+    }
+    config.taskmasterd.nodaemon |= options.nodaemon;
+    config.taskmasterd.silent |= options.silent;
+    // Relative to where the daemon was started, not to the home directory it moves to once
+    // daemonized.
+    let cwd =
+        env::current_dir().map_err(|e| format!("Could not get current directory: {:?}", e))?;
+    config.taskmasterd.logfile = cwd.join(&config.taskmasterd.logfile);
+
+    // Already detached from the terminal when re-executed.
+    let inherited = inherited_listener()?;
+    let children = inherited_children()?;
+    let daemonized = should_daemonize(&config.taskmasterd);
+    if daemonized && inherited.is_none() {
+        daemonize(&dir, daemon_output(&config.taskmasterd))?;
+    }
+    let daemon = &config.taskmasterd;
+    let log = RotatingLogWriter::new(
+        &daemon.logfile,
+        daemon.logfile_maxbytes,
+        daemon.logfile_backups,
+        false,
+    )
+    .map_err(|e| format!("Could not open {}: {:?}", daemon.logfile.display(), e))?;
+    let mut sinks: Vec<Box<dyn Write + Send>> = vec![Box::new(log)];
+    if !daemonized && !daemon.silent {
+        sinks.push(Box::new(io::stderr()));
+    }
+    logger::redirect(Box::new(Tee(sinks)), daemon.loglevel);
+    info!(
+        "taskmasterd {} started with pid {}",
+        env!("CARGO_PKG_VERSION"),
+        std::process::id()
+    );
+
+    for warning in config.warnings() {
+        warn!("{}", warning);
+    }
+
+    let flags = SignalFlags::register()?;
 
     let pool = ThreadPool::new(NUM_THREADS)?;
 
-    let path = Path::new("config.yaml");
-    let _config = Config::try_from(path).map_err(|e| format!("{:?}", e))?;
+    let addr = listen_addr(&config)?;
+    let unix_socket = config
+        .unix_http_server
+        .as_ref()
+        .map(UnixSocket::bind)
+        .transpose()?;
+    raise_limit(Limit::Files, daemon.minfds)?;
+    raise_limit(Limit::Processes, daemon.minprocs)?;
+    // Those of the inherited processes are still written to.
+    if !daemon.nocleanup && children.is_none() {
+        if let Err(e) = logfile::clear_auto_logfiles(&daemon.childlogdir, &daemon.identifier) {
+            warn!("Could not clear AUTO log files: {:?}", e);
+        }
+    }
+    let webhook = config.taskmasterd.webhook();
+    let watch = config.taskmasterd.watch;
+    let registry = Arc::new(Mutex::new(match children {
+        Some(children) => Registry::adopt(config, children),
+        None => Registry::new(config),
+    }));
+    if let Some(webhook) = &webhook {
+        webhook.notify(webhook::Event::DaemonStart, None, "taskmasterd started");
+    }
+
+    let listener = match inherited {
+        Some(listener) if listener.local_addr().ok() == Some(addr) => listener,
+        _ => TcpListener::bind(addr).map_err(|e| format!("Could not bind {}: {:?}", addr, e))?,
+    };
+    let reaper = spawn_reaper(Arc::clone(&registry), Arc::clone(&flags.shutdown))?;
+    let watcher = if watch {
+        watch_config(&path, &flags)
+    } else {
+        None
+    };
 
-    let listener = TcpListener::bind(DEFAULT_ADDR).map_err(|e| format!("{:?}", e))?;
+    serve(
+        &listener,
+        unix_socket.as_ref().map(|socket| &socket.listener),
+        &pool,
+        &flags,
+        &registry,
+        &path,
+    )?;
+    if reaper.join().is_err() {
+        error!("The reaper panicked");
+    }
+    if watcher.is_some_and(|watcher| watcher.join().is_err()) {
+        error!("The config watcher panicked");
+    }
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(tcp_stream) => pool.execute(|| {
-                let _ = handle_connection(tcp_stream);
-            }),
-            Err(e) => eprintln!("Error while listening for incoming messages: {:?}", e),
+    // Let the commands in flight complete before stopping what they act on.
+    pool.join();
+    drop(pool);
+    let mut registry = registry
+        .lock()
+        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?;
+    if flags.reexec.load(Ordering::SeqCst) {
+        info!("Re-executing taskmasterd");
+        let children = registry.hand_over();
+        let error = reexec(exe.as_deref(), &listener, &children);
+        // Still in charge of them, then.
+        registry.stop_all();
+        return error;
+    }
+    registry.stop_all();
+    drop(registry);
+    if daemonized {
+        if let Err(e) = fs::remove_file(dir.join(PID_FILE)) {
+            warn!("Could not remove pid file: {:?}", e);
         }
     }
+    // Blocking, so the notification isn't lost when the daemon exits.
+    if let Some(webhook) = &webhook {
+        webhook.notify_blocking(webhook::Event::DaemonStop, None, "taskmasterd stopped");
+    }
 
     Ok(())
 }
 
-fn handle_connection(mut stream: TcpStream) -> Result<(), String> {
-    let mut buf = [0; 1024];
+/// Takes the TCP listener handed over by the daemon that re-executed into this one, if any.
+fn inherited_listener() -> Result<Option<TcpListener>, String> {
+    let fd = match env::var(LISTEN_FD_ENV) {
+        Ok(fd) => fd,
+        Err(_) => return Ok(None),
+    };
+    // Not for the children to see.
+    env::remove_var(LISTEN_FD_ENV);
+    let fd = fd
+        .parse::<RawFd>()
+        .map_err(|_| format!("{}: invalid file descriptor `{}`", LISTEN_FD_ENV, fd))?;
+    // Nor for them to inherit.
+    process::set_cloexec(fd, true).map_err(|e| format!("{}: fd {}: {}", LISTEN_FD_ENV, fd, e))?;
+    // SAFETY: the fd is the listener the previous daemon left open for us, and nothing else
+    // in this process owns it.
+    Ok(Some(unsafe { TcpListener::from_raw_fd(fd) }))
+}
 
-    match stream.read(&mut buf) {
-        Ok(bytes) => {
-            let _cmd: Command = serde_json::from_str(&String::from_utf8_lossy(&buf[..bytes]))
-                .map_err(|e| format! {"Failed to deserialize Command: {:?}", e})?;
-            // Execute the command here.
+/// Takes the processes handed over by the daemon that re-executed into this one, if any.
+fn inherited_children() -> Result<Option<HashMap<String, Vec<Inherited>>>, String> {
+    let children = match env::var(CHILDREN_ENV) {
+        Ok(children) => children,
+        Err(_) => return Ok(None),
+    };
+    // Not for the children to see.
+    env::remove_var(CHILDREN_ENV);
+    serde_json::from_str(&children)
+        .map(Some)
+        .map_err(|e| format!("{}: {}", CHILDREN_ENV, e))
+}
 
-            // Answer back to client with command's status.
+/// Replaces the daemon with a fresh run of `exe`, its own binary, with the same arguments.
+/// It is handed `listener`, so clients never find the port closed, and `children`, whose
+/// pipes must already survive the exec. Only returns if that fails.
+fn reexec(
+    exe: Result<&Path, &io::Error>,
+    listener: &TcpListener,
+    children: &HashMap<String, Vec<Inherited>>,
+) -> Result<(), String> {
+    let fd = listener.as_raw_fd();
+    process::set_cloexec(fd, false)
+        .map_err(|e| format!("Could not keep the listener open: {}", e))?;
+    let exe = exe.map_err(|e| format!("Could not find own binary: {}", e))?;
+    let children = serde_json::to_string(children)
+        .map_err(|e| format!("Could not describe the processes to hand over: {}", e))?;
+    let error = std::process::Command::new(exe)
+        .args(env::args_os().skip(1))
+        .env(LISTEN_FD_ENV, fd.to_string())
+        .env(CHILDREN_ENV, children)
+        .exec();
+    Err(format!("Could not execute {}: {}", exe.display(), error))
+}
+
+/// Resource limit raised on start.
+#[derive(Debug, Clone, Copy)]
+enum Limit {
+    /// Open file descriptors, see `minfds`.
+    Files,
+    /// Processes of the daemon's user, see `minprocs`.
+    Processes,
+}
+
+/// Raises the soft limit of `limit` to at least `minimum`, and the hard limit too if needed,
+/// which only root may do.
+fn raise_limit(limit: Limit, minimum: u64) -> Result<(), String> {
+    let (resource, setting) = match limit {
+        Limit::Files => (libc::RLIMIT_NOFILE, "minfds"),
+        Limit::Processes => (libc::RLIMIT_NPROC, "minprocs"),
+    };
+    let mut rlimit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `rlimit` is a valid pointer to a `struct rlimit`.
+    if unsafe { libc::getrlimit(resource, &mut rlimit) } != 0 {
+        return Err(format!(
+            "Could not get the limit for `{}`: {:?}",
+            setting,
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    let minimum = minimum as libc::rlim_t;
+    if rlimit.rlim_cur == libc::RLIM_INFINITY || rlimit.rlim_cur >= minimum {
+        return Ok(());
+    }
+    let hard = rlimit.rlim_max;
+    rlimit.rlim_cur = minimum;
+    if hard != libc::RLIM_INFINITY && hard < minimum {
+        rlimit.rlim_max = minimum;
+    }
+    // SAFETY: `rlimit` is a valid pointer to a `struct rlimit`.
+    if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+        return Err(format!(
+            "`{}` is {}, but the hard limit is {} and could not be raised: {:?}",
+            setting,
+            minimum,
+            hard,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// Address the daemon listens on over TCP: `inet_http_server.port` if configured,
+/// `DEFAULT_ADDR` otherwise. This is on top of the `unix_http_server` socket, if any.
+fn listen_addr(config: &Config) -> Result<SocketAddr, String> {
+    match &config.inet_http_server {
+        Some(server) => server.bind_addr(),
+        None => DEFAULT_ADDR
+            .parse()
+            .map_err(|e| format!("Invalid default address: {:?}", e)),
+    }
+}
+
+/// Reaps the processes of `registry` that exit on their own, on a background thread, until
+/// `shutdown` is set.
+fn spawn_reaper(
+    registry: Arc<Mutex<Registry>>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, String> {
+    thread::Builder::new()
+        .name("tm-reaper".into())
+        .spawn(move || {
+            while !shutdown.load(Ordering::SeqCst) {
+                match registry.lock() {
+                    Ok(mut registry) => {
+                        registry.reap();
+                    }
+                    Err(e) => error!("Failed to acquire lock: {:?}", e),
+                }
+                thread::sleep(REAP_INTERVAL);
+            }
+        })
+        .map_err(|e| format!("Could not spawn the reaper: {:?}", e))
+}
+
+/// Reloads the config file at `path` whenever it changes, the same way as on SIGHUP, as
+/// `watch` asks. The daemon keeps running without it if the watch could not be set up.
+#[cfg(feature = "notify")]
+fn watch_config(path: &Path, flags: &SignalFlags) -> Option<thread::JoinHandle<()>> {
+    match watch::spawn(path, Arc::clone(&flags.reload), Arc::clone(&flags.shutdown)) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            error!("{}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+fn watch_config(_path: &Path, _flags: &SignalFlags) -> Option<thread::JoinHandle<()>> {
+    warn!("`watch` needs taskmasterd to be built with the `notify` feature, ignoring it");
+    None
+}
+
+/// Dispatches incoming connections, over TCP and on the `unix` socket if any, to the `pool`
+/// until shutdown is requested, reloading `config_path` whenever asked to.
+fn serve(
+    listener: &TcpListener,
+    unix: Option<&UnixListener>,
+    pool: &ThreadPool,
+    flags: &SignalFlags,
+    registry: &Arc<Mutex<Registry>>,
+    config_path: &Path,
+) -> Result<(), String> {
+    // Don't block on `accept`, otherwise the flags would only be checked on new connections.
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("{:?}", e))?;
+    if let Some(unix) = unix {
+        unix.set_nonblocking(true).map_err(|e| format!("{:?}", e))?;
+    }
+
+    while !flags.shutdown.load(Ordering::SeqCst) {
+        logger::tick();
+        if flags.reload.swap(false, Ordering::SeqCst) {
+            match reload(registry, config_path) {
+                Ok(diff) => info!("Reloaded {}:\n{}", config_path.display(), diff),
+                Err(e) => error!("Could not reload {}: {}", config_path.display(), e),
+            }
+        }
+        if flags.reopen.swap(false, Ordering::SeqCst) {
+            logfile::reopen_all();
+            info!("Reopening log files");
+        }
+        if flags.restart.swap(false, Ordering::SeqCst) {
+            match restart(registry, config_path) {
+                Ok(()) => info!("Restarted with {}", config_path.display()),
+                Err(e) => error!("Could not restart with {}: {}", config_path.display(), e),
+            }
+        }
+
+        let mut idle = true;
+        match listener.accept() {
+            Ok((stream, _)) => {
+                idle = false;
+                dispatch(stream, pool, flags, registry, config_path);
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => error!("Error while listening for incoming messages: {:?}", e),
+        }
+        if let Some(unix) = unix {
+            match unix.accept() {
+                Ok((stream, _)) => {
+                    idle = false;
+                    dispatch(stream, pool, flags, registry, config_path);
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => error!("Error while listening on the UNIX socket: {:?}", e),
+            }
+        }
+        if idle {
+            thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+    }
+
+    Ok(())
+}
+
+/// Hands a freshly accepted `stream` to the `pool` to be handled.
+fn dispatch<S: Stream>(
+    stream: S,
+    pool: &ThreadPool,
+    flags: &SignalFlags,
+    registry: &Arc<Mutex<Registry>>,
+    config_path: &Path,
+) {
+    if let Err(e) = stream.set_nonblocking(false) {
+        error!("Could not set stream to blocking: {:?}", e);
+        return;
+    }
+    let registry = Arc::clone(registry);
+    let flags = flags.clone();
+    let config_path = config_path.to_path_buf();
+    pool.execute(move || {
+        if let Err(e) = handle_connection(stream, &registry, &flags, &config_path) {
+            error!("{}", e);
+        }
+    });
+}
+
+/// Re-reads the config file and applies its changes to the managed processes, the same way
+/// `Update` does.
+fn reload(registry: &Mutex<Registry>, config_path: &Path) -> Result<ConfigDiff, String> {
+    let config = Config::from_path(config_path).map_err(|e| e.to_string())?;
+    let diff = registry
+        .lock()
+        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?
+        .update(config);
+    Ok(diff)
+}
+
+/// Stops every process, re-reads the config file and starts everything again, like `Reload`
+/// asks. Processes are left alone if the config file is invalid.
+fn restart(registry: &Mutex<Registry>, config_path: &Path) -> Result<(), String> {
+    let config = Config::from_path(config_path).map_err(|e| e.to_string())?;
+    registry
+        .lock()
+        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?
+        .restart_all(config);
+    Ok(())
+}
+
+/// Reads the config file at `config_path` for a command, or tells the client why it can't.
+fn read_config(config_path: &Path) -> Result<Config, Response> {
+    Config::from_path(config_path)
+        .map_err(|e| Response::Error(format!("Could not read {}: {}", config_path.display(), e)))
+}
+
+/// Starts managing the programs and groups `names` refer to in a freshly read config file,
+/// like `Add` asks.
+fn add(
+    registry: &Mutex<Registry>,
+    config_path: &Path,
+    names: &[String],
+) -> Result<Response, String> {
+    let config = match read_config(config_path) {
+        Ok(config) => config,
+        Err(response) => return Ok(response),
+    };
+    let mut registry = registry
+        .lock()
+        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?;
+
+    Ok(for_each_name(names, |name| {
+        registry
+            .add(name, &config)
+            .map(|_| format!("{}: added process group", name))
+    }))
+}
+
+/// Re-reads the config file and tells how it differs from the one the processes run with,
+/// like `ReRead` asks. Nothing is applied.
+fn reread(registry: &Mutex<Registry>, config_path: &Path) -> Result<Response, String> {
+    let config = match read_config(config_path) {
+        Ok(config) => config,
+        Err(response) => return Ok(response),
+    };
+    let diff = registry
+        .lock()
+        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?
+        .config()
+        .diff(&config);
+    Ok(Response::Ok(describe_diff(
+        &diff,
+        ["available", "disappeared", "changed"],
+    )))
+}
+
+/// Re-reads the config file and applies its changes to the programs and groups `names` refer
+/// to, or to every one of them for `all`, like `Update` asks. Programs whose settings did not
+/// change keep running untouched.
+fn update(
+    registry: &Mutex<Registry>,
+    config_path: &Path,
+    names: &[String],
+) -> Result<Response, String> {
+    let mut config = match read_config(config_path) {
+        Ok(config) => config,
+        Err(response) => return Ok(response),
+    };
+    let mut registry = registry
+        .lock()
+        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?;
+
+    if !names.iter().any(|name| name == "all") {
+        if let Err(e) = limit_update(&mut config, registry.config(), names) {
+            return Ok(Response::Error(e));
+        }
+    }
+    let diff = registry.update(config);
+    Ok(Response::Ok(describe_diff(
+        &diff,
+        [
+            "added process group",
+            "removed process group",
+            "updated process group",
+        ],
+    )))
+}
+
+/// Reverts the programs, FastCGI programs, event listener pools and groups of `new` that
+/// `names` don't refer to to their settings in `old`, so that updating to `new` leaves them
+/// alone.
+///
+/// # Errors
+///
+/// Errors if one of `names` is in neither config.
+fn limit_update(new: &mut Config, old: &Config, names: &[String]) -> Result<(), String> {
+    let mut scope = Vec::new();
+    for name in names {
+        let members = [old, new]
+            .iter()
+            .filter_map(|config| config.group.get(name))
+            .flat_map(|group| group.programs.iter().cloned())
+            .collect::<Vec<String>>();
+        if members.is_empty()
+            && old.process_config(name).is_none()
+            && new.process_config(name).is_none()
+        {
+            return Err(format!("{}: ERROR (no such process)", name));
+        }
+        scope.push(name.clone());
+        scope.extend(members);
+    }
+
+    limit_entries(&mut new.programs, &old.programs, &scope);
+    limit_entries(&mut new.fcgi_programs, &old.fcgi_programs, &scope);
+    limit_entries(&mut new.eventlisteners, &old.eventlisteners, &scope);
+    limit_entries(&mut new.group, &old.group, &scope);
+    Ok(())
+}
+
+/// Reverts the entries of `new` outside of `scope` to those of `old`.
+fn limit_entries<T: Clone>(
+    new: &mut HashMap<String, T>,
+    old: &HashMap<String, T>,
+    scope: &[String],
+) {
+    let names = old
+        .keys()
+        .chain(new.keys())
+        .filter(|name| !scope.contains(name))
+        .cloned()
+        .collect::<Vec<String>>();
+    for name in names {
+        match old.get(&name) {
+            Some(entry) => new.insert(name, entry.clone()),
+            None => new.remove(&name),
+        };
+    }
+}
+
+/// One line per program in `diff`, saying it was added, removed or changed with the matching
+/// word of `words`.
+fn describe_diff(diff: &ConfigDiff, words: [&str; 3]) -> String {
+    if diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty() {
+        return "No config updates to processes".to_string();
+    }
+    let [added, removed, changed] = words;
+    diff.added
+        .iter()
+        .map(|name| format!("{}: {}", name, added))
+        .chain(
+            diff.removed
+                .iter()
+                .map(|name| format!("{}: {}", name, removed)),
+        )
+        .chain(
+            diff.changed
+                .keys()
+                .map(|name| format!("{}: {}", name, changed)),
+        )
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Reads a command from `stream`, executes it and writes back the answer. Commands that
+/// stream their answer, like `tail -f`, do so until the client hangs up or shutdown is
+/// requested. Those that need the config file read it from `config_path`.
+fn handle_connection<S: Stream>(
+    mut stream: S,
+    registry: &Mutex<Registry>,
+    flags: &SignalFlags,
+    config_path: &Path,
+) -> Result<(), String> {
+    let timeout = registry
+        .lock()
+        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?
+        .config()
+        .taskmasterd
+        .command_timeout();
+    // Don't let a client that never sends anything hold this worker forever.
+    stream
+        .set_read_timeout(timeout)
+        .map_err(|e| format!("{:?}", e))?;
+
+    // Credentials come first, in a frame of their own.
+    let (credentials, received) = match read_command(&mut stream) {
+        Ok(Command::Authenticate(credentials)) => (Some(credentials), read_command(&mut stream)),
+        received => (None, received),
+    };
+    let authenticated = S::authenticates(
+        registry
+            .lock()
+            .map_err(|e| format!("Failed to acquire lock: {:?}", e))?
+            .config(),
+        credentials.as_ref(),
+    );
+
+    match received {
+        Ok(cmd) if !authenticated => {
+            warn!(
+                "Refused {:?} from {}: authentication failed",
+                cmd,
+                stream.peer()
+            );
+            let answer = serde_json::to_string(&Response::Error(
+                "Authentication failed: check the username and password of taskmasterctl".into(),
+            ))
+            .map_err(|e| format!("Failed to serialize Response: {:?}", e))?;
             stream
-                .write_all(b"Your program is running ok.")
+                .write_all(answer.as_bytes())
                 .map_err(|e| format!("{:?}", e))?;
         }
+        Ok(cmd) => {
+            info!("Received {:?} from {}", cmd, stream.peer());
+            let followed = match &cmd {
+                Command::Tail {
+                    name,
+                    stream: output,
+                    follow: true,
+                } => Some(
+                    registry
+                        .lock()
+                        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?
+                        .logfile(name, *output)
+                        .map_err(|e| format!("{}: ERROR ({})", name, e)),
+                ),
+                Command::MainTail { follow: true } => Some(Ok(vec![registry
+                    .lock()
+                    .map_err(|e| format!("Failed to acquire lock: {:?}", e))?
+                    .config()
+                    .taskmasterd
+                    .logfile
+                    .clone()])),
+                Command::Fg(name) => Some(
+                    registry
+                        .lock()
+                        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?
+                        .logfiles(name)
+                        .map_err(|e| format!("{}: ERROR ({})", name, e)),
+                ),
+                _ => None,
+            };
+            if let Some(paths) = followed {
+                return match (paths, &cmd) {
+                    (Ok(paths), Command::Fg(name)) => {
+                        follow(stream, &paths, &flags.shutdown, |bytes| {
+                            registry
+                                .lock()
+                                .map_err(|e| format!("Failed to acquire lock: {:?}", e))?
+                                .send_input(name, bytes)
+                        })
+                    }
+                    // Nothing is expected from the client.
+                    (Ok(paths), _) => follow(stream, &paths, &flags.shutdown, |_| Ok(())),
+                    (Err(e), _) => stream
+                        .write_all(e.as_bytes())
+                        .map_err(|e| format!("{:?}", e)),
+                };
+            }
+            let answer = match &cmd {
+                Command::Add(names) => add(registry, config_path, names)?,
+                Command::ReRead => reread(registry, config_path)?,
+                Command::Update(names) => update(registry, config_path, names)?,
+                _ => execute(&cmd, registry)?,
+            };
+            debug!(
+                "Answered {:?} with {}",
+                cmd,
+                match answer {
+                    Response::Error(_) => "an error",
+                    _ => "success",
+                }
+            );
+            let answer = serde_json::to_string(&answer)
+                .map_err(|e| format!("Failed to serialize Response: {:?}", e))?;
+
+            // Answer back to client with command's status.
+            let written = stream
+                .write_all(answer.as_bytes())
+                .map_err(|e| format!("{:?}", e));
+            match cmd {
+                // Same path as SIGTERM: the accept loop ends, then every process is stopped.
+                Command::Shutdown => flags.shutdown.store(true, Ordering::SeqCst),
+                // Left to the accept loop, which knows where the config file is.
+                Command::Reload => flags.restart.store(true, Ordering::SeqCst),
+                // Shuts down like `shutdown`, then `run` executes the daemon again.
+                Command::ReExec => {
+                    flags.reexec.store(true, Ordering::SeqCst);
+                    flags.shutdown.store(true, Ordering::SeqCst);
+                }
+                _ => {}
+            }
+            written?;
+        }
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+            info!("Dropping idle connection from {}", stream.peer());
+        }
+        Err(ref e)
+            if e.kind() == ErrorKind::InvalidData || e.kind() == ErrorKind::UnexpectedEof =>
+        {
+            return Err(format!("Failed to deserialize Command: {}", e));
+        }
         Err(e) => {
-            eprintln!("Could not read from stream: {:?}", e);
+            error!("Could not read from stream: {:?}", e);
         }
     }
     Ok(())
 }
 
-/// Daemonize the current program.
-fn daemonize(home: &PathBuf) -> Result<(), String> {
-    let stderr = File::create(home.join("taskmasterd.log")).map_err(|e| format!("{:?}", e))?;
+/// Reads the command a client sends, however many reads it arrives in.
+///
+/// The client keeps the connection open for the answer, so this stops at the end of the JSON
+/// value rather than waiting for the end of the stream. Unbuffered, so that what the client
+/// sends next, like the input of `fg`, is left in the stream.
+///
+/// # Errors
+///
+/// Errors with `InvalidData` if the command is not valid, or longer than
+/// `MAX_COMMAND_BYTES`.
+fn read_command<R: Read>(reader: R) -> io::Result<Command> {
+    let mut reader = reader.take(MAX_COMMAND_BYTES);
+    let mut deserializer = serde_json::Deserializer::from_reader(&mut reader);
+    match Command::deserialize(&mut deserializer) {
+        Ok(command) => Ok(command),
+        Err(_) if reader.limit() == 0 => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("command longer than {} bytes", MAX_COMMAND_BYTES),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
 
-    let daemonize = Daemonize::new()
-        .pid_file(home.join("taskmasterd.pid"))
+/// Streams what gets written to the logs at `paths` to the client, starting with the last
+/// `TAIL_BYTES` of each, until the client hangs up or `shutdown` is set. What the client
+/// sends in the meantime is handed to `input`, whose errors are reported to the client.
+fn follow<S, F>(
+    mut stream: S,
+    paths: &[PathBuf],
+    shutdown: &AtomicBool,
+    mut input: F,
+) -> Result<(), String>
+where
+    S: Stream,
+    F: FnMut(&[u8]) -> Result<(), String>,
+{
+    stream
+        .set_read_timeout(Some(FOLLOW_POLL_INTERVAL))
+        .map_err(|e| format!("{:?}", e))?;
+
+    let mut offsets = vec![(0, TAIL_BYTES); paths.len()];
+    let mut buf = [0; 1024];
+    while !shutdown.load(Ordering::SeqCst) {
+        for (path, (offset, max)) in paths.iter().zip(&mut offsets) {
+            match logfile::read_from(path, *offset, *max) {
+                Ok((bytes, end)) => {
+                    if stream.write_all(&bytes).is_err() {
+                        // The client is gone.
+                        return Ok(());
+                    }
+                    *offset = end;
+                    *max = u64::MAX;
+                }
+                // Nothing was logged yet.
+                Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Could not read {}: {:?}", path.display(), e)),
+            }
+        }
+
+        // Doubles as the delay between polls, unless the client sends something or hangs
+        // up.
+        match stream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(bytes) => {
+                if let Err(e) = input(&buf[..bytes]) {
+                    if stream
+                        .write_all(format!("ERROR: {}\n", e).as_bytes())
+                        .is_err()
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(format!("{:?}", e)),
+        }
+    }
+    Ok(())
+}
+
+/// Executes `command` against the managed processes, and returns the answer for the client.
+fn execute(command: &Command, registry: &Mutex<Registry>) -> Result<Response, String> {
+    // Wait on the processes they start or stop, without holding the lock all along.
+    match command {
+        Command::Start(names) => return start(registry, names),
+        Command::Stop(names) => return stop(registry, names),
+        Command::Restart(names) => return restart_programs(registry, names),
+        _ => {}
+    }
+    let mut registry = registry
+        .lock()
+        .map_err(|e| format!("Failed to acquire lock: {:?}", e))?;
+
+    let response = match command {
+        Command::ResetCounters(names) => {
+            for_each_program(&mut registry, names, |registry, name| {
+                registry
+                    .reset_counters(name)
+                    .map(|counters| format!("{}: counters reset (were {})", name, counters))
+            })
+        }
+        Command::Signal { signal, names } => {
+            for_each_program(&mut registry, names, |registry, name| {
+                registry
+                    .signal(name, *signal)
+                    .map(|_| format!("{}: signalled {}", name, signal))
+            })
+        }
+        Command::Clear(names) => for_each_program(&mut registry, names, |registry, name| {
+            registry
+                .clear_logs(name)
+                .map(|_| format!("{}: cleared", name))
+        }),
+        Command::Remove(names) => for_each_name(names, |name| {
+            registry
+                .remove(name)
+                .map(|_| format!("{}: removed process group", name))
+        }),
+        Command::Signals(names) => {
+            let names = all_if_empty(&registry, names);
+            for_each_program(&mut registry, &names, |registry, name| {
+                registry.stop_behavior(name)
+            })
+        }
+        Command::Tail { name, stream, .. } => {
+            let tail = registry.logfile(name, *stream).and_then(|paths| {
+                paths
+                    .iter()
+                    .map(|path| {
+                        logfile::read_from(path, 0, TAIL_BYTES)
+                            .map(|(bytes, _)| String::from_utf8_lossy(&bytes).into_owned())
+                            .map_err(|e| format!("{:?}", e))
+                    })
+                    .collect::<Result<String, _>>()
+            });
+            match tail {
+                Ok(tail) => Response::Ok(tail),
+                Err(e) => Response::Error(format!("{}: ERROR ({})", name, e)),
+            }
+        }
+        Command::MainTail { .. } => {
+            let path = &registry.config().taskmasterd.logfile;
+            match logfile::read_from(path, 0, TAIL_BYTES) {
+                Ok((bytes, _)) => Response::Ok(String::from_utf8_lossy(&bytes).into_owned()),
+                Err(e) => Response::Error(format!("taskmasterd: ERROR ({:?})", e)),
+            }
+        }
+        Command::Status(names) => {
+            // Only a summary of every program is worth counting states for.
+            let all = names.is_empty() || names.iter().any(|name| name == "all");
+            let names = all_if_empty(&registry, names);
+            let mut reports = Vec::new();
+            for name in &names {
+                match registry.resolve(name) {
+                    Ok(programs) => {
+                        for program in programs {
+                            reports.extend(registry.status(&program).into_iter().map(Ok));
+                        }
+                    }
+                    // Reported along with those of the names that do designate processes.
+                    Err(e) => reports.push(Err(e)),
+                }
+            }
+            let counts = if all {
+                let found = reports
+                    .iter()
+                    .filter_map(|report| report.as_ref().ok().cloned())
+                    .collect::<Vec<_>>();
+                response::count_states(&found)
+            } else {
+                Vec::new()
+            };
+            Response::Status(reports, counts)
+        }
+        Command::PID(names) if names.is_empty() => Response::Pid(
+            std::iter::once(("taskmasterd".to_string(), Ok(std::process::id()))).collect(),
+        ),
+        Command::PID(names) => {
+            let mut pids = HashMap::new();
+            for name in names {
+                match registry.resolve(name) {
+                    Ok(programs) => {
+                        for program in programs {
+                            pids.extend(registry.pids(&program));
+                        }
+                    }
+                    Err(_) => {
+                        pids.insert(name.clone(), Err("no such process".to_string()));
+                    }
+                }
+            }
+            Response::Pid(pids)
+        }
+        Command::Shutdown => Response::Ok("Shutting down".to_string()),
+        Command::Reload => Response::Ok("Restarting".to_string()),
+        Command::ReExec => Response::Ok("Re-executing".to_string()),
+        Command::Version => Response::Ok(format!("taskmasterd {}", env!("CARGO_PKG_VERSION"))),
+        Command::Authenticate(_) => Response::Error("Already authenticated".to_string()),
+        _ => Response::Ok("Your program is running ok.".to_string()),
+    };
+    Ok(response)
+}
+
+/// Every program name if `names` is empty, `names` otherwise.
+fn all_if_empty(registry: &Registry, names: &[String]) -> Vec<String> {
+    if names.is_empty() {
+        registry.program_names()
+    } else {
+        names.to_vec()
+    }
+}
+
+/// Runs `action` on every program designated by `names`, expanding groups to their members.
+///
+/// Members are visited in start order. The response is an error if any of them failed.
+fn for_each_program<F>(registry: &mut Registry, names: &[String], mut action: F) -> Response
+where
+    F: FnMut(&mut Registry, &str) -> Result<String, String>,
+{
+    let mut lines = Vec::new();
+    let mut failed = false;
+    for name in names {
+        match registry.resolve(name) {
+            Ok(programs) => {
+                for program in programs {
+                    lines.push(action(registry, &program).unwrap_or_else(|e| {
+                        failed = true;
+                        format!("{}: ERROR ({})", program, e)
+                    }));
+                }
+            }
+            Err(e) => {
+                failed = true;
+                lines.push(e);
+            }
+        }
+    }
+    let message = lines.join("\n");
+    if failed {
+        Response::Error(message)
+    } else {
+        Response::Ok(message)
+    }
+}
+
+/// Starts the programs `names` refer to one priority at a time: the programs of a priority
+/// are only started once those of the previous one each stayed up for `startsecs`, or went
+/// `FATAL`. The response has the outcome of each program, and is an error if any failed.
+///
+/// The answer doesn't wait past the `timeout` of taskmasterctl: it then says which programs
+/// are still starting and which are queued, and the reaper carries on with them.
+fn start(registry: &Mutex<Registry>, names: &[String]) -> Result<Response, String> {
+    let lock = || {
+        registry
+            .lock()
+            .map_err(|e| format!("Failed to acquire lock: {:?}", e))
+    };
+    let mut lines = Vec::new();
+    let mut failed = false;
+    let tiers = {
+        let registry = lock()?;
+        let mut programs = Vec::new();
+        for name in names {
+            match registry.resolve(name) {
+                Ok(members) => programs.extend(members),
+                Err(e) => {
+                    failed = true;
+                    lines.push(e);
+                }
+            }
+        }
+        let config = registry.config();
+        let mut programs = config
+            .start_order(programs.iter().map(String::as_str))
+            .into_iter()
+            .map(|name| {
+                let priority = config.process_config(name).map(|program| program.priority);
+                (priority, name.to_string())
+            })
+            .collect::<Vec<_>>();
+        programs.dedup();
+        programs
+            .chunk_by(|(a, _), (b, _)| a == b)
+            .map(|tier| tier.iter().map(|(_, name)| name.clone()).collect())
+            .collect::<Vec<Vec<String>>>()
+    };
+
+    let deadline = reply_deadline(&*lock()?);
+    let mut tiers = tiers.into_iter();
+    while let Some(tier) = tiers.next() {
+        let mut results = HashMap::new();
+        let mut pending = Vec::new();
+        {
+            let mut registry = lock()?;
+            for name in &tier {
+                match registry.start(name) {
+                    Ok(()) => pending.push(name.clone()),
+                    Err(e) => {
+                        results.insert(name.clone(), Err(e));
+                    }
+                }
+            }
+        }
+        while !pending.is_empty() && Instant::now() < deadline {
+            thread::sleep(REAP_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+            let mut registry = lock()?;
+            registry.reap();
+            pending.retain(|name| match registry.start_result(name) {
+                Some(result) => {
+                    results.insert(name.clone(), result);
+                    false
+                }
+                None => true,
+            });
+        }
+        for name in tier {
+            lines.push(match results.remove(&name) {
+                Some(Err(e)) => {
+                    failed = true;
+                    format!("{}: ERROR ({})", name, e)
+                }
+                Some(Ok(())) => format!("{}: started", name),
+                None => format!("{}: starting", name),
+            });
+        }
+        if !pending.is_empty() {
+            let rest = tiers.collect::<Vec<Vec<String>>>();
+            lines.extend(
+                rest.iter()
+                    .flatten()
+                    .map(|name| format!("{}: queued", name)),
+            );
+            lock()?.defer_start(pending, rest);
+            break;
+        }
+    }
+    let message = lines.join("\n");
+    if failed {
+        Ok(Response::Error(message))
+    } else {
+        Ok(Response::Ok(message))
+    }
+}
+
+/// Stops the programs `names` refer to, group members in reverse start order. Each is sent its
+/// `stopsignal`, and killed by the reaper if it still runs after its `stopwaitsecs`.
+///
+/// The answer doesn't wait past the `timeout` of taskmasterctl: it then says which programs
+/// are still stopping.
+fn stop(registry: &Mutex<Registry>, names: &[String]) -> Result<Response, String> {
+    let lock = || {
+        registry
+            .lock()
+            .map_err(|e| format!("Failed to acquire lock: {:?}", e))
+    };
+    // The programs asked to stop, or why a name couldn't be.
+    let mut results = Vec::new();
+    let deadline = {
+        let mut registry = lock()?;
+        for name in names {
+            match registry.resolve(name) {
+                Ok(programs) => {
+                    for program in programs.into_iter().rev() {
+                        results.push(match registry.stop(&program) {
+                            Ok(()) => Ok(program),
+                            Err(e) => Err(format!("{}: ERROR ({})", program, e)),
+                        });
+                    }
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+        reply_deadline(&registry)
+    };
+
+    wait_for_stops(registry, results.iter().flatten(), deadline)?;
+    let registry = lock()?;
+    let mut failed = false;
+    let lines = results
+        .into_iter()
+        .map(|result| match result {
+            Ok(program) if registry.is_stopping(&program) => format!("{}: stopping", program),
+            Ok(program) => format!("{}: stopped", program),
+            Err(line) => {
+                failed = true;
+                line
+            }
+        })
+        .collect::<Vec<String>>();
+    let message = lines.join("\n");
+    if failed {
+        Ok(Response::Error(message))
+    } else {
+        Ok(Response::Ok(message))
+    }
+}
+
+/// Restarts the programs or groups `names` refer to: once all the members of one are done
+/// stopping, like `stop` does, they are started again.
+///
+/// The answer doesn't wait past the `timeout` of taskmasterctl: it then says which are still
+/// stopping, and the reaper starts them once they stopped.
+fn restart_programs(registry: &Mutex<Registry>, names: &[String]) -> Result<Response, String> {
+    let lock = || {
+        registry
+            .lock()
+            .map_err(|e| format!("Failed to acquire lock: {:?}", e))
+    };
+    let mut restarts = Vec::new();
+    let deadline = {
+        let mut registry = lock()?;
+        for name in names {
+            restarts.push((name, registry.begin_restart(name)));
+        }
+        reply_deadline(&registry)
+    };
+
+    let stopping = restarts
+        .iter()
+        .filter_map(|(_, programs)| programs.as_ref().ok())
+        .flatten();
+    wait_for_stops(registry, stopping, deadline)?;
+    let mut registry = lock()?;
+    let mut failed = false;
+    let lines = restarts
+        .into_iter()
+        .map(|(name, programs)| {
+            match programs.and_then(|programs| registry.finish_restart(programs)) {
+                Ok(true) => format!("{}: restarted", name),
+                Ok(false) => format!("{}: restarting", name),
+                Err(e) => {
+                    failed = true;
+                    format!("{}: ERROR ({})", name, e)
+                }
+            }
+        })
+        .collect::<Vec<String>>();
+    let message = lines.join("\n");
+    if failed {
+        Ok(Response::Error(message))
+    } else {
+        Ok(Response::Ok(message))
+    }
+}
+
+/// Reaps until none of `programs` is stopping anymore, or until `deadline`.
+fn wait_for_stops<'a, I>(
+    registry: &Mutex<Registry>,
+    programs: I,
+    deadline: Instant,
+) -> Result<(), String>
+where
+    I: Iterator<Item = &'a String> + Clone,
+{
+    loop {
+        let mut registry = registry
+            .lock()
+            .map_err(|e| format!("Failed to acquire lock: {:?}", e))?;
+        registry.reap();
+        let mut programs = programs.clone();
+        if Instant::now() >= deadline || !programs.any(|program| registry.is_stopping(program)) {
+            return Ok(());
+        }
+        drop(registry);
+        thread::sleep(REAP_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+    }
+}
+
+/// When a slow `start`, `stop` or `restart` should answer anyway, for taskmasterctl not to
+/// give up waiting.
+fn reply_deadline(registry: &Registry) -> Instant {
+    Instant::now()
+        + registry
+            .config()
+            .taskmasterctl
+            .timeout()
+            .saturating_sub(REPLY_MARGIN)
+}
+
+/// Runs `action` on each of `names`, groups included, without expanding them to their
+/// members. The response is an error if any of them failed.
+fn for_each_name<F>(names: &[String], mut action: F) -> Response
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut failed = false;
+    let lines = names
+        .iter()
+        .map(|name| {
+            action(name).unwrap_or_else(|e| {
+                failed = true;
+                format!("{}: ERROR ({})", name, e)
+            })
+        })
+        .collect::<Vec<String>>();
+    let message = lines.join("\n");
+    if failed {
+        Response::Error(message)
+    } else {
+        Response::Ok(message)
+    }
+}
+
+/// Whether the daemon should detach from the terminal, rather than run in the foreground.
+fn should_daemonize(daemon: &Taskmasterd) -> bool {
+    !daemon.nodaemon
+}
+
+/// File the stdout and stderr of the daemon go to once it detached from the terminal: its
+/// `logfile`, unless it stays in the foreground, or `silent` asks for them to be discarded.
+fn daemon_output(daemon: &Taskmasterd) -> Option<&Path> {
+    if daemon.nodaemon || daemon.silent {
+        None
+    } else {
+        Some(&daemon.logfile)
+    }
+}
+
+/// Daemonize the current program, sending its stdout and stderr to `output`, or discarding
+/// them if there is none.
+fn daemonize(home: &PathBuf, output: Option<&Path>) -> Result<(), String> {
+    let mut daemonize = Daemonize::new()
+        .pid_file(home.join(PID_FILE))
         .chown_pid_file(true)
         .working_directory(home)
         .user(get_current_uid())
         .group(get_current_gid())
         .umask(0o027)
-        .stderr(stderr)
         .privileged_action(|| "Executed before drop privileges");
+    if let Some(path) = output {
+        // The activity log gets appended to this file too.
+        let stderr = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Could not open {}: {:?}", path.display(), e))?;
+        let stdout = stderr.try_clone().map_err(|e| format!("{:?}", e))?;
+        daemonize = daemonize.stdout(stdout).stderr(stderr);
+    }
 
     match daemonize.start() {
         Ok(_) => Ok(()),
         Err(e) => Err(e.to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn shutdown_stops_all_processes() {
+        let config: Config = "
+programs:
+  sleep:
+    command: sleep 100
+    numprocs: 2
+"
+        .parse()
+        .unwrap();
+        let registry = Arc::new(Mutex::new(Registry::new(config)));
+        {
+            let mut registry = registry.lock().unwrap();
+            assert_eq!(registry.processes("sleep").len(), 2);
+            assert!(registry
+                .processes("sleep")
+                .iter_mut()
+                .all(|p| p.is_running()));
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pool = ThreadPool::new(1).unwrap();
+        let flags = SignalFlags::default();
+        flags.shutdown.store(true, Ordering::SeqCst);
+        // Returns right away since shutdown was requested.
+        serve(
+            &listener,
+            None,
+            &pool,
+            &flags,
+            &registry,
+            Path::new("unused.yaml"),
+        )
+        .unwrap();
+
+        let mut registry = registry.lock().unwrap();
+        registry.stop_all();
+        assert!(!registry
+            .processes("sleep")
+            .iter_mut()
+            .any(|p| p.is_running()));
+    }
+
+    #[test]
+    fn notifies_webhook_of_daemon_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config: Config = format!(
+            "taskmasterd:\n  webhook_url: http://{}/hook\n  webhook_events: [daemon_stop]\n",
+            listener.local_addr().unwrap()
+        )
+        .parse()
+        .unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 4096];
+            let bytes = stream.read(&mut buf).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&buf[..bytes]).into_owned()
+        });
+
+        let webhook = config.taskmasterd.webhook().unwrap();
+        // Not subscribed to this one, so the endpoint only ever gets the next.
+        webhook.notify(webhook::Event::DaemonStart, None, "taskmasterd started");
+        webhook.notify_blocking(webhook::Event::DaemonStop, None, "taskmasterd stopped");
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1"), "{}", request);
+        assert!(request.contains(r#""event":"daemon_stop""#), "{}", request);
+    }
+
+    #[test]
+    fn shutdown_command_ends_accept_loop() {
+        let registry = Arc::new(Mutex::new(Registry::new(Config::default())));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let pool = ThreadPool::new(1).unwrap();
+            let flags = SignalFlags::default();
+            serve(
+                &listener,
+                None,
+                &pool,
+                &flags,
+                &registry,
+                Path::new("unused.yaml"),
+            )
+            .unwrap();
+            flags.shutdown.load(Ordering::SeqCst)
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(
+                serde_json::to_string(&Command::Shutdown)
+                    .unwrap()
+                    .as_bytes(),
+            )
+            .unwrap();
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Response>(&answer).unwrap(),
+            Response::Ok("Shutting down".into())
+        );
+        assert!(server.join().unwrap());
+    }
+
+    #[test]
+    fn daemonizes_unless_nodaemon() {
+        assert!(should_daemonize(&Taskmasterd::default()));
+        let config: Config = "taskmasterd:\n  nodaemon: true\n  silent: true\n"
+            .parse()
+            .unwrap();
+        assert!(!should_daemonize(&config.taskmasterd));
+    }
+
+    #[test]
+    fn reads_large_command() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Far more than a socket buffer holds, so it takes many writes and reads.
+        let names = (0..20_000)
+            .map(|i| format!("program_{:08}", i))
+            .collect::<Vec<_>>();
+        let command = Command::Start(names.clone());
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let message = serde_json::to_string(&Command::Start(names)).unwrap();
+            assert!(message.len() > 256 * 1024);
+            stream.write_all(message.as_bytes()).unwrap();
+            // Kept open, like a client waiting for the answer.
+            stream
+        });
+        let (stream, _) = listener.accept().unwrap();
+        assert_eq!(read_command(&stream).unwrap(), command);
+        drop(client.join().unwrap());
+
+        let truncated = &br#"{"Start":["web"#[..];
+        assert_eq!(
+            read_command(truncated).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+
+        // A name that never ends.
+        let endless = (&br#"{"Start":[""#[..]).chain(io::repeat(b'a'));
+        let e = read_command(endless).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "command longer than 1048576 bytes");
+    }
+
+    #[test]
+    fn checks_config() {
+        let dir = crate::temp_dir("check-config");
+        let check = |name: &str, yaml: &str| {
+            let path = dir.join(name);
+            fs::write(&path, yaml).unwrap();
+            check_config(&Options {
+                config: Some(path),
+                ..Options::default()
+            })
+        };
+
+        assert_eq!(
+            check("valid.yaml", "programs:\n  web:\n    command: /bin/true\n"),
+            Ok(())
+        );
+        let errors = check(
+            "invalid.yaml",
+            "programs:\n  web:\n    command: /bin/true\n    process_name: '%(nope)s'\n  \
+             api:\n    command: '/bin/echo %(process_num)d'\n",
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("web: "), "{}", errors[0]);
+        let errors = check("empty.yaml", "programs:\n  web:\n    command: ''\n").unwrap_err();
+        assert!(
+            errors[0].contains("`command` must not be empty"),
+            "{:?}",
+            errors
+        );
+        let errors = check(
+            "owner.yaml",
+            "unix_http_server:\n  file: /tmp/taskmaster.sock\n  chown: no_such_user_tm\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec!["unix_http_server: chown: Unknown user `no_such_user_tm`"]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn listener_survives_exec() {
+        let cloexec = |fd| unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC != 0;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = listener.as_raw_fd();
+        assert!(cloexec(fd));
+        process::set_cloexec(fd, false).unwrap();
+        assert!(!cloexec(fd));
+        process::set_cloexec(fd, true).unwrap();
+        assert!(cloexec(fd));
+        assert!(process::set_cloexec(-1, false).is_err());
+    }
+
+    #[test]
+    fn daemon_output_follows_logfile() {
+        let daemon = |yaml: &str| yaml.parse::<Config>().unwrap().taskmasterd;
+
+        let config = daemon("taskmasterd:\n  logfile: /var/log/tm.log\n");
+        assert_eq!(daemon_output(&config), Some(Path::new("/var/log/tm.log")));
+        assert_eq!(
+            daemon_output(&Taskmasterd::default()),
+            Some(Path::new("taskmasterd.log"))
+        );
+        let config = daemon("taskmasterd:\n  logfile: /var/log/tm.log\n  silent: true\n");
+        assert_eq!(daemon_output(&config), None);
+        let config = daemon("taskmasterd:\n  nodaemon: true\n");
+        assert_eq!(daemon_output(&config), None);
+    }
+
+    #[test]
+    fn raises_limits() {
+        // Already satisfied.
+        raise_limit(Limit::Files, 0).unwrap();
+        raise_limit(Limit::Processes, 1).unwrap();
+
+        let e = raise_limit(Limit::Files, u64::MAX - 1);
+        if users::get_effective_uid() != 0 {
+            assert!(e.unwrap_err().starts_with("`minfds` is"));
+        }
+    }
+
+    #[test]
+    fn parses_options() {
+        let args = |args: &[&str]| {
+            Options::from_args(&args.iter().map(ToString::to_string).collect::<Vec<_>>())
+        };
+        assert_eq!(args(&[]), Ok(Options::default()));
+        assert_eq!(
+            args(&["-n", "--silent", "-c", "taskmaster.yaml"]),
+            Ok(Options {
+                nodaemon: true,
+                config: Some("taskmaster.yaml".into()),
+                silent: true,
+            })
+        );
+        assert_eq!(
+            args(&["--nodaemon", "-s"]),
+            Ok(Options {
+                nodaemon: true,
+                config: None,
+                silent: true,
+            })
+        );
+        assert!(args(&["-c"]).is_err());
+        assert!(args(&["--daemon"]).is_err());
+    }
+
+    #[test]
+    fn listens_on_configured_port() {
+        let config: Config = "
+inet_http_server:
+  port: 127.0.0.1:9999
+"
+        .parse()
+        .unwrap();
+        assert_eq!(listen_addr(&config), Ok("127.0.0.1:9999".parse().unwrap()));
+        assert_eq!(
+            listen_addr(&Config::default()),
+            Ok(DEFAULT_ADDR.parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn reload_applies_diff() {
+        let path = crate::temp_path("reload").with_extension("yaml");
+        let old = "
+programs:
+  kept:
+    command: sleep 100
+  removed:
+    command: sleep 100
+";
+        let registry = Mutex::new(Registry::new(old.parse().unwrap()));
+        let kept_pid = registry.lock().unwrap().processes("kept")[0].id();
+
+        fs::write(
+            &path,
+            "
+programs:
+  kept:
+    command: sleep 100
+  added:
+    command: sleep 100
+",
+        )
+        .unwrap();
+        let diff = reload(&registry, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(diff.added.contains("added"));
+        assert!(diff.removed.contains("removed"));
+
+        let mut registry = registry.lock().unwrap();
+        assert_eq!(registry.processes("kept")[0].id(), kept_pid);
+        assert!(registry.processes("removed").is_empty());
+        assert!(registry.processes("added")[0].is_running());
+        registry.stop_all();
+    }
+
+    #[test]
+    fn update_keeps_unchanged_programs() {
+        let path = crate::temp_path("update").with_extension("yaml");
+        let registry = Mutex::new(Registry::new(
+            "
+programs:
+  a:
+    command: sleep 100
+  b:
+    command: sleep 100
+"
+            .parse()
+            .unwrap(),
+        ));
+        let running = |name: &str| {
+            let mut registry = registry.lock().unwrap();
+            let process = &registry.processes(name)[0];
+            (process.id(), process.started())
+        };
+        let (a, b) = (running("a"), running("b"));
+
+        fs::write(
+            &path,
+            "
+programs:
+  a:
+    command: sleep 100
+  b:
+    command: sleep 200
+",
+        )
+        .unwrap();
+        assert_eq!(
+            reread(&registry, &path).unwrap(),
+            Response::Ok("b: changed".into())
+        );
+        // `b` is left alone unless asked for.
+        assert_eq!(
+            update(&registry, &path, &["a".to_string()]).unwrap(),
+            Response::Ok("No config updates to processes".into())
+        );
+        assert_eq!(running("b"), b);
+        assert!(matches!(
+            update(&registry, &path, &["nope".to_string()]).unwrap(),
+            Response::Error(_)
+        ));
+
+        assert_eq!(
+            update(&registry, &path, &["all".to_string()]).unwrap(),
+            Response::Ok("b: updated process group".into())
+        );
+        fs::remove_file(&path).unwrap();
+        assert_eq!(running("a"), a);
+        assert_ne!(running("b").0, b.0);
+        registry.lock().unwrap().stop_all();
+    }
+
+    #[test]
+    fn limits_update_to_listeners_and_fcgi_programs() {
+        let old: Config = "
+fcgi_programs:
+  php:
+    command: php-cgi
+    socket: tcp://localhost:9002
+eventlisteners:
+  mem:
+    command: memmon
+    events: [PROCESS_STATE]
+"
+        .parse()
+        .unwrap();
+        let changed = "
+fcgi_programs:
+  php:
+    command: php-cgi
+    socket: tcp://localhost:9003
+eventlisteners:
+  mem:
+    command: memmon -a 200MB
+    events: [PROCESS_STATE]
+";
+
+        let mut new: Config = changed.parse().unwrap();
+        limit_update(&mut new, &old, &["mem".to_string()]).unwrap();
+        assert_eq!(new.fcgi_programs, old.fcgi_programs);
+        assert_ne!(new.eventlisteners, old.eventlisteners);
+        let mut new: Config = changed.parse().unwrap();
+        limit_update(&mut new, &old, &["php".to_string()]).unwrap();
+        assert_ne!(new.fcgi_programs, old.fcgi_programs);
+        assert_eq!(new.eventlisteners, old.eventlisteners);
+    }
+
+    #[test]
+    fn add_new_program() {
+        let path = crate::temp_path("add").with_extension("yaml");
+        let registry = Mutex::new(Registry::new(
+            "programs:\n  old:\n    command: sleep 100\n"
+                .parse()
+                .unwrap(),
+        ));
+        fs::write(
+            &path,
+            "
+programs:
+  old:
+    command: sleep 100
+  new:
+    command: sleep 100
+  manual:
+    command: sleep 100
+    autostart: false
+",
+        )
+        .unwrap();
+
+        let names = vec!["new".to_string(), "manual".to_string()];
+        assert_eq!(
+            add(&registry, &path, &names).unwrap(),
+            Response::Ok("new: added process group\nmanual: added process group".into())
+        );
+        {
+            let mut registry = registry.lock().unwrap();
+            assert!(registry.processes("new")[0].is_running());
+            assert!(registry.processes("manual").is_empty());
+            assert!(registry.config().programs.contains_key("manual"));
+        }
+
+        assert_eq!(
+            add(&registry, &path, &["old".to_string()]).unwrap(),
+            Response::Error("old: ERROR (already active)".into())
+        );
+        assert_eq!(
+            add(&registry, &path, &["nope".to_string()]).unwrap(),
+            Response::Error("nope: ERROR (no such process)".into())
+        );
+        fs::remove_file(&path).unwrap();
+        registry.lock().unwrap().stop_all();
+    }
+
+    #[test]
+    fn remove_stopped_program() {
+        let config: Config = "
+programs:
+  running:
+    command: sleep 100
+  stopped:
+    command: sleep 100
+group:
+  app:
+    programs: running,stopped
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        execute(&Command::Stop(vec!["stopped".into()]), &registry).unwrap();
+
+        let answer = execute(&Command::Remove(vec!["stopped".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Ok("stopped: removed process group".into())
+        );
+        {
+            let registry = registry.lock().unwrap();
+            assert!(!registry.config().programs.contains_key("stopped"));
+            assert_eq!(registry.config().group["app"].programs, ["running"]);
+        }
+
+        let names = vec!["running".into(), "app".into(), "nope".into()];
+        let answer = execute(&Command::Remove(names), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Error(
+                "running: ERROR (still running, stop it first)
+app: ERROR (still running, stop it first)
+nope: ERROR (no such process)"
+                    .into()
+            )
+        );
+        let mut registry = registry.lock().unwrap();
+        assert!(registry.processes("running")[0].is_running());
+        registry.stop_all();
+    }
+
+    #[test]
+    fn clear_logs() {
+        let path = crate::temp_path("clear-logs");
+        let config: Config = format!(
+            "
+programs:
+  web:
+    command: 'true'
+    autostart: false
+    stdout_logfile: {}
+",
+            path.display()
+        )
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        let backup = |i| PathBuf::from(format!("{}.{}", path.display(), i));
+        fs::write(&path, "hello\n").unwrap();
+        fs::write(backup(1), "older\n").unwrap();
+        fs::write(backup(2), "oldest\n").unwrap();
+
+        let answer = execute(&Command::Clear(vec!["web".into()]), &registry).unwrap();
+        assert_eq!(answer, Response::Ok("web: cleared".into()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(!backup(1).exists());
+        assert!(!backup(2).exists());
+
+        let answer = execute(&Command::Clear(vec!["nope".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Error("nope: ERROR (no such process)".into())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn restart_stops_then_starts() {
+        let config: Config = "
+programs:
+  web:
+    command: sleep 100
+  db:
+    command: sleep 100
+    priority: 1
+  manual:
+    command: sleep 100
+    autostart: false
+group:
+  app:
+    programs: web,db
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        let pid = |name: &str| registry.lock().unwrap().processes(name)[0].id();
+        let (web, db) = (pid("web"), pid("db"));
+
+        let names = vec!["app".into(), "manual".into()];
+        let answer = execute(&Command::Restart(names), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Ok("app: restarted\nmanual: restarted".into())
+        );
+        assert_ne!(pid("web"), web);
+        assert_ne!(pid("db"), db);
+        let answer = execute(&Command::Restart(vec!["nope".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Error("nope: ERROR (no such process)".into())
+        );
+
+        let mut registry = registry.lock().unwrap();
+        for name in &["web", "db", "manual"] {
+            assert!(registry.processes(name)[0].is_running(), "{}", name);
+        }
+        assert_eq!(registry.reset_counters("web").unwrap().restarts, 1);
+        registry.stop_all();
+    }
+
+    #[test]
+    fn idle_connection_is_dropped() {
+        let config: Config = "
+taskmasterd:
+  command_timeout: 1
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+
+        // The client never sends anything: the handler gives up instead of blocking forever.
+        let start = std::time::Instant::now();
+        handle_connection(
+            server_side,
+            &registry,
+            &SignalFlags::default(),
+            Path::new("taskmaster.yaml"),
+        )
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        drop(client);
+    }
+
+    #[test]
+    fn authenticates_clients() {
+        let config: Config = "
+inet_http_server:
+  port: 127.0.0.1:0
+  username: admin
+  password: '{SHA}e5e9fa1ba31ecd1ae84f75caaa474f3a663f05f4'
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let send = |credentials: Option<(&str, &str)>| {
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            if let Some((username, password)) = credentials {
+                let credentials = Command::Authenticate(Credentials {
+                    username: username.into(),
+                    password: password.into(),
+                });
+                client
+                    .write_all(serde_json::to_string(&credentials).unwrap().as_bytes())
+                    .unwrap();
+            }
+            client
+                .write_all(serde_json::to_string(&Command::Version).unwrap().as_bytes())
+                .unwrap();
+            let (server_side, _) = listener.accept().unwrap();
+            handle_connection(
+                server_side,
+                &registry,
+                &SignalFlags::default(),
+                Path::new("taskmaster.yaml"),
+            )
+            .unwrap();
+            let mut answer = serde_json::Deserializer::from_reader(client);
+            Response::deserialize(&mut answer).unwrap()
+        };
+
+        let refused = Response::Error(
+            "Authentication failed: check the username and password of taskmasterctl".into(),
+        );
+        assert_eq!(send(None), refused);
+        assert_eq!(send(Some(("admin", "wrong"))), refused);
+        assert_eq!(send(Some(("root", "secret"))), refused);
+        assert!(matches!(
+            send(Some(("admin", "secret"))),
+            Response::Ok(version) if version.contains(env!("CARGO_PKG_VERSION"))
+        ));
+    }
+
+    #[test]
+    fn logs_commands() {
+        use crate::config::LogLevel;
+
+        #[derive(Clone)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let log = Shared(Arc::new(Mutex::new(Vec::new())));
+        logger::redirect(Box::new(log.clone()), LogLevel::Debug);
+        let registry = Mutex::new(Registry::new(Config::default()));
+        let (mut client, server_side) = UnixStream::pair().unwrap();
+        let credentials = Credentials {
+            username: "admin".into(),
+            password: "hunter2".into(),
+        };
+        // Sent twice, so that the second one is logged as the command.
+        for command in &[
+            Command::Authenticate(credentials.clone()),
+            Command::Authenticate(credentials),
+        ] {
+            client
+                .write_all(serde_json::to_string(command).unwrap().as_bytes())
+                .unwrap();
+        }
+        handle_connection(
+            server_side,
+            &registry,
+            &SignalFlags::default(),
+            Path::new("taskmaster.yaml"),
+        )
+        .unwrap();
+        let (mut client, server_side) = UnixStream::pair().unwrap();
+        let start = Command::Start(vec!["web".into(), "db".into()]);
+        client
+            .write_all(serde_json::to_string(&start).unwrap().as_bytes())
+            .unwrap();
+        handle_connection(
+            server_side,
+            &registry,
+            &SignalFlags::default(),
+            Path::new("taskmaster.yaml"),
+        )
+        .unwrap();
+        logger::redirect(Box::new(io::stderr()), LogLevel::default());
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let received = log
+            .lines()
+            .find(|line| line.contains("INFO Received Start"))
+            .unwrap_or_else(|| panic!("{}", log));
+        assert!(received.contains("\"web\"") && received.contains("\"db\""));
+        assert!(log.contains("DEBUG Answered Start"), "{}", log);
+        assert!(log.contains("INFO Received Authenticate"), "{}", log);
+        assert!(!log.contains("hunter2"), "{}", log);
+    }
+
+    #[test]
+    fn serves_unix_socket() {
+        let path = crate::temp_path("unix-server").with_extension("sock");
+        // Giving the file to its own user needs no privileges.
+        let user = users::get_current_username().unwrap();
+        let config: Config = format!(
+            "unix_http_server:\n  file: {}\n  chmod: \"0660\"\n  chown: {}\n",
+            path.display(),
+            user.to_string_lossy()
+        )
+        .parse()
+        .unwrap();
+        let socket = UnixSocket::bind(config.unix_http_server.as_ref().unwrap()).unwrap();
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o7777, 0o660);
+        assert_eq!(
+            std::os::unix::fs::MetadataExt::uid(&meta),
+            get_current_uid()
+        );
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(serde_json::to_string(&Command::Version).unwrap().as_bytes())
+            .unwrap();
+        let (server_side, _) = socket.listener.accept().unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        handle_connection(
+            server_side,
+            &registry,
+            &SignalFlags::default(),
+            Path::new("taskmaster.yaml"),
+        )
+        .unwrap();
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).unwrap();
+        assert!(answer.contains("taskmasterd"), "{}", answer);
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn tail_log() {
+        let path = crate::temp_path("tail-log");
+        let config: Config = format!(
+            "
+programs:
+  echo:
+    command: echo hello
+    stdout_logfile: {}
+",
+            path.display()
+        )
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        let tail = |follow| Command::Tail {
+            name: "echo".into(),
+            stream: crate::command::Stream::Stdout,
+            follow,
+        };
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while execute(&tail(false), &registry).unwrap() != Response::Ok("hello\n".into()) {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "output was not logged"
+            );
+            thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+        let answer = execute(
+            &Command::Tail {
+                name: "echo".into(),
+                stream: crate::command::Stream::Stderr,
+                follow: false,
+            },
+            &registry,
+        )
+        .unwrap();
+        assert_eq!(answer, Response::Error("echo: ERROR (no log file)".into()));
+
+        // Following: what's already there, then what gets appended.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        client
+            .write_all(serde_json::to_string(&tail(true)).unwrap().as_bytes())
+            .unwrap();
+        let handler = thread::spawn(move || {
+            handle_connection(
+                server_side,
+                &registry,
+                &SignalFlags::default(),
+                Path::new("taskmaster.yaml"),
+            )
+        });
+
+        let mut buf = [0; 6];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello\n");
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"more\n")
+            .unwrap();
+        let mut buf = [0; 5];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"more\n");
+
+        // Hanging up ends the stream.
+        drop(client);
+        handler.join().unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn maintail() {
+        let path = crate::temp_path("main");
+        fs::write(&path, "taskmasterd started\n").unwrap();
+        let config: Config = format!("taskmasterd:\n  logfile: {}\n", path.display())
+            .parse()
+            .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        let answer = execute(&Command::MainTail { follow: false }, &registry).unwrap();
+        assert_eq!(answer, Response::Ok("taskmasterd started\n".into()));
+
+        fs::remove_file(&path).unwrap();
+        let answer = execute(&Command::MainTail { follow: false }, &registry).unwrap();
+        assert!(matches!(answer, Response::Error(_)));
+    }
+
+    #[test]
+    fn instances_named_after_process_name() {
+        let config: Config = "
+programs:
+  web:
+    command: sleep 100
+    numprocs: 2
+    process_name: '%(program_name)s_%(process_num)02d'
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+
+        let names = registry
+            .lock()
+            .unwrap()
+            .processes("web")
+            .iter()
+            .map(|process| process.name().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["web_00", "web_01"]);
+
+        registry.lock().unwrap().stop_all();
+    }
+
+    #[test]
+    fn start_and_stop_group() {
+        let config: Config = "
+programs:
+  first:
+    command: sleep 100
+  second:
+    command: sleep 100
+group:
+  both:
+    programs: first,second
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+
+        let answer = execute(&Command::Stop(vec!["both".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Ok("second: stopped\nfirst: stopped".into())
+        );
+
+        let answer = execute(&Command::Start(vec!["both".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Ok("first: started\nsecond: started".into())
+        );
+        {
+            let mut registry = registry.lock().unwrap();
+            assert!(registry.processes("first")[0].is_running());
+            assert!(registry.processes("second")[0].is_running());
+        }
+
+        let answer = execute(
+            &Command::Status(vec!["both".into(), "nope".into()]),
+            &registry,
+        )
+        .unwrap();
+        match answer {
+            Response::Status(reports, counts) => {
+                assert!(counts.is_empty());
+                assert_eq!(reports.len(), 3);
+                assert!(reports[..2]
+                    .iter()
+                    .all(|report| report.as_ref().unwrap().state
+                        == crate::response::ProcessState::Running));
+                assert_eq!(reports[2], Err("nope: ERROR (no such process)".into()));
+            }
+            answer => panic!("unexpected answer: {:?}", answer),
+        }
+        // Asking for all of them explicitly counts their states too.
+        match execute(&Command::Status(vec!["all".into()]), &registry).unwrap() {
+            Response::Status(reports, counts) => {
+                assert_eq!(reports.len(), 2);
+                assert_eq!(counts, [(crate::response::ProcessState::Running, 2)]);
+            }
+            answer => panic!("unexpected answer: {:?}", answer),
+        }
+
+        registry.lock().unwrap().stop_all();
+    }
+
+    #[test]
+    fn reload_restarts_everything() {
+        let path = crate::temp_path("reload-all").with_extension("yaml");
+        fs::write(&path, "programs:\n  sleep:\n    command: sleep 100\n").unwrap();
+        let registry = Mutex::new(Registry::new(Config::from_path(&path).unwrap()));
+        let old_pid = registry.lock().unwrap().processes("sleep")[0].id();
+
+        fs::write(
+            &path,
+            "programs:\n  sleep:\n    command: sleep 100\n  other:\n    command: sleep 100\n",
+        )
+        .unwrap();
+        restart(&registry, &path).unwrap();
+        {
+            let mut registry = registry.lock().unwrap();
+            // Restarted even though its config did not change.
+            let sleep = &mut registry.processes("sleep")[0];
+            assert!(sleep.is_running());
+            assert_ne!(sleep.id(), old_pid);
+            assert!(registry.processes("other")[0].is_running());
+        }
+
+        // An invalid config leaves everything running.
+        fs::write(&path, "programs:\n  sleep:\n    command: ''\n").unwrap();
+        assert!(restart(&registry, &path).is_err());
+        assert!(registry.lock().unwrap().processes("other")[0].is_running());
+
+        registry.lock().unwrap().stop_all();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn start_and_stop_all() {
+        let config: Config = "
+programs:
+  web:
+    command: sleep 100
+    priority: 10
+  db:
+    command: sleep 100
+    priority: 1
+  cache:
+    command: sleep 100
+    priority: 5
+group:
+  app:
+    programs: web,cache
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+
+        let answer = execute(&Command::Stop(vec!["all".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Ok("web: stopped\ncache: stopped\ndb: stopped".into())
+        );
+        {
+            let mut registry = registry.lock().unwrap();
+            for name in registry.program_names() {
+                assert!(!registry.processes(&name)[0].is_running());
+            }
+        }
+
+        let answer = execute(&Command::Start(vec!["all".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Ok("db: started\ncache: started\nweb: started".into())
+        );
+
+        let answer = execute(&Command::Stop(vec!["app:*".into()]), &registry).unwrap();
+        assert_eq!(answer, Response::Ok("web: stopped\ncache: stopped".into()));
+        assert!(registry.lock().unwrap().processes("db")[0].is_running());
+
+        registry.lock().unwrap().stop_all();
+    }
+
+    #[test]
+    fn starts_one_priority_at_a_time() {
+        let config: Config = "
+programs:
+  db:
+    command: sleep 100
+    autostart: false
+    priority: 1
+    startsecs: 1
+  broken:
+    command: 'false'
+    autostart: false
+    priority: 1
+    startsecs: 1
+    startretries: 0
+  web:
+    command: sleep 100
+    autostart: false
+    priority: 2
+    startsecs: 0
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+
+        let answer = execute(&Command::Start(vec!["all".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Error("broken: ERROR (spawn error)\ndb: started\nweb: started".into())
+        );
+        let mut registry = registry.lock().unwrap();
+        let db = registry.processes("db")[0].started();
+        let web = registry.processes("web")[0].started();
+        assert!(web.duration_since(db) >= Duration::from_secs(1));
+        assert_eq!(
+            registry.status("broken")[0].state,
+            response::ProcessState::Fatal
+        );
+        registry.stop_all();
+    }
+
+    #[test]
+    fn answers_stop_before_the_client_gives_up() {
+        // Ignores SIGTERM, so that only the SIGKILL after `stopwaitsecs` stops it.
+        let config: Config = "
+taskmasterctl:
+  timeout: 1
+programs:
+  stubborn:
+    command: \"/bin/sh -c trap${IFS}''${IFS}TERM;exec${IFS}sleep${IFS}100\"
+    stopwaitsecs: 2
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        let ignores_term = || {
+            let pid = registry.lock().unwrap().processes("stubborn")[0].id();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap() != "sleep\n" {
+                assert!(Instant::now() < deadline, "the trap was never set");
+                thread::sleep(Duration::from_millis(20));
+            }
+            pid
+        };
+        let reap_while_stopping = || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while registry.lock().unwrap().is_stopping("stubborn") {
+                assert!(Instant::now() < deadline, "stubborn was never killed");
+                registry.lock().unwrap().reap();
+                thread::sleep(REAP_INTERVAL);
+            }
+        };
+        let state = || registry.lock().unwrap().status("stubborn")[0].state;
+
+        ignores_term();
+        let asked = Instant::now();
+        let answer = execute(&Command::Stop(vec!["stubborn".into()]), &registry).unwrap();
+        assert_eq!(answer, Response::Ok("stubborn: stopping".into()));
+        assert!(asked.elapsed() < Duration::from_secs(1));
+        assert_eq!(state(), crate::response::ProcessState::Stopping);
+        // Left to the reaper.
+        reap_while_stopping();
+        assert!(asked.elapsed() >= Duration::from_secs(2));
+        assert_eq!(state(), crate::response::ProcessState::Stopped);
+
+        // Nothing to wait for.
+        let answer = execute(&Command::Restart(vec!["stubborn".into()]), &registry).unwrap();
+        assert_eq!(answer, Response::Ok("stubborn: restarted".into()));
+        let pid = ignores_term();
+        let answer = execute(&Command::Restart(vec!["stubborn".into()]), &registry).unwrap();
+        assert_eq!(answer, Response::Ok("stubborn: restarting".into()));
+        reap_while_stopping();
+        registry.lock().unwrap().reap();
+        assert_eq!(state(), crate::response::ProcessState::Running);
+        assert_ne!(registry.lock().unwrap().processes("stubborn")[0].id(), pid);
+        registry.lock().unwrap().stop_all();
+    }
+
+    #[test]
+    fn answers_start_before_the_client_gives_up() {
+        let config: Config = "
+taskmasterctl:
+  timeout: 1
+programs:
+  db:
+    command: sleep 100
+    autostart: false
+    priority: 1
+    startsecs: 2
+  web:
+    command: sleep 100
+    autostart: false
+    priority: 2
+    startsecs: 0
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+
+        let asked = Instant::now();
+        let answer = execute(&Command::Start(vec!["all".into()]), &registry).unwrap();
+        assert_eq!(answer, Response::Ok("db: starting\nweb: queued".into()));
+        assert!(asked.elapsed() < Duration::from_secs(1));
+
+        // Left to the reaper.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut registry = registry.lock().unwrap();
+        while registry.processes("web").is_empty() {
+            assert!(Instant::now() < deadline, "web was never started");
+            registry.reap();
+            thread::sleep(REAP_INTERVAL);
+        }
+        let db = registry.processes("db")[0].started();
+        let web = registry.processes("web")[0].started();
+        assert!(web.duration_since(db) >= Duration::from_secs(2));
+        registry.stop_all();
+    }
+
+    #[test]
+    fn reaps_exited_processes() {
+        let config: Config = "
+programs:
+  short:
+    command: 'false'
+    autorestart: never
+    startsecs: 0
+  long:
+    command: sleep 100
+"
+        .parse()
+        .unwrap();
+        let registry = Arc::new(Mutex::new(Registry::new(config)));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let reaper = spawn_reaper(Arc::clone(&registry), Arc::clone(&shutdown)).unwrap();
+
+        let state = |name: &str| registry.lock().unwrap().status(name)[0].state;
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while registry.lock().unwrap().processes("short")[0]
+            .exit_status()
+            .is_none()
+        {
+            assert!(std::time::Instant::now() < deadline, "never reaped");
+            thread::sleep(REAP_INTERVAL);
+        }
+        assert_eq!(state("short"), crate::response::ProcessState::Exited);
+        assert_eq!(state("long"), crate::response::ProcessState::Running);
+        let answer = execute(&Command::ResetCounters(vec!["short".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Ok("short: counters reset (were restarts 0, retries 0, exits [1])".into())
+        );
+
+        shutdown.store(true, Ordering::SeqCst);
+        reaper.join().unwrap();
+        let mut registry = registry.lock().unwrap();
+        registry.stop_all();
+        assert_eq!(
+            registry.status("long")[0].state,
+            crate::response::ProcessState::Stopped
+        );
+    }
+
+    #[test]
+    fn autostart_and_autorestart() {
+        let config: Config = "
+programs:
+  manual:
+    command: sleep 100
+    autostart: false
+  flapping:
+    command: 'false'
+    autorestart: always
+    startsecs: 0
+  done:
+    command: 'true'
+    startsecs: 0
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        assert!(registry.lock().unwrap().processes("manual").is_empty());
+
+        let restarts = || {
+            registry
+                .lock()
+                .unwrap()
+                .reset_counters("flapping")
+                .unwrap()
+                .restarts
+        };
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let mut total = 0;
+        while total < 2 {
+            assert!(std::time::Instant::now() < deadline, "never restarted");
+            registry.lock().unwrap().reap();
+            total += restarts();
+            thread::sleep(REAP_INTERVAL);
+        }
+
+        // Exited successfully, which `unexpected` doesn't restart.
+        let mut registry = registry.lock().unwrap();
+        registry.reap();
+        assert_eq!(registry.reset_counters("done").unwrap().restarts, 0);
+        registry.stop_all();
+    }
+
+    #[test]
+    fn pid() {
+        let config: Config = "
+programs:
+  sleep:
+    command: sleep 100
+  stopped:
+    command: sleep 100
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        assert_eq!(
+            execute(&Command::Stop(vec!["stopped".into()]), &registry).unwrap(),
+            Response::Ok("stopped: stopped".into())
+        );
+
+        let answer = execute(&Command::PID(vec![]), &registry).unwrap();
+        let expected = vec![("taskmasterd".to_string(), Ok(std::process::id()))];
+        assert_eq!(answer, Response::Pid(expected.into_iter().collect()));
+
+        let sleep_pid = registry.lock().unwrap().processes("sleep")[0].id();
+        let names = vec!["sleep".into(), "stopped".into(), "nope".into()];
+        let answer = execute(&Command::PID(names), &registry).unwrap();
+        let expected = vec![
+            ("sleep".to_string(), Ok(sleep_pid)),
+            ("stopped".to_string(), Err("not running".to_string())),
+            ("nope".to_string(), Err("no such process".to_string())),
+        ];
+        assert_eq!(answer, Response::Pid(expected.into_iter().collect()));
+
+        registry.lock().unwrap().stop_all();
+    }
+
+    #[test]
+    fn signal_without_stopping() {
+        let config: Config = "
+programs:
+  sleep:
+    command: sleep 100
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+
+        let signal = |signal, name: &str| Command::Signal {
+            signal,
+            names: vec![name.into()],
+        };
+        // sleep doesn't handle any signal, so check delivery with one that ends it.
+        let answer = execute(&signal(config::Signal::KILL, "sleep"), &registry).unwrap();
+        assert_eq!(answer, Response::Ok("sleep: signalled SIGKILL".into()));
+        let answer = execute(&signal(config::Signal::HUP, "nope"), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Error("nope: ERROR (no such process)".into())
+        );
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while registry.lock().unwrap().processes("sleep")[0].is_running() {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "signal was not delivered"
+            );
+            thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+        let answer = execute(&signal(config::Signal::HUP, "sleep"), &registry).unwrap();
+        assert_eq!(answer, Response::Error("sleep: ERROR (not running)".into()));
+    }
+
+    #[test]
+    fn reset_counters_keeps_process_running() {
+        let config: Config = "
+programs:
+  sleep:
+    command: sleep 100
+"
+        .parse()
+        .unwrap();
+        let registry = Mutex::new(Registry::new(config));
+        execute(&Command::Stop(vec!["sleep".into()]), &registry).unwrap();
+        execute(&Command::Start(vec!["sleep".into()]), &registry).unwrap();
+
+        let answer = execute(&Command::ResetCounters(vec!["sleep".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Ok(
+                "sleep: counters reset (were restarts 1, retries 0, exits [signal 15])".into()
+            )
+        );
+        {
+            let mut registry = registry.lock().unwrap();
+            assert_eq!(registry.reset_counters("sleep"), Ok(Default::default()));
+            assert!(registry.processes("sleep")[0].is_running());
+        }
+
+        let answer = execute(&Command::ResetCounters(vec!["nope".into()]), &registry).unwrap();
+        assert_eq!(
+            answer,
+            Response::Error("nope: ERROR (no such process)".into())
+        );
+
+        registry.lock().unwrap().stop_all();
+    }
+}
@@ -0,0 +1,117 @@
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+/// Socket the local syslog daemon listens on.
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// `user` facility, the one meant for user-level programs.
+const FACILITY_USER: u8 = 1;
+
+/// Severity of the messages sent to syslog.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// Used for stderr.
+    Err = 3,
+    /// Used for stdout.
+    Info = 6,
+}
+
+/// Sends every line written to it to the local syslog daemon, tagged with a process name.
+///
+/// Partial lines are held back until their end is written.
+pub struct SyslogWriter {
+    socket: UnixDatagram,
+    tag: String,
+    severity: Severity,
+    /// Start of a line whose end hasn't been written yet.
+    pending: Vec<u8>,
+}
+
+impl SyslogWriter {
+    /// Connects to the local syslog daemon.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the syslog socket could not be reached.
+    pub fn new(tag: &str, severity: Severity) -> io::Result<Self> {
+        Self::connect(Path::new(SYSLOG_SOCKET), tag, severity)
+    }
+
+    fn connect(path: &Path, tag: &str, severity: Severity) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self {
+            socket,
+            tag: tag.to_string(),
+            severity,
+            pending: Vec::new(),
+        })
+    }
+
+    fn send(&self, line: &[u8]) -> io::Result<()> {
+        self.socket
+            .send(&format_line(self.severity, &self.tag, line))
+            .map(|_| ())
+    }
+}
+
+impl Write for SyslogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line = self.pending.drain(..=end).collect::<Vec<u8>>();
+            self.send(&line[..end])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogWriter {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            let _ = self.send(&self.pending);
+        }
+    }
+}
+
+/// Formats `line` as a syslog message from `tag`, like `<14>web: listening`.
+fn format_line(severity: Severity, tag: &str, line: &[u8]) -> Vec<u8> {
+    let priority = FACILITY_USER * 8 + severity as u8;
+    let mut message = format!("<{}>{}: ", priority, tag).into_bytes();
+    message.extend_from_slice(line);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_lines_with_process_name() {
+        assert_eq!(
+            format_line(Severity::Info, "web", b"hello"),
+            b"<14>web: hello"
+        );
+        assert_eq!(format_line(Severity::Err, "web", b"oops"), b"<11>web: oops");
+
+        let path = crate::temp_path("syslog");
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let mut writer = SyslogWriter::connect(&path, "web_00", Severity::Info).unwrap();
+        writer.write_all(b"first\nsec").unwrap();
+        writer.write_all(b"ond\n").unwrap();
+
+        let mut buf = [0; 64];
+        for expected in &["<14>web_00: first", "<14>web_00: second"] {
+            let bytes = server.recv(&mut buf).unwrap();
+            assert_eq!(std::str::from_utf8(&buf[..bytes]).unwrap(), *expected);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Values available to `%(name)s` expansions in templated config fields.
+#[derive(Debug, Default, Clone)]
+pub struct Context {
+    pub program_name: String,
+    pub process_num: u16,
+    pub group_name: String,
+    pub host_node_name: String,
+    /// Directory of the config file, if known.
+    pub here: Option<PathBuf>,
+}
+
+/// A value substituted into a template.
+enum Value {
+    Str(String),
+    Int(u64),
+}
+
+impl Context {
+    fn get(&self, key: &str) -> Option<Value> {
+        match key {
+            "program_name" => Some(Value::Str(self.program_name.clone())),
+            "process_num" => Some(Value::Int(u64::from(self.process_num))),
+            "group_name" => Some(Value::Str(self.group_name.clone())),
+            "host_node_name" => Some(Value::Str(self.host_node_name.clone())),
+            "here" => self
+                .here
+                .as_ref()
+                .map(|here| Value::Str(here.display().to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Name of the machine, as returned by `gethostname(2)` the first time it is asked for.
+pub fn host_node_name() -> String {
+    static HOST_NODE_NAME: OnceLock<String> = OnceLock::new();
+    HOST_NODE_NAME.get_or_init(gethostname).clone()
+}
+
+fn gethostname() -> String {
+    let mut buf = [0_u8; 256];
+    // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Renders a `process_name` template, for instance `%(program_name)s_%(process_num)02d`.
+///
+/// # Errors
+///
+/// Errors if the template is malformed or refers to an unknown key.
+pub fn render_process_name(template: &str, ctx: &Context) -> Result<String, String> {
+    render(template, ctx)
+}
+
+/// Substitutes every `%(key)<conversion>` token of `template` with its value in `ctx`.
+///
+/// Conversions follow Python's `%` formatting: `s` for strings, `d` for integers, with an
+/// optional width and `0` (zero-padding) or `-` (left-justify) flag, like `%(process_num)02d`.
+/// `%%` is a literal `%`, which never starts a token. Any other `%` that does not start a
+/// token is kept as is.
+pub fn render(template: &str, ctx: &Context) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('%') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let token = match after.strip_prefix('(') {
+            Some(token) => token,
+            None => {
+                rendered.push('%');
+                rest = after.strip_prefix('%').unwrap_or(after);
+                continue;
+            }
+        };
+
+        let end = token
+            .find(')')
+            .ok_or_else(|| format!("Unterminated expansion in `{}`", template))?;
+        let key = &token[..end];
+        let spec = &token[end + 1..];
+
+        let flags_len = spec.find(|c| c != '0' && c != '-').unwrap_or(spec.len());
+        let (flags, spec) = spec.split_at(flags_len);
+        let width_len = spec
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(spec.len());
+        let (width, spec) = spec.split_at(width_len);
+        let width = width.parse::<usize>().unwrap_or(0);
+        let conversion = spec
+            .chars()
+            .next()
+            .ok_or_else(|| format!("Missing conversion after %({}) in `{}`", key, template))?;
+
+        let value = ctx
+            .get(key)
+            .ok_or_else(|| format!("Unknown expansion %({}) in `{}`", key, template))?;
+        let value = match (conversion, value) {
+            ('s', Value::Str(s)) => s,
+            ('s', Value::Int(i)) | ('d', Value::Int(i)) => i.to_string(),
+            ('d', Value::Str(_)) => {
+                return Err(format!("%({})d expects a number in `{}`", key, template))
+            }
+            (other, _) => {
+                return Err(format!(
+                    "Unsupported conversion `{}` for %({}) in `{}`",
+                    other, key, template
+                ))
+            }
+        };
+
+        if flags.contains('-') {
+            rendered.push_str(&format!("{:<width$}", value, width = width));
+        } else if flags.contains('0') && conversion == 'd' {
+            rendered.push_str(&format!("{:0>width$}", value, width = width));
+        } else {
+            rendered.push_str(&format!("{:>width$}", value, width = width));
+        }
+
+        rest = &spec[conversion.len_utf8()..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Replaces the `%(key)s` tokens of `template` with `value`, leaving everything else, `%%`
+/// escapes included, for `render` to deal with.
+pub fn substitute(template: &str, key: &str, value: &str) -> String {
+    substitute_escaping(template, key, Some(value), "%%")
+}
+
+/// Like `substitute`, for the settings `render` never sees: `%%` escapes are collapsed into
+/// `%` too. Without a `value`, only they are.
+pub fn substitute_final(template: &str, key: &str, value: Option<&str>) -> String {
+    substitute_escaping(template, key, value, "%")
+}
+
+/// Replaces the `%(key)s` tokens of `template` with `value` if any, and `%%` escapes with
+/// `escape`.
+fn substitute_escaping(template: &str, key: &str, value: Option<&str>, escape: &str) -> String {
+    let token = format!("%({})s", key);
+    let mut substituted = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('%') {
+        substituted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with("%%") {
+            substituted.push_str(escape);
+            rest = &rest[2..];
+        } else if let (true, Some(value)) = (rest.starts_with(&token), value) {
+            substituted.push_str(value);
+            rest = &rest[token.len()..];
+        } else {
+            substituted.push('%');
+            rest = &rest[1..];
+        }
+    }
+    substituted.push_str(rest);
+    substituted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(program_name: &str, process_num: u16) -> Context {
+        Context {
+            program_name: program_name.into(),
+            process_num,
+            group_name: "grp".into(),
+            host_node_name: "host".into(),
+            here: Some(PathBuf::from("/etc/taskmaster")),
+        }
+    }
+
+    #[test]
+    fn padded_process_num() {
+        let names = (0..2)
+            .map(|num| render_process_name("%(program_name)s_%(process_num)02d", &ctx("web", num)))
+            .collect::<Result<Vec<String>, String>>()
+            .unwrap();
+        assert_eq!(names, vec!["web_00", "web_01"]);
+    }
+
+    #[test]
+    fn every_key() {
+        let rendered = render(
+            "%(program_name)s %(process_num)s %(group_name)s %(host_node_name)s %(here)s",
+            &ctx("web", 3),
+        )
+        .unwrap();
+        assert_eq!(rendered, "web 3 grp host /etc/taskmaster");
+    }
+
+    #[test]
+    fn host_node_name_in_command() {
+        let ctx = Context {
+            host_node_name: host_node_name(),
+            ..ctx("web", 0)
+        };
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+        assert_eq!(
+            render("ping %(host_node_name)s", &ctx).unwrap(),
+            format!("ping {}", hostname.trim_end())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(render("%(nope)s", &ctx("web", 0)).is_err());
+        assert!(render("%(program_name)d", &ctx("web", 0)).is_err());
+        assert!(render("%(program_name", &ctx("web", 0)).is_err());
+        assert!(render("%(here)s", &Context::default()).is_err());
+        assert_eq!(render("100%", &ctx("web", 0)).unwrap(), "100%");
+    }
+
+    #[test]
+    fn escapes() {
+        let ctx = ctx("web", 0);
+        assert_eq!(render("%%", &ctx).unwrap(), "%");
+        assert_eq!(render("%%(NOTVAR)s", &ctx).unwrap(), "%(NOTVAR)s");
+        assert_eq!(render("/first%%20name", &ctx).unwrap(), "/first%20name");
+        assert_eq!(
+            render(
+                "%(program_name)s at 100%%, %%%(process_num)d %%(here)s",
+                &ctx
+            )
+            .unwrap(),
+            "web at 100%, %0 %(here)s"
+        );
+
+        // Escapes survive substitution, to be collapsed when rendering.
+        let substituted = substitute("%(here)s/%%(here)s %(program_name)s", "here", "/etc");
+        assert_eq!(substituted, "/etc/%%(here)s %(program_name)s");
+        assert_eq!(render(&substituted, &ctx).unwrap(), "/etc/%(here)s web");
+
+        let substituted = substitute_final("%(here)s/100%%/%(program_name)s", "here", Some("/etc"));
+        assert_eq!(substituted, "/etc/100%/%(program_name)s");
+        assert_eq!(
+            substitute_final("a%%b/%(here)s", "here", None),
+            "a%b/%(here)s"
+        );
+    }
+}
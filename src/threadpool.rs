@@ -1,29 +1,48 @@
-use std::sync::{mpsc, Arc, Mutex};
+use std::any::Any;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
 use std::thread;
 
+/// Fixed set of worker threads running the jobs submitted to it.
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: mpsc::Sender<Message>,
+    /// Number of jobs submitted but not completed yet, signaled whenever it drops to 0.
+    pending: Arc<Pending>,
 }
 
+type Pending = (Mutex<usize>, Condvar);
+
 impl ThreadPool {
     /// Create a new `ThreadPool`.
     ///
     /// The size is the number of threads in the pool.
-    pub fn new(size: usize) -> Result<Self, &'static str> {
+    ///
+    /// # Errors
+    ///
+    /// Errors if `size` is 0, or if a worker thread failed to spawn.
+    pub fn new(size: usize) -> Result<Self, String> {
         if size < 1 {
-            return Err("Number of threads too small");
+            return Err("Number of threads too small".to_string());
         }
 
         let (sender, receiver) = mpsc::channel();
         let receiver = Arc::new(Mutex::new(receiver));
+        let pending = Arc::new((Mutex::new(0), Condvar::new()));
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            let worker = Worker::new(id, Arc::clone(&receiver), Arc::clone(&pending))
+                .map_err(|e| format!("Could not spawn worker #{}: {:?}", id, e))?;
+            workers.push(worker);
         }
 
-        Ok(Self { workers, sender })
+        Ok(Self {
+            workers,
+            sender,
+            pending,
+        })
     }
 
     pub fn execute<F>(&self, f: F)
@@ -32,8 +51,44 @@ impl ThreadPool {
     {
         let job = Box::new(f);
 
+        if let Ok(mut pending) = self.pending.0.lock() {
+            *pending += 1;
+        }
         if let Err(e) = self.sender.send(Message::NewJob(job)) {
-            eprintln!("Failed to send message: {:?}", e);
+            error!("Failed to send message: {:?}", e);
+            job_done(&self.pending);
+        }
+    }
+
+    /// Runs `f` on a worker, and sends back what it returns to the receiver.
+    ///
+    /// If `f` panics, the sender is dropped without sending anything.
+    pub fn execute_with_result<F, R>(&self, f: F) -> mpsc::Receiver<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.execute(move || {
+            // The caller may not be waiting for the result anymore.
+            let _ = sender.send(f());
+        });
+        receiver
+    }
+
+    /// Blocks until every job submitted so far has completed. The pool can still be used
+    /// afterwards.
+    pub fn join(&self) {
+        let (lock, idle) = &*self.pending;
+        let pending = match lock.lock() {
+            Ok(pending) => pending,
+            Err(e) => {
+                error!("Failed to acquire lock: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = idle.wait_while(pending, |pending| *pending > 0) {
+            error!("Failed to wait for jobs: {:?}", e);
         }
     }
 }
@@ -43,7 +98,7 @@ impl Drop for ThreadPool {
         // Iterate through the Workers once to make sure that they all receive a Terminate message.
         for _ in &self.workers {
             if let Err(e) = self.sender.send(Message::Terminate) {
-                eprintln!("Failed to send Terminate: {:?}", e);
+                error!("Failed to send Terminate: {:?}", e);
             }
         }
 
@@ -51,7 +106,7 @@ impl Drop for ThreadPool {
         for worker in &mut self.workers {
             if let Some(thread) = worker.thread.take() {
                 if let Err(e) = thread.join() {
-                    eprintln!("Worker #{}: {:?}", worker.id, e);
+                    error!("Worker #{}: {:?}", worker.id, e);
                 }
             }
         }
@@ -63,44 +118,142 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Self {
-        let thread = thread::spawn(move || loop {
-            let lock = match receiver.lock() {
-                Ok(lock) => lock,
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+        pending: Arc<Pending>,
+    ) -> io::Result<Self> {
+        let builder = thread::Builder::new().name(format!("tm-worker-{}", id));
+        let thread = builder.spawn(move || loop {
+            // Only hold the lock while waiting for a message, so that other workers
+            // can pick up jobs while this one is busy.
+            let message = match receiver.lock() {
+                Ok(lock) => lock.recv(),
                 Err(e) => {
-                    eprintln!("Failed to acquire lock: {:?}", e);
+                    error!("Failed to acquire lock: {:?}", e);
                     continue;
                 }
             };
 
-            let message = match lock.recv() {
+            let message = match message {
                 Ok(message) => message,
                 Err(e) => {
-                    eprintln!("Failed to receive: {:?}", e);
+                    error!("Failed to receive: {:?}", e);
                     continue;
                 }
             };
 
             match message {
                 Message::NewJob(job) => {
-                    job();
+                    // A panicking job must not take the worker down with it.
+                    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                        let current = thread::current();
+                        let name = current.name().unwrap_or("worker");
+                        error!("{}: job panicked: {}", name, panic_message(&*payload));
+                    }
+                    job_done(&pending);
                 }
                 Message::Terminate => {
                     break;
                 }
             }
-        });
+        })?;
 
-        Self {
+        Ok(Self {
             id,
             thread: Some(thread),
+        })
+    }
+}
+
+/// Counts a job out of `pending`, waking up `join` if it was the last one.
+fn job_done(pending: &Pending) {
+    let (lock, idle) = pending;
+    if let Ok(mut pending) = lock.lock() {
+        *pending -= 1;
+        if *pending == 0 {
+            idle.notify_all();
         }
     }
 }
 
+/// Extracts the message a panic was started with, if any.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
 enum Message {
     NewJob(Job),
     Terminate,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn worker_survives_panicking_job() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        // A single worker, so the second job can only run if it survived the first one.
+        let pool = ThreadPool::new(1).unwrap();
+
+        pool.execute(|| panic!("job failed"));
+        let job_counter = Arc::clone(&counter);
+        pool.execute(move || {
+            job_counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        // Dropping the pool waits for every job to be processed.
+        drop(pool);
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn join_waits_for_jobs() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(2).unwrap();
+
+        for _ in 0..4 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                thread::sleep(std::time::Duration::from_millis(50));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.join();
+        assert_eq!(counter.load(Ordering::SeqCst), 4);
+
+        // Still usable afterwards.
+        let job_counter = Arc::clone(&counter);
+        pool.execute(move || {
+            job_counter.fetch_add(1, Ordering::SeqCst);
+        });
+        pool.join();
+        assert_eq!(counter.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn execute_with_result() {
+        let pool = ThreadPool::new(1).unwrap();
+        assert_eq!(pool.execute_with_result(|| 2 + 2).recv(), Ok(4));
+
+        let panicked = pool.execute_with_result(|| -> u32 { panic!("no result") });
+        assert!(panicked.recv().is_err());
+    }
+
+    #[test]
+    fn names_workers() {
+        let pool = ThreadPool::new(1).unwrap();
+        let name = pool.execute_with_result(|| thread::current().name().map(String::from));
+        assert_eq!(name.recv(), Ok(Some("tm-worker-0".to_string())));
+    }
+}
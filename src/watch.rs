@@ -0,0 +1,139 @@
+use notify::{RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, RecvTimeoutError},
+    Arc,
+};
+use std::thread;
+use std::time::Duration;
+
+/// How long the config file must stay untouched after a change before it is reloaded, so
+/// that a file being written is only read once complete.
+const DEBOUNCE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Watches the config file at `path` on a background thread, raising `reload` once changes
+/// to it settle, until `shutdown` is set.
+///
+/// The directory holding the file is watched rather than the file itself, since editors
+/// often save by replacing the file.
+///
+/// # Errors
+///
+/// Errors if the watch could not be set up, or the thread failed to spawn.
+pub fn spawn(
+    path: &Path,
+    reload: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, String> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| format!("{} has no parent directory", path.display()))?;
+    let file = path.to_path_buf();
+    let (changes, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            Ok(event) if !event.kind.is_access() && event.paths.contains(&file) => {
+                // Only fails once the watcher thread is gone, when nobody cares anymore.
+                let _ = changes.send(());
+            }
+            Ok(_) => {}
+            Err(e) => error!("Error while watching the config file: {:?}", e),
+        }
+    })
+    .map_err(|e| format!("Could not watch {}: {:?}", path.display(), e))?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|e| format!("Could not watch {}: {:?}", dir.display(), e))?;
+
+    thread::Builder::new()
+        .name("tm-watcher".into())
+        .spawn(move || {
+            // Dropping the watcher stops it, so it lives as long as this thread.
+            let _watcher = watcher;
+            debounce(&receiver, DEBOUNCE_WINDOW, &shutdown, || {
+                reload.store(true, Ordering::SeqCst)
+            });
+        })
+        .map_err(|e| format!("Could not spawn the config watcher: {:?}", e))
+}
+
+/// Calls `on_change` once for each burst of `changes`, when none came for `window`. Returns
+/// once `shutdown` is set or `changes` hangs up.
+fn debounce(
+    changes: &Receiver<()>,
+    window: Duration,
+    shutdown: &AtomicBool,
+    mut on_change: impl FnMut(),
+) {
+    let mut pending = false;
+    while !shutdown.load(Ordering::SeqCst) {
+        match changes.recv_timeout(window) {
+            Ok(()) => pending = true,
+            Err(RecvTimeoutError::Timeout) if pending => {
+                pending = false;
+                on_change();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn debounces_changes() {
+        let window = Duration::from_millis(100);
+        let (changes, receiver) = mpsc::channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let watcher = {
+            let (shutdown, reloads) = (Arc::clone(&shutdown), Arc::clone(&reloads));
+            thread::spawn(move || {
+                debounce(&receiver, window, &shutdown, || {
+                    reloads.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+        };
+
+        // A file being written comes as several quick changes.
+        for _ in 0..3 {
+            changes.send(()).unwrap();
+            thread::sleep(window / 4);
+        }
+        assert_eq!(reloads.load(Ordering::SeqCst), 0, "reloaded too early");
+        thread::sleep(window * 3);
+        assert_eq!(reloads.load(Ordering::SeqCst), 1);
+
+        shutdown.store(true, Ordering::SeqCst);
+        watcher.join().unwrap();
+        assert_eq!(reloads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn watches_file() {
+        let dir = crate::temp_dir("watch");
+        let path = dir.join("taskmaster.yaml");
+        std::fs::write(&path, "programs: {}\n").unwrap();
+
+        let reload = Arc::new(AtomicBool::new(false));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let watcher = spawn(&path, Arc::clone(&reload), Arc::clone(&shutdown)).unwrap();
+        // Other files of the directory don't count.
+        std::fs::write(dir.join("other.yaml"), "").unwrap();
+        thread::sleep(DEBOUNCE_WINDOW * 3);
+        assert!(!reload.load(Ordering::SeqCst));
+
+        std::fs::write(&path, "programs:\n  web:\n    command: ls\n").unwrap();
+        thread::sleep(DEBOUNCE_WINDOW * 3);
+        assert!(reload.load(Ordering::SeqCst));
+
+        shutdown.store(true, Ordering::SeqCst);
+        watcher.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long to wait for the webhook endpoint before giving up on an attempt.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delay before the first retry. It doubles on each subsequent one.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Significant events the daemon can report to a webhook.
+pub enum Event {
+    /// The daemon started.
+    DaemonStart,
+    /// The daemon is shutting down.
+    DaemonStop,
+    /// A process exited too quickly too many times in a row, and was given up on.
+    ProcessFatal,
+    /// A program was restarted too many times in a short while.
+    RestartStorm,
+}
+
+impl Event {
+    /// Every event, the ones reported when the config doesn't restrict them.
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::DaemonStart,
+            Self::DaemonStop,
+            Self::ProcessFatal,
+            Self::RestartStorm,
+        ]
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+/// Payload POSTed to the webhook.
+struct Payload<'a> {
+    event: Event,
+    /// Name of the process the event is about, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    process: Option<&'a str>,
+    message: &'a str,
+    /// Seconds since the UNIX epoch.
+    timestamp: u64,
+}
+
+/// Endpoint notified of significant events.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    host: String,
+    port: u16,
+    path: String,
+    events: Vec<Event>,
+    /// How many more times to try delivering after a failed attempt.
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl Webhook {
+    /// Creates a webhook POSTing the given `events` to `url`.
+    ///
+    /// # Errors
+    ///
+    /// Errors if `url` is not a valid `http://` URL.
+    pub fn new(url: &str, events: Vec<Event>, retries: u32) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Webhook URL `{}` must start with http://", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("Invalid port in webhook URL `{}`", url))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in webhook URL `{}`", url));
+        }
+
+        Ok(Self {
+            host: host.into(),
+            port,
+            path: path.into(),
+            events,
+            retries,
+            retry_delay: RETRY_DELAY,
+        })
+    }
+
+    /// Reports `event` in the background, if the webhook is interested in it.
+    /// Delivery failures are logged, never fatal.
+    pub fn notify(&self, event: Event, process: Option<&str>, message: &str) {
+        if let Some(body) = self.body(event, process, message) {
+            let webhook = self.clone();
+            thread::spawn(move || webhook.deliver(&body));
+        }
+    }
+
+    /// Same as `notify`, but waits until delivery succeeded or every retry failed.
+    pub fn notify_blocking(&self, event: Event, process: Option<&str>, message: &str) {
+        if let Some(body) = self.body(event, process, message) {
+            self.deliver(&body);
+        }
+    }
+
+    fn body(&self, event: Event, process: Option<&str>, message: &str) -> Option<String> {
+        if !self.events.contains(&event) {
+            return None;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let payload = Payload {
+            event,
+            process,
+            message,
+            timestamp,
+        };
+        match serde_json::to_string(&payload) {
+            Ok(body) => Some(body),
+            Err(e) => {
+                error!("Could not serialize webhook payload: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// POSTs `body`, retrying up to `retries` times with an exponential backoff.
+    fn deliver(&self, body: &str) -> bool {
+        let mut delay = self.retry_delay;
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            match self.post(body) {
+                Ok(()) => return true,
+                Err(e) => warn!(
+                    "Webhook delivery to {}:{}{} failed (attempt {}/{}): {}",
+                    self.host,
+                    self.port,
+                    self.path,
+                    attempt + 1,
+                    self.retries + 1,
+                    e
+                ),
+            }
+        }
+        false
+    }
+
+    fn post(&self, body: &str) -> Result<(), String> {
+        let mut stream = self.connect()?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| e.to_string())?;
+
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .map_err(|e| e.to_string())?;
+        let status = response
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| "Malformed HTTP response".to_string())?;
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            Err(format!("Endpoint answered with HTTP status {}", status))
+        }
+    }
+
+    /// Connects to the first address of the endpoint that answers within `TIMEOUT`.
+    fn connect(&self) -> Result<TcpStream, String> {
+        let mut error = format!("No address found for {}", self.host);
+        for addr in (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+        {
+            match TcpStream::connect_timeout(&addr, TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = e.to_string(),
+            }
+        }
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn parse_url() {
+        let webhook = Webhook::new("http://localhost:8080/hooks/tm", Event::all(), 0).unwrap();
+        assert_eq!(
+            (webhook.host.as_str(), webhook.port, webhook.path.as_str()),
+            ("localhost", 8080, "/hooks/tm")
+        );
+
+        let webhook = Webhook::new("http://example.com", Event::all(), 0).unwrap();
+        assert_eq!(
+            (webhook.host.as_str(), webhook.port, webhook.path.as_str()),
+            ("example.com", 80, "/")
+        );
+
+        assert!(Webhook::new("https://example.com", Event::all(), 0).is_err());
+        assert!(Webhook::new("http://example.com:port", Event::all(), 0).is_err());
+    }
+
+    #[test]
+    fn retries_until_delivered() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in &["500 Internal Server Error", "200 OK"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buf = [0; 4096];
+                let bytes = stream.read(&mut buf).unwrap();
+                requests.push(String::from_utf8_lossy(&buf[..bytes]).into_owned());
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+            requests
+        });
+
+        let mut webhook = Webhook::new(&url, vec![Event::ProcessFatal], 2).unwrap();
+        webhook.retry_delay = Duration::from_millis(10);
+
+        let body = webhook
+            .body(Event::ProcessFatal, Some("web"), "spawn error")
+            .unwrap();
+        assert!(webhook.deliver(&body));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("POST /hook HTTP/1.1"));
+        assert!(requests[1].contains(r#""event":"process_fatal","process":"web""#));
+
+        // Not subscribed to this one.
+        assert!(webhook.body(Event::DaemonStart, None, "started").is_none());
+    }
+}
@@ -0,0 +1,176 @@
+//! Harness running a real daemon for the integration tests to talk to.
+
+use std::fs;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use taskmaster::{
+    client,
+    command::Command,
+    config::ServerUrl,
+    response::Response,
+    server::{self, Options},
+};
+
+/// How long to wait for the daemon to answer, or to come up.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A daemon running on a thread of the test, listening on an ephemeral port. It is shut
+/// down when dropped.
+pub struct Daemon {
+    url: ServerUrl,
+    dir: PathBuf,
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl Daemon {
+    /// Starts a daemon with `sections` of config, like a `programs` section, appended to the
+    /// settings of the harness. `name` keeps the files of concurrent daemons apart.
+    pub fn start(name: &str, sections: &str) -> Self {
+        let (dir, addr) = write_config(name, sections);
+        let options = Options {
+            nodaemon: true,
+            config: Some(dir.join("taskmaster.yaml")),
+            silent: true,
+        };
+        let thread = thread::spawn(move || server::run(&options));
+        let daemon = Self {
+            url: ServerUrl::Http(addr),
+            dir,
+            thread: Some(thread),
+        };
+        // The client retries while the daemon is coming up.
+        daemon.send(&Command::Version);
+        daemon
+    }
+
+    /// Sends `command` to the daemon, and leaves the connection open for what it streams
+    /// back.
+    pub fn open(&self, command: &Command) -> TcpStream {
+        let addr = match &self.url {
+            ServerUrl::Http(addr) => addr,
+            url => panic!("not a TCP address: {}", url),
+        };
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let command = serde_json::to_string(command).unwrap();
+        stream.write_all(command.as_bytes()).unwrap();
+        stream
+    }
+
+    /// Sends `command` to the daemon and returns its answer.
+    pub fn send(&self, command: &Command) -> Response {
+        client::send_command(&self.url, None, command, TIMEOUT).unwrap()
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = client::send_command(&self.url, None, &Command::Shutdown, TIMEOUT);
+        if let Some(thread) = self.thread.take() {
+            let result = thread.join();
+            // Don't panic again while a failed test is unwinding.
+            if !thread::panicking() {
+                result.unwrap().unwrap();
+            }
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The `taskmasterd` binary running in a process of its own, for what a daemon on a thread
+/// of the test can't do, like executing itself again. It is shut down when dropped.
+pub struct DaemonProcess {
+    url: ServerUrl,
+    dir: PathBuf,
+    child: Child,
+}
+
+impl DaemonProcess {
+    /// Starts the binary with `sections` of config, like `Daemon::start`. It runs from a copy,
+    /// at `binary`, that may be replaced.
+    pub fn start(name: &str, sections: &str) -> Self {
+        let (dir, addr) = write_config(name, sections);
+        let binary = dir.join("taskmasterd");
+        fs::copy(env!("CARGO_BIN_EXE_taskmasterd"), &binary).unwrap();
+        let child = std::process::Command::new(&binary)
+            .arg("--nodaemon")
+            .arg("--silent")
+            .arg("-c")
+            .arg(dir.join("taskmaster.yaml"))
+            .spawn()
+            .unwrap();
+        let daemon = Self {
+            url: ServerUrl::Http(addr),
+            dir,
+            child,
+        };
+        daemon.send(&Command::Version);
+        daemon
+    }
+
+    /// Directory holding the config file and the logs.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the binary the daemon runs is.
+    pub fn binary(&self) -> PathBuf {
+        self.dir.join("taskmasterd")
+    }
+
+    /// PID of the daemon.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Sends `command` to the daemon and returns its answer.
+    pub fn send(&self, command: &Command) -> Response {
+        client::send_command(&self.url, None, command, TIMEOUT).unwrap()
+    }
+}
+
+impl Drop for DaemonProcess {
+    fn drop(&mut self) {
+        if client::send_command(&self.url, None, &Command::Shutdown, TIMEOUT).is_err() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Writes the config of a daemon called `name`, with `sections` appended to the settings of
+/// the harness, in a directory of its own. Returns that directory, and the address the
+/// daemon will listen on.
+fn write_config(name: &str, sections: &str) -> (PathBuf, String) {
+    let dir = std::env::temp_dir().join(format!("taskmaster-it-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let addr = free_addr();
+    fs::write(
+        dir.join("taskmaster.yaml"),
+        format!(
+            "
+taskmasterd:
+  logfile: {dir}/taskmasterd.log
+  childlogdir: {dir}
+inet_http_server:
+  port: {addr}
+{sections}",
+            dir = dir.display(),
+            addr = addr,
+            sections = sections
+        ),
+    )
+    .unwrap();
+    (dir, addr)
+}
+
+/// A local address nothing listens on, for now.
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::{Daemon, DaemonProcess};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+use taskmaster::{
+    command::Command,
+    response::{ProcessState, Response},
+};
+
+#[test]
+fn status_round_trip() {
+    let daemon = Daemon::start(
+        "status",
+        "
+programs:
+  sleeper:
+    command: sleep 100
+    startsecs: 0
+  manual:
+    command: sleep 100
+    autostart: false
+",
+    );
+
+    match daemon.send(&Command::Status(vec![])) {
+        Response::Status(reports, counts) => {
+            let state = |name: &str| {
+                reports
+                    .iter()
+                    .flatten()
+                    .find(|report| report.name == name)
+                    .map(|report| report.state)
+            };
+            assert_eq!(state("sleeper"), Some(ProcessState::Running));
+            assert_eq!(state("manual"), Some(ProcessState::Stopped));
+            assert_eq!(
+                counts,
+                [(ProcessState::Running, 1), (ProcessState::Stopped, 1)]
+            );
+        }
+        response => panic!("unexpected response: {:?}", response),
+    }
+
+    match daemon.send(&Command::Status(vec!["sleeper".into(), "nope".into()])) {
+        Response::Status(reports, counts) => {
+            assert_eq!(reports.len(), 2);
+            assert_eq!(reports[0].as_ref().unwrap().name, "sleeper");
+            assert_eq!(reports[1], Err("nope: ERROR (no such process)".into()));
+            assert!(counts.is_empty());
+        }
+        response => panic!("unexpected response: {:?}", response),
+    }
+}
+
+#[test]
+fn fg_streams_output_and_forwards_input() {
+    let daemon = Daemon::start(
+        "fg",
+        "
+programs:
+  echo:
+    command: cat
+    startsecs: 0
+    stdin_fifo: '%(here)s/echo.fifo'
+    stdout_logfile: '%(here)s/echo.log'
+",
+    );
+
+    let mut stream = daemon.open(&Command::Fg("echo".into()));
+    stream.write_all(b"hello\n").unwrap();
+    let mut output = Vec::new();
+    while !output.ends_with(b"hello\n") {
+        let mut buf = [0; 64];
+        let bytes = stream.read(&mut buf).unwrap();
+        assert_ne!(bytes, 0, "daemon hung up after {:?}", output);
+        output.extend_from_slice(&buf[..bytes]);
+    }
+    drop(stream);
+
+    let mut stream = daemon.open(&Command::Fg("nope".into()));
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).unwrap();
+    assert_eq!(answer, "nope: ERROR (no such process)");
+}
+
+#[test]
+fn reexec_keeps_processes() {
+    let daemon = DaemonProcess::start(
+        "reexec",
+        "
+programs:
+  echo:
+    command: cat
+    startsecs: 0
+    stdin_fifo: '%(here)s/echo.fifo'
+    stdout_logfile: '%(here)s/echo.log'
+",
+    );
+    let pid = || match daemon.send(&Command::PID(vec!["echo".into()])) {
+        Response::Pid(pids) => pids["echo"].clone().unwrap(),
+        response => panic!("unexpected response: {:?}", response),
+    };
+    let echo = |line: &str| {
+        let mut fifo = OpenOptions::new()
+            .write(true)
+            .open(daemon.dir().join("echo.fifo"))
+            .unwrap();
+        fifo.write_all(line.as_bytes()).unwrap();
+        let log = daemon.dir().join("echo.log");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !fs::read_to_string(&log).unwrap_or_default().contains(line) {
+            assert!(Instant::now() < deadline, "{:?} was not logged", line);
+            thread::sleep(Duration::from_millis(50));
+        }
+    };
+    echo("before\n");
+    let before = pid();
+    // Upgraded under its feet, like a package manager does.
+    let binary = daemon.binary();
+    fs::remove_file(&binary).unwrap();
+    fs::copy(env!("CARGO_BIN_EXE_taskmasterd"), &binary).unwrap();
+
+    assert_eq!(
+        daemon.send(&Command::ReExec),
+        Response::Ok("Re-executing".into())
+    );
+    assert_eq!(pid(), before);
+    echo("after\n");
+    assert_eq!(
+        fs::read_to_string(daemon.dir().join("echo.log")).unwrap(),
+        "before\nafter\n"
+    );
+    let log = fs::read_to_string(daemon.dir().join("taskmasterd.log")).unwrap();
+    assert!(
+        log.contains(&format!("adopted: echo with pid {}", before)),
+        "{}",
+        log
+    );
+    // Same PID, new binary.
+    let started = format!("started with pid {}", daemon.id());
+    assert_eq!(log.matches(&started).count(), 2, "{}", log);
+}